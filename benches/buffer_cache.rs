@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, Throughput,
+};
+use rand::prelude::*;
+
+use raving_viz::cache::{BufferCache, EvictionPolicy};
+
+// the block allocation `BufferCache` used before the free set, a
+// linear scan for the first unused block, kept for comparison
+struct LinearScan {
+    block_map: HashMap<usize, usize>,
+    used_blocks: Vec<bool>,
+}
+
+impl LinearScan {
+    fn new(block_capacity: usize) -> Self {
+        Self {
+            block_map: HashMap::default(),
+            used_blocks: vec![false; block_capacity],
+        }
+    }
+
+    fn bind_block(&mut self, k: usize) -> bool {
+        if self.block_map.contains_key(&k) {
+            return false;
+        }
+
+        let (block_ix, _) = self
+            .used_blocks
+            .iter()
+            .enumerate()
+            .find(|(_, &v)| !v)
+            .expect("out of blocks");
+
+        self.used_blocks[block_ix] = true;
+        self.block_map.insert(k, block_ix);
+        true
+    }
+
+    fn unbind_block(&mut self, k: &usize) {
+        if let Some(block_ix) = self.block_map.remove(k) {
+            self.used_blocks[block_ix] = false;
+        }
+    }
+}
+
+const BLOCK_COUNTS: [usize; 3] = [1_000, 10_000, 100_000];
+
+// binding every block of an empty cache
+fn fill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill");
+    group.sample_size(10);
+
+    for n in BLOCK_COUNTS {
+        group.throughput(Throughput::Elements(n as u64));
+
+        group.bench_with_input(BenchmarkId::new("free_set", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut cache = BufferCache::new(4, 16, n);
+                for k in 0..n {
+                    cache.bind_block(k).unwrap();
+                }
+                cache
+            })
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("linear_scan", n),
+            &n,
+            |b, &n| {
+                b.iter(|| {
+                    let mut cache = LinearScan::new(n);
+                    for k in 0..n {
+                        cache.bind_block(k);
+                    }
+                    cache
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// replacing random keys of a full cache, as when streaming tiles
+fn churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("churn");
+
+    let ops = 1_000;
+
+    for n in BLOCK_COUNTS {
+        let mut rng = StdRng::seed_from_u64(n as u64);
+        let victims = (0..ops).map(|_| rng.gen_range(0..n)).collect::<Vec<_>>();
+
+        group.throughput(Throughput::Elements(ops as u64));
+
+        let mut cache = BufferCache::new(4, 16, n);
+        let mut keys = (0..n).collect::<Vec<_>>();
+        let mut next = n;
+        for &k in &keys {
+            cache.bind_block(k).unwrap();
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("free_set", n),
+            &victims,
+            |b, victims| {
+                b.iter(|| {
+                    for &i in victims {
+                        let old = std::mem::replace(&mut keys[i], next);
+                        cache.unbind_block(&old);
+                        cache.bind_block(next).unwrap();
+                        next += 1;
+                    }
+                })
+            },
+        );
+
+        let mut cache = LinearScan::new(n);
+        let mut keys = (0..n).collect::<Vec<_>>();
+        let mut next = n;
+        for &k in &keys {
+            cache.bind_block(k);
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("linear_scan", n),
+            &victims,
+            |b, victims| {
+                b.iter(|| {
+                    for &i in victims {
+                        let old = std::mem::replace(&mut keys[i], next);
+                        cache.unbind_block(&old);
+                        cache.bind_block(next);
+                        next += 1;
+                    }
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// scrolling a window of keys through the cache with `rebind_blocks`
+fn rebind(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rebind");
+    group.sample_size(20);
+
+    for n in BLOCK_COUNTS {
+        let step = (n / 100).max(1);
+        group.throughput(Throughput::Elements(step as u64));
+
+        let mut cache = BufferCache::new(4, 16, n);
+        cache.rebind_blocks(0..n).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("free_set", n), &n, |b, &n| {
+            let mut start = 0;
+            b.iter(|| {
+                start += step;
+                cache.rebind_blocks(start..start + n).unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// binding new keys to a full cache, each evicting a block, while a
+// random subset of the bound keys is looked up between binds
+fn evict(c: &mut Criterion) {
+    let mut group = c.benchmark_group("evict");
+
+    let ops = 1_000;
+
+    for policy in [EvictionPolicy::Lru, EvictionPolicy::Lfu] {
+        for n in BLOCK_COUNTS {
+            let mut rng = StdRng::seed_from_u64(n as u64);
+
+            group.throughput(Throughput::Elements(ops as u64));
+
+            let mut cache = BufferCache::new(4, 16, n).with_eviction(policy);
+            for k in 0..n {
+                cache.bind_block(k).unwrap();
+            }
+            let mut next = n;
+
+            let id = BenchmarkId::new(format!("{:?}", policy), n);
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    for _ in 0..ops {
+                        for _ in 0..4 {
+                            let k = rng.gen_range(next - n..next);
+                            cache.get_range(&k);
+                        }
+                        cache.bind_block(next).unwrap();
+                        next += 1;
+                    }
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, fill, churn, rebind, evict);
+criterion_main!(benches);
@@ -10,5 +10,13 @@ layout (push_constant) uniform Input {
 } inputs;
 
 void main() {
-  f_color = i_color;
+  vec3 light_dir = normalize(vec3(0.3, 1.0, 0.5));
+  float ambient = 0.3;
+
+  // two-sided, since open surfaces can be seen from either side
+  vec3 n = normalize(i_norm);
+  float diffuse = abs(dot(n, light_dir));
+
+  f_color = vec4(i_color.rgb * (ambient + (1.0 - ambient) * diffuse),
+                 i_color.a);
 }
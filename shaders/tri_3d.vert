@@ -8,7 +8,8 @@ layout (location = 0) out vec3 o_norm;
 layout (location = 1) out vec4 o_color;
 
 layout (set = 0, binding = 0) uniform Camera {
-  mat4 t;
+  mat4 view;
+  mat4 proj;
 } camera;
 
 layout (push_constant) uniform Input {
@@ -16,8 +17,7 @@ layout (push_constant) uniform Input {
 } inputs;
 
 void main() {
-  vec4 p = camera.t * vec4(pos, 1.0);
-  gl_Position = vec4(p.xy, 0.0, 1.0);
+  gl_Position = camera.proj * camera.view * vec4(pos, 1.0);
   o_norm = norm;
   o_color = color;
 }
@@ -1,6 +1,6 @@
 use std::{
     borrow::Borrow,
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
 };
 
 use ash::vk;
@@ -14,17 +14,33 @@ use rspirv_reflect::DescriptorInfo;
 
 use rustc_hash::FxHashMap;
 
+use roaring::RoaringBitmap;
+
 use anyhow::{anyhow, Result};
 
 use crossbeam::atomic::AtomicCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
+pub mod ranges;
+pub mod staging;
+pub mod workers;
+
+use staging::{StagingRing, UploadBatch};
+use workers::{
+    CancelToken, PendingRequests, Priority, RequestQueue, WorkerPool,
+};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CacheError {
     OutOfBlocks,
+    OutOfSpace { requested: usize, free: usize },
     ElemSizeMismatch,
     BlockSizeMismatch { actual: usize, expected: usize },
     BufferSizeMismatch { actual: usize, expected: usize },
+    StagingTooSmall { size: usize, capacity: usize },
+    QueueFull,
+    WorkersStopped,
 }
 
 impl std::fmt::Display for CacheError {
@@ -33,6 +49,9 @@ impl std::fmt::Display for CacheError {
             CacheError::OutOfBlocks => {
                 write!(f, "Buffer cache allocation error: Out of blocks, need reallocation")
             }
+            CacheError::OutOfSpace { requested, free } => {
+                write!(f, "Buffer cache allocation error: No free range of {} elements, with {} elements free in total", requested, free)
+            }
             CacheError::BlockSizeMismatch { actual, expected } => {
                 write!(f, "Buffer cache update error: Data consisted of {} elements, but block expected {}", actual, expected)
             }
@@ -42,12 +61,485 @@ impl std::fmt::Display for CacheError {
             CacheError::BufferSizeMismatch { actual, expected } => {
                 write!(f, "Buffer cache update error: Provided buffer is {} bytes, expected {}", actual, expected)
             }
+            CacheError::StagingTooSmall { size, capacity } => {
+                write!(f, "Buffer cache update error: Data of {} bytes doesn't fit in staging buffer of {} bytes", size, capacity)
+            }
+            CacheError::QueueFull => {
+                write!(f, "Buffer cache update error: Too many pending update requests")
+            }
+            CacheError::WorkersStopped => {
+                write!(f, "Buffer cache update error: Update request queue has been shut down")
+            }
         }
     }
 }
 
 impl std::error::Error for CacheError {}
 
+/// What `BufferCache::bind_block` does when every block is in use
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EvictionPolicy {
+    /// Fail with `CacheError::OutOfBlocks`
+    #[default]
+    None,
+    /// Unbind the block that was least recently bound or accessed
+    Lru,
+    /// Unbind the block that was accessed the fewest times since it
+    /// was bound, the least recently used among those
+    Lfu,
+}
+
+/// What `BufferCache::bind_block` and `rebind_blocks` do when every
+/// block is in use and none can be evicted
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GrowPolicy {
+    /// Fail with `CacheError::OutOfBlocks`
+    #[default]
+    Fixed,
+    /// Double the block count, as many times as needed, up to
+    /// `max_blocks`. Bound keys keep their blocks.
+    Double { max_blocks: usize },
+}
+
+impl GrowPolicy {
+    /// The block count to grow to from `current` so that there are
+    /// at least `required` blocks, if the policy allows it
+    fn grown_capacity(&self, current: usize, required: usize) -> Option<usize> {
+        match *self {
+            GrowPolicy::Fixed => None,
+            GrowPolicy::Double { max_blocks } => {
+                let mut capacity = current.max(1);
+                while capacity < required {
+                    capacity *= 2;
+                }
+                let capacity = capacity.min(max_blocks);
+                (capacity >= required && capacity > current).then_some(capacity)
+            }
+        }
+    }
+}
+
+/// A snapshot of the counters of a `BufferCache` or `GpuBufferCache`,
+/// along with its current size and occupancy.
+///
+/// The counters accumulate until `reset_stats` is called, so taking
+/// a snapshot each frame and using `since` gives per-frame numbers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups and binds of keys that were bound
+    pub hits: u64,
+    /// Lookups and binds of keys that weren't bound
+    pub misses: u64,
+    pub binds: u64,
+    /// Explicit unbinds, including by `rebind_blocks`, but not
+    /// evictions
+    pub unbinds: u64,
+    pub evictions: u64,
+    /// Binds that failed with `CacheError::OutOfBlocks`
+    pub out_of_blocks: u64,
+    /// Times the block capacity was increased by the grow policy
+    pub grows: u64,
+
+    /// Blocks written by `GpuBufferCache::apply_data_updates`
+    pub uploads: u64,
+    pub bytes_uploaded: u64,
+    /// Data messages of the wrong size for a block
+    pub dropped_wrong_width: u64,
+    /// Data messages for keys that were no longer bound
+    pub dropped_unbound: u64,
+    /// Data messages for keys that had been unbound and bound again
+    /// since the data was requested
+    pub dropped_stale: u64,
+
+    /// Update requests skipped because they were cancelled, e.g.
+    /// because their key was unbound before a worker got to them
+    pub requests_cancelled: u64,
+    /// Update requests refused or displaced because the queue was full
+    pub requests_rejected: u64,
+    /// Update requests whose data couldn't be created
+    pub requests_failed: u64,
+    pub pending_requests: usize,
+
+    pub used_blocks: usize,
+    pub pinned_blocks: usize,
+    pub block_capacity: usize,
+    pub block_size: usize,
+    pub elem_size: usize,
+}
+
+impl CacheStats {
+    /// The fraction of lookups that were hits, if there were any
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+
+    pub fn occupancy(&self) -> f64 {
+        if self.block_capacity == 0 {
+            return 0.0;
+        }
+        self.used_blocks as f64 / self.block_capacity as f64
+    }
+
+    /// The counters accumulated since the `earlier` snapshot; the
+    /// sizes are those of `self`
+    pub fn since(&self, earlier: &Self) -> Self {
+        let d = |now: u64, then: u64| now.saturating_sub(then);
+        Self {
+            hits: d(self.hits, earlier.hits),
+            misses: d(self.misses, earlier.misses),
+            binds: d(self.binds, earlier.binds),
+            unbinds: d(self.unbinds, earlier.unbinds),
+            evictions: d(self.evictions, earlier.evictions),
+            out_of_blocks: d(self.out_of_blocks, earlier.out_of_blocks),
+            grows: d(self.grows, earlier.grows),
+            uploads: d(self.uploads, earlier.uploads),
+            bytes_uploaded: d(self.bytes_uploaded, earlier.bytes_uploaded),
+            dropped_wrong_width: d(
+                self.dropped_wrong_width,
+                earlier.dropped_wrong_width,
+            ),
+            dropped_unbound: d(self.dropped_unbound, earlier.dropped_unbound),
+            dropped_stale: d(self.dropped_stale, earlier.dropped_stale),
+            requests_cancelled: d(
+                self.requests_cancelled,
+                earlier.requests_cancelled,
+            ),
+            requests_rejected: d(
+                self.requests_rejected,
+                earlier.requests_rejected,
+            ),
+            requests_failed: d(self.requests_failed, earlier.requests_failed),
+            ..*self
+        }
+    }
+
+    fn reset_counters(&mut self) {
+        *self = Self {
+            pending_requests: self.pending_requests,
+            used_blocks: self.used_blocks,
+            pinned_blocks: self.pinned_blocks,
+            block_capacity: self.block_capacity,
+            block_size: self.block_size,
+            elem_size: self.elem_size,
+            ..Self::default()
+        };
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let block_bytes = self.block_size * self.elem_size;
+        writeln!(
+            f,
+            "blocks: {}/{} used ({:.1}%), {} pinned, {} bytes each",
+            self.used_blocks,
+            self.block_capacity,
+            self.occupancy() * 100.0,
+            self.pinned_blocks,
+            block_bytes,
+        )?;
+
+        write!(f, "lookups: {} hits, {} misses", self.hits, self.misses)?;
+        if let Some(rate) = self.hit_rate() {
+            write!(f, " ({:.1}% hits)", rate * 100.0)?;
+        }
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "binds: {}, unbinds: {}, evictions: {}, grows: {}, out of blocks: {}",
+            self.binds,
+            self.unbinds,
+            self.evictions,
+            self.grows,
+            self.out_of_blocks
+        )?;
+
+        write!(
+            f,
+            "uploads: {} ({} bytes), dropped: {} wrong width, {} unbound, {} stale",
+            self.uploads,
+            self.bytes_uploaded,
+            self.dropped_wrong_width,
+            self.dropped_unbound,
+            self.dropped_stale,
+        )?;
+
+        if self.pending_requests > 0
+            || self.requests_cancelled
+                + self.requests_rejected
+                + self.requests_failed
+                > 0
+        {
+            write!(
+                f,
+                "\nrequests: {} pending, {} cancelled, {} rejected, {} failed",
+                self.pending_requests,
+                self.requests_cancelled,
+                self.requests_rejected,
+                self.requests_failed,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Access times and counts per block, for the eviction policies, and
+/// the hit and miss counts of the cache. Atomic so that lookups
+/// through `&BufferCache` can record them.
+#[derive(Debug, Default)]
+struct BlockUsage {
+    clock: AtomicU64,
+    last_used: Vec<AtomicU64>,
+    use_count: Vec<AtomicU64>,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Clone for BlockUsage {
+    fn clone(&self) -> Self {
+        let load = |v: &AtomicU64| AtomicU64::new(v.load(Ordering::Relaxed));
+        Self {
+            clock: load(&self.clock),
+            last_used: self.last_used.iter().map(load).collect(),
+            use_count: self.use_count.iter().map(load).collect(),
+
+            hits: load(&self.hits),
+            misses: load(&self.misses),
+        }
+    }
+}
+
+impl BlockUsage {
+    fn new(block_count: usize) -> Self {
+        let mut usage = Self::default();
+        usage.resize(block_count);
+        usage
+    }
+
+    fn resize(&mut self, block_count: usize) {
+        self.last_used.resize_with(block_count, Default::default);
+        self.use_count.resize_with(block_count, Default::default);
+    }
+
+    fn record_lookup(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn touch(&self, block_ix: usize) {
+        let now = self.tick();
+        self.last_used[block_ix].store(now, Ordering::Relaxed);
+        self.use_count[block_ix].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // a freshly bound block counts as used once
+    fn reset(&self, block_ix: usize) {
+        let now = self.tick();
+        self.last_used[block_ix].store(now, Ordering::Relaxed);
+        self.use_count[block_ix].store(1, Ordering::Relaxed);
+    }
+
+    // the block with the lowest rank is evicted first
+    fn rank(&self, policy: EvictionPolicy, block_ix: usize) -> (u64, u64) {
+        let last_used = self.last_used[block_ix].load(Ordering::Relaxed);
+        match policy {
+            EvictionPolicy::Lfu => {
+                let count = self.use_count[block_ix].load(Ordering::Relaxed);
+                (count, last_used)
+            }
+            _ => (last_used, 0),
+        }
+    }
+}
+
+/// The bound blocks, ordered by their rank for eviction.
+///
+/// Lookups through `&BufferCache` only update the atomic usage, so
+/// the queued ranks go stale. Ranks only increase, though, so when a
+/// stale entry reaches the front it's moved back to its current rank,
+/// which keeps eviction at amortized `O(log n)`.
+#[derive(Debug, Clone)]
+struct EvictionQueue {
+    order: BTreeSet<((u64, u64), u32)>,
+    // the rank each queued block was queued with
+    queued: Vec<Option<(u64, u64)>>,
+}
+
+impl EvictionQueue {
+    fn new(block_count: usize) -> Self {
+        Self {
+            order: BTreeSet::new(),
+            queued: vec![None; block_count],
+        }
+    }
+
+    fn resize(&mut self, block_count: usize) {
+        if block_count < self.queued.len() {
+            self.order.retain(|&(_, ix)| (ix as usize) < block_count);
+        }
+        self.queued.resize(block_count, None);
+    }
+
+    fn clear(&mut self) {
+        self.order.clear();
+        self.queued.fill(None);
+    }
+
+    fn insert(&mut self, block_ix: usize, rank: (u64, u64)) {
+        self.remove(block_ix);
+        self.order.insert((rank, block_ix as u32));
+        self.queued[block_ix] = Some(rank);
+    }
+
+    fn remove(&mut self, block_ix: usize) {
+        if let Some(rank) = self.queued[block_ix].take() {
+            self.order.remove(&(rank, block_ix as u32));
+        }
+    }
+
+    // removes and returns the unpinned block with the lowest rank;
+    // pinned blocks stay queued, but are skipped over each time
+    fn pop(
+        &mut self,
+        usage: &BlockUsage,
+        policy: EvictionPolicy,
+        pins: &BlockPins,
+    ) -> Option<usize> {
+        let mut pinned = Vec::new();
+
+        let victim = loop {
+            let (rank, ix) = match self.order.pop_first() {
+                Some(entry) => entry,
+                None => break None,
+            };
+            let block_ix = ix as usize;
+
+            let current = usage.rank(policy, block_ix);
+            if current != rank {
+                self.order.insert((current, ix));
+                self.queued[block_ix] = Some(current);
+            } else if pins.is_pinned(block_ix) {
+                pinned.push((rank, ix));
+            } else {
+                self.queued[block_ix] = None;
+                break Some(block_ix);
+            }
+        };
+
+        self.order.extend(pinned);
+        victim
+    }
+}
+
+/// Pin counts per block. A block with a nonzero count can't be
+/// unbound or evicted. The counts are shared with the `BlockLease`s
+/// of the block, which decrement them when dropped.
+#[derive(Debug, Default)]
+struct BlockPins {
+    counts: Vec<Arc<AtomicUsize>>,
+}
+
+impl Clone for BlockPins {
+    // a clone of the cache has its own pins, which aren't released
+    // by the leases of the original
+    fn clone(&self) -> Self {
+        let counts = self
+            .counts
+            .iter()
+            .map(|c| Arc::new(AtomicUsize::new(c.load(Ordering::Acquire))))
+            .collect();
+        Self { counts }
+    }
+}
+
+impl BlockPins {
+    fn new(block_count: usize) -> Self {
+        let mut pins = Self::default();
+        pins.resize(block_count);
+        pins
+    }
+
+    fn resize(&mut self, block_count: usize) {
+        self.counts.resize_with(block_count, Default::default);
+    }
+
+    fn count(&self, block_ix: usize) -> usize {
+        self.counts[block_ix].load(Ordering::Acquire)
+    }
+
+    fn is_pinned(&self, block_ix: usize) -> bool {
+        self.count(block_ix) > 0
+    }
+
+    fn pin(&self, block_ix: usize) -> usize {
+        self.counts[block_ix].fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    // returns `None` if the block wasn't pinned
+    fn unpin(&self, block_ix: usize) -> Option<usize> {
+        self.counts[block_ix]
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| {
+                c.checked_sub(1)
+            })
+            .ok()
+            .map(|c| c - 1)
+    }
+
+    // leases that outlived a `clear()` may still hold the old count,
+    // so it's detached rather than reset
+    fn reset(&mut self, block_ix: usize) {
+        let count = &mut self.counts[block_ix];
+        if Arc::get_mut(count).is_some() {
+            count.store(0, Ordering::Release);
+        } else {
+            *count = Arc::default();
+        }
+    }
+}
+
+/// Keeps a block of a `BufferCache` pinned, so that it stays bound,
+/// until the lease and all its clones are dropped
+#[derive(Debug)]
+pub struct BlockLease {
+    count: Arc<AtomicUsize>,
+    range: std::ops::Range<usize>,
+}
+
+impl BlockLease {
+    /// The byte range of the leased block in the buffer
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.range.clone()
+    }
+}
+
+impl Clone for BlockLease {
+    fn clone(&self) -> Self {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        Self {
+            count: self.count.clone(),
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl Drop for BlockLease {
+    fn drop(&mut self) {
+        let _ =
+            self.count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| {
+                    c.checked_sub(1)
+                });
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BufferCache<K>
 where
@@ -63,8 +555,25 @@ where
 
     block_capacity: usize,
     used_block_count: usize,
-    used_blocks: Vec<bool>,
-    // used_rows: roaring::RoaringBitmap,
+    // the lowest free block is used first, so that the bound blocks
+    // stay packed at the start of the buffer
+    free_blocks: RoaringBitmap,
+    eviction: EvictionPolicy,
+    grow: GrowPolicy,
+    usage: BlockUsage,
+    // only kept up to date while eviction is enabled
+    eviction_queue: EvictionQueue,
+    // the key bound to each block, to find the keys to evict
+    block_keys: Vec<Option<K>>,
+    evicted: Vec<K>,
+    pins: BlockPins,
+    // only the counters that need `&mut self` to update are used
+    stats: CacheStats,
+
+    // the generation of each block's current binding, unique across
+    // all bindings of the cache
+    generations: Vec<u64>,
+    next_generation: u64,
 }
 
 impl<K: std::hash::Hash + Eq> BufferCache<K> {
@@ -82,10 +591,86 @@ impl<K: std::hash::Hash + Eq> BufferCache<K> {
 
             block_capacity,
             used_block_count: 0,
-            used_blocks: vec![false; block_capacity],
+            free_blocks: RoaringBitmap::from_iter(0..block_capacity as u32),
+
+            eviction: EvictionPolicy::None,
+            grow: GrowPolicy::Fixed,
+            usage: BlockUsage::new(block_capacity),
+            eviction_queue: EvictionQueue::new(block_capacity),
+            block_keys: (0..block_capacity).map(|_| None).collect(),
+            evicted: Vec::new(),
+            pins: BlockPins::new(block_capacity),
+            stats: CacheStats::default(),
+
+            generations: vec![0; block_capacity],
+            next_generation: 1,
         }
     }
 
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.usage.hits.load(Ordering::Relaxed),
+            misses: self.usage.misses.load(Ordering::Relaxed),
+
+            used_blocks: self.used_block_count,
+            pinned_blocks: self.pinned_blocks(),
+            block_capacity: self.block_capacity,
+            block_size: self.block_size,
+            elem_size: self.elem_size,
+
+            ..self.stats
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.reset_counters();
+        self.usage.hits.store(0, Ordering::Relaxed);
+        self.usage.misses.store(0, Ordering::Relaxed);
+    }
+
+    pub fn with_eviction(mut self, policy: EvictionPolicy) -> Self {
+        self.set_eviction_policy(policy);
+        self
+    }
+
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.eviction
+    }
+
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.eviction = policy;
+
+        self.eviction_queue.clear();
+        self.eviction_queue.resize(self.block_capacity);
+        if policy != EvictionPolicy::None {
+            for &block_ix in self.block_map.values() {
+                let rank = self.usage.rank(policy, block_ix);
+                self.eviction_queue.insert(block_ix, rank);
+            }
+        }
+    }
+
+    pub fn with_grow_policy(mut self, policy: GrowPolicy) -> Self {
+        self.grow = policy;
+        self
+    }
+
+    pub fn grow_policy(&self) -> GrowPolicy {
+        self.grow
+    }
+
+    pub fn set_grow_policy(&mut self, policy: GrowPolicy) {
+        self.grow = policy;
+    }
+
+    /// Returns the keys that have been evicted to make room for other
+    /// keys since the last call, oldest first. The data of these keys
+    /// is no longer in the buffer, and their blocks may already hold
+    /// data for other keys.
+    pub fn take_evicted(&mut self) -> Vec<K> {
+        std::mem::take(&mut self.evicted)
+    }
+
     pub fn block_capacity(&self) -> usize {
         self.block_capacity
     }
@@ -111,21 +696,122 @@ impl<K: std::hash::Hash + Eq> BufferCache<K> {
         self.block_capacity * self.block_size * self.elem_size
     }
 
+    /// Unbinds every key, including pinned ones; outstanding leases
+    /// no longer pin anything
     pub fn clear(&mut self) {
-        self.block_map.clear();
-        self.used_blocks.iter_mut().for_each(|v| *v = false);
+        for (_, block_ix) in self.block_map.drain() {
+            self.free_blocks.insert(block_ix as u32);
+            self.block_keys[block_ix] = None;
+        }
+        self.eviction_queue.clear();
         self.used_block_count = 0;
+        self.evicted.clear();
     }
 
+    /// Unbinds every key, and sets the block count and size
     pub fn reallocate(&mut self, new_block_count: usize, new_width: usize) {
-        self.clear();
-        self.used_blocks.resize(new_block_count, false);
+        self.reallocate_blocks(new_block_count);
         self.block_size = new_width;
     }
 
+    /// Unbinds every key, and sets the block count
     pub fn reallocate_blocks(&mut self, block_count: usize) {
         self.clear();
-        self.used_blocks.resize(block_count, false);
+        self.free_blocks = RoaringBitmap::from_iter(0..block_count as u32);
+        self.resize_block_state(block_count);
+    }
+
+    /// Increases the block count to `block_count`, keeping every key
+    /// bound to the same block, so the existing contents of the buffer
+    /// stay valid at the same offsets. Does nothing if the cache
+    /// already has as many blocks.
+    pub fn grow_blocks(&mut self, block_count: usize) {
+        if block_count <= self.block_capacity {
+            return;
+        }
+
+        self.free_blocks
+            .insert_range(self.block_capacity as u32..block_count as u32);
+        self.resize_block_state(block_count);
+    }
+
+    fn resize_block_state(&mut self, block_count: usize) {
+        self.block_capacity = block_count;
+        self.usage.resize(block_count);
+        self.eviction_queue.resize(block_count);
+        self.block_keys.resize_with(block_count, || None);
+        self.pins.resize(block_count);
+        self.generations.resize(block_count, 0);
+    }
+
+    /// The block count that `bind_block(k)` would grow the cache to,
+    /// or `None` if it wouldn't grow. The buffer backing the cache can
+    /// then be grown before binding, so that if that fails, the cache
+    /// is left as it was.
+    pub fn capacity_to_bind<Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        if self.block_map.contains_key(k) || !self.is_full() || self.can_evict()
+        {
+            return None;
+        }
+        self.grow
+            .grown_capacity(self.block_capacity, self.used_block_count + 1)
+    }
+
+    /// Like `capacity_to_bind`, for `rebind_blocks(new_keys)`
+    pub fn capacity_to_rebind<'a>(
+        &self,
+        new_keys: impl IntoIterator<Item = &'a K>,
+    ) -> Option<usize>
+    where
+        K: 'a,
+    {
+        let new_keys = new_keys.into_iter().collect::<HashSet<_>>();
+        let required =
+            self.rebind_required(new_keys.len(), |k| new_keys.contains(k));
+
+        if required <= self.block_capacity {
+            return None;
+        }
+        self.grow.grown_capacity(self.block_capacity, required)
+    }
+
+    // with eviction enabled, binding more keys than there are blocks
+    // would evict some of the new keys, so the pinned keys that stay
+    // bound are counted as well
+    fn rebind_required(
+        &self,
+        new_key_count: usize,
+        is_new: impl Fn(&K) -> bool,
+    ) -> usize {
+        let kept_pinned = self
+            .block_map
+            .iter()
+            .filter(|&(k, &ix)| !is_new(k) && self.pins.is_pinned(ix))
+            .count();
+        new_key_count + kept_pinned
+    }
+
+    // whether `evict_block` would find a block to evict
+    fn can_evict(&self) -> bool {
+        self.eviction != EvictionPolicy::None
+            && self.block_map.values().any(|&ix| !self.pins.is_pinned(ix))
+    }
+
+    // grows according to the grow policy, if that leaves at least
+    // `required` blocks
+    fn try_grow(&mut self, required: usize) -> bool {
+        match self.grow.grown_capacity(self.block_capacity, required) {
+            Some(block_count) => {
+                self.grow_blocks(block_count);
+                self.stats.grows += 1;
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn resize_blocks(&mut self, new_width: usize) {
@@ -150,14 +836,44 @@ impl<K: std::hash::Hash + Eq> BufferCache<K> {
         start..end
     }
 
+    /// Returns the byte range of the key's block, and counts as a use
+    /// of the block for eviction and as a hit or miss
     pub fn get_range<Q: ?Sized>(&self, k: &Q) -> Option<std::ops::Range<usize>>
     where
         K: Borrow<Q>,
         Q: std::hash::Hash + Eq,
     {
-        self.block_map.get(k).map(|i| self.range_for_ix(*i))
+        let block_ix = self.block_map.get(k).copied();
+        self.usage.record_lookup(block_ix.is_some());
+        let block_ix = block_ix?;
+        self.usage.touch(block_ix);
+        Some(self.range_for_ix(block_ix))
     }
 
+    /// The generation of the key's current binding. Each time a key is
+    /// bound, including when it's bound again after being unbound, it
+    /// gets a new generation, so data computed for an earlier binding
+    /// can be told apart.
+    pub fn generation<Q: ?Sized>(&self, k: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        self.block_map.get(k).map(|&ix| self.generations[ix])
+    }
+
+    // like `get_range`, for writing data, which doesn't count as a use
+    fn block_range<Q: ?Sized>(&self, k: &Q) -> Option<std::ops::Range<usize>>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        self.block_map.get(k).map(|&ix| self.range_for_ix(ix))
+    }
+
+    /// Binds the keys in `new_keys`, and unbinds all other keys
+    /// except for pinned ones. Returns the keys that were freshly
+    /// bound.
     pub fn rebind_blocks(
         &mut self,
         new_keys: impl IntoIterator<Item = K>,
@@ -166,9 +882,20 @@ impl<K: std::hash::Hash + Eq> BufferCache<K> {
         K: Clone + std::fmt::Debug,
     {
         let new_keys = new_keys.into_iter().collect::<HashSet<_>>();
-        let old_keys = self.block_map.keys().cloned().collect::<HashSet<_>>();
 
-        let to_remove = old_keys.difference(&new_keys);
+        let to_remove = self
+            .block_map
+            .keys()
+            .filter(|k| !new_keys.contains(*k))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let required =
+            self.rebind_required(new_keys.len(), |k| new_keys.contains(k));
+        if required > self.block_capacity && !self.try_grow(required) {
+            self.stats.out_of_blocks += 1;
+            return Err(CacheError::OutOfBlocks);
+        }
 
         for key in to_remove {
             self.unbind_block(&key);
@@ -187,44 +914,181 @@ impl<K: std::hash::Hash + Eq> BufferCache<K> {
     /// returns `Ok(false)` if the key was already bound, `Ok(true)`
     /// if the key was freshly bound (and thus the backing buffer
     /// needs to be updated)
-    pub fn bind_block(
-        &mut self,
-        k: K,
-    ) -> std::result::Result<bool, CacheError> {
-        if let Some(range) = self.get_range(&k) {
+    ///
+    /// if the cache is full, a block is evicted according to the
+    /// eviction policy, and its key can be retrieved with
+    /// `take_evicted`; if no block can be evicted, the cache grows
+    /// according to the grow policy
+    pub fn bind_block(&mut self, k: K) -> std::result::Result<bool, CacheError>
+    where
+        K: Clone,
+    {
+        if let Some(&block_ix) = self.block_map.get(&k) {
+            self.usage.record_lookup(true);
+            self.usage.touch(block_ix);
             return Ok(false);
         }
 
-        let (block_ix, _) = self
-            .used_blocks
-            .iter()
-            .enumerate()
-            .find(|(_, &v)| !v)
-            .ok_or(CacheError::OutOfBlocks)?;
+        self.usage.record_lookup(false);
 
-        self.used_blocks[block_ix] = true;
+        if self.is_full() {
+            if let Err(err) = self.evict_block() {
+                if !self.try_grow(self.used_block_count + 1) {
+                    self.stats.out_of_blocks += 1;
+                    return Err(err);
+                }
+            }
+        }
+
+        let block_ix = match self.free_blocks.min() {
+            Some(ix) => ix as usize,
+            None => {
+                self.stats.out_of_blocks += 1;
+                return Err(CacheError::OutOfBlocks);
+            }
+        };
 
+        self.free_blocks.remove(block_ix as u32);
+        self.usage.reset(block_ix);
+        self.pins.reset(block_ix);
+        self.generations[block_ix] = self.next_generation;
+        self.next_generation += 1;
+
+        if self.eviction != EvictionPolicy::None {
+            let rank = self.usage.rank(self.eviction, block_ix);
+            self.eviction_queue.insert(block_ix, rank);
+        }
+
+        self.block_keys[block_ix] = Some(k.clone());
         self.block_map.insert(k, block_ix);
         self.used_block_count += 1;
+        self.stats.binds += 1;
 
         Ok(true)
     }
 
+    fn evict_block(&mut self) -> std::result::Result<(), CacheError>
+    where
+        K: Clone,
+    {
+        if self.eviction == EvictionPolicy::None {
+            return Err(CacheError::OutOfBlocks);
+        }
+
+        let block_ix = self
+            .eviction_queue
+            .pop(&self.usage, self.eviction, &self.pins)
+            .ok_or(CacheError::OutOfBlocks)?;
+        let key = self.block_keys[block_ix]
+            .clone()
+            .expect("Buffer cache: Queued block has no key");
+
+        self.release_block(&key);
+        self.evicted.push(key);
+        self.stats.evictions += 1;
+
+        Ok(())
+    }
+
+    /// returns `None` if the key isn't bound, or if it's pinned, in
+    /// which case it stays bound
     pub fn unbind_block<Q: ?Sized>(&mut self, k: &Q) -> Option<()>
     where
         K: Borrow<Q>,
         Q: std::hash::Hash + Eq,
     {
-        let block_ix = self.block_map.remove(k)?;
+        self.release_block(k)?;
+        self.stats.unbinds += 1;
+        Some(())
+    }
+
+    fn release_block<Q: ?Sized>(&mut self, k: &Q) -> Option<()>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        let block_ix = *self.block_map.get(k)?;
+        if self.pins.is_pinned(block_ix) {
+            return None;
+        }
+        self.block_map.remove(k);
+        self.block_keys[block_ix] = None;
+        self.eviction_queue.remove(block_ix);
+        let was_used = self.free_blocks.insert(block_ix as u32);
         debug_assert!(
-            self.used_blocks[block_ix],
+            was_used,
             "Buffer cache: Block map entry existed but block was not in use"
         );
-        self.used_blocks[block_ix] = false;
         self.used_block_count -= 1;
         Some(())
     }
 
+    /// Increments the pin count of a bound key, returning the new
+    /// count, or `None` if the key isn't bound
+    pub fn pin<Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        let block_ix = *self.block_map.get(k)?;
+        Some(self.pins.pin(block_ix))
+    }
+
+    /// Decrements the pin count of a bound key, returning the new
+    /// count, or `None` if the key isn't bound or wasn't pinned
+    pub fn unpin<Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        let block_ix = *self.block_map.get(k)?;
+        self.pins.unpin(block_ix)
+    }
+
+    /// Pins a bound key until the returned lease, and all its
+    /// clones, are dropped
+    pub fn lease<Q: ?Sized>(&self, k: &Q) -> Option<BlockLease>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        let block_ix = *self.block_map.get(k)?;
+        self.pins.pin(block_ix);
+        self.usage.touch(block_ix);
+
+        Some(BlockLease {
+            count: self.pins.counts[block_ix].clone(),
+            range: self.range_for_ix(block_ix),
+        })
+    }
+
+    pub fn pin_count<Q: ?Sized>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        self.block_map
+            .get(k)
+            .map(|&ix| self.pins.count(ix))
+            .unwrap_or(0)
+    }
+
+    pub fn is_pinned<Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        self.pin_count(k) > 0
+    }
+
+    /// The number of bound blocks that are pinned
+    pub fn pinned_blocks(&self) -> usize {
+        self.block_map
+            .values()
+            .filter(|&&ix| self.pins.is_pinned(ix))
+            .count()
+    }
+
     // pub fn reallocate
 
     // fn pick_row<Q: ?Sized>(
@@ -267,7 +1131,7 @@ impl<K: std::hash::Hash + Eq> BufferCache<K> {
         }
 
         let range = self
-            .get_range(block)
+            .block_range(block)
             .ok_or(anyhow!("Buffer cache error: Unbound key {:?}", block))?;
 
         buffer[range].clone_from_slice(data);
@@ -284,6 +1148,9 @@ where
     // T: Eq + Send + Sync + 'static,
 {
     key: K,
+    generation: u64,
+    priority: Priority,
+    cancel: CancelToken,
     // payload: T,
     create_payload: Box<
         dyn FnOnce(K) -> anyhow::Result<DataMsg<K>> + Send + Sync + 'static,
@@ -294,15 +1161,19 @@ impl<K> UpdateReqMsg<K>
 where
     K: std::hash::Hash + Eq + Send + Sync + 'static,
 {
-    pub fn new<F, G>(key: K, f: F, signal: G) -> Self
+    /// `generation` is that of the key's binding when the request is
+    /// made, see `BufferCache::generation`; the data is dropped if the
+    /// key has been bound again by the time it arrives
+    pub fn new<F, G>(key: K, generation: u64, f: F, signal: G) -> Self
     where
         F: FnOnce(&K) -> anyhow::Result<Vec<u8>> + Send + Sync + 'static,
         G: FnOnce() + Send + Sync + 'static,
     {
-        let create_payload = Box::new(|key| {
+        let create_payload = Box::new(move |key| {
             let data = f(&key)?;
             Ok(DataMsg {
                 key,
+                generation,
                 data,
                 and_then: Some(Box::new(signal)),
             })
@@ -310,9 +1181,35 @@ where
 
         Self {
             key,
+            generation,
+            priority: Priority::default(),
+            cancel: CancelToken::default(),
             create_payload,
         }
     }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// A handle that cancels the request if it hasn't been picked up
+    /// by a worker yet
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
 }
 
 pub struct DataMsg<K>
@@ -320,6 +1217,7 @@ where
     K: std::hash::Hash + Eq + Send + Sync + 'static,
 {
     key: K,
+    generation: u64,
     data: Vec<u8>,
     and_then: Option<Box<dyn FnOnce() + Send + Sync + 'static>>,
 }
@@ -330,7 +1228,21 @@ where
 //     UpToDate,
 // }
 
-/// TODO: only GpuToCpu memory locations currently
+// the staging buffer and pending writes of a device-local cache
+struct StagedUploads {
+    buffer: BufferIx,
+    ring: StagingRing,
+    batch: UploadBatch<Box<dyn FnOnce() + Send + Sync + 'static>>,
+}
+
+/// A `BufferCache` backed by a GPU buffer, filled with the data that
+/// workers produce for its keys.
+///
+/// By default the buffer is host-visible (`CpuToGpu`), and data is
+/// written to it directly in `apply_data_updates`. Caches created
+/// with `new_device_local` live in `GpuOnly` memory instead; the data
+/// is batched there, and copied from a staging buffer by
+/// `record_uploads`.
 pub struct GpuBufferCache<K>
 where
     K: std::hash::Hash + Eq + Send + Sync + 'static,
@@ -343,13 +1255,26 @@ where
 
     cache: BufferCache<K>,
 
+    staging: Option<StagedUploads>,
+    // buffers replaced by `grow_buffer` that may still be in use
+    retired: Vec<BufferRes>,
+    // the larger buffer of a device-local cache that has grown, and
+    // the number of bytes to copy to it from the current buffer; the
+    // copy and the swap are recorded by the next `record_uploads`
+    pending_grow: Option<(BufferRes, usize)>,
+
     // block_state_map: FxHashMap<u64, Arc<AtomicCell<BlockState>>>,
     // block_state_map: FxHashMap<K, Arc<AtomicCell<BlockState>>>,
-    pub update_request_tx: crossbeam::channel::Sender<UpdateReqMsg<K>>,
-    update_request_rx: crossbeam::channel::Receiver<UpdateReqMsg<K>>,
+    requests: Arc<RequestQueue<K>>,
+    workers: Option<WorkerPool<K>>,
+    pending: PendingRequests<K>,
+    max_pending: usize,
 
     pub data_msg_tx: crossbeam::channel::Sender<DataMsg<K>>,
     data_msg_rx: crossbeam::channel::Receiver<DataMsg<K>>,
+
+    // the upload counters; the rest are kept by the `BufferCache`
+    upload_stats: CacheStats,
 }
 
 impl<K> GpuBufferCache<K>
@@ -357,23 +1282,75 @@ where
     K: std::hash::Hash + Eq + Send + Sync + 'static,
 {
     // returns a closure that can be used in a loop by a worker thread
-    // to consume the update requests
+    // to consume the update requests, as an alternative to
+    // `spawn_workers`
     //
-    // the closure blocks until an update request is received
+    // the closure blocks until an update request is received, and
+    // returns an error once the request queue has been shut down
     pub fn data_msg_worker(
         &self,
     ) -> Box<dyn Fn() -> anyhow::Result<()> + Send + Sync + 'static> {
-        let in_rx = self.update_request_rx.clone();
+        let requests = self.requests.clone();
         let out_tx = self.data_msg_tx.clone();
 
-        Box::new(move || {
-            let msg = in_rx.recv()?;
-            let data = (msg.create_payload)(msg.key)?;
-            out_tx.send(data)?;
-            Ok(())
-        })
+        Box::new(move || {
+            let msg = requests
+                .pop()
+                .ok_or(anyhow!("GPU cache request queue was shut down"))?;
+            match (msg.create_payload)(msg.key) {
+                Ok(data) => out_tx.send(data)?,
+                Err(err) => {
+                    msg.cancel.cancel();
+                    requests.record_failure();
+                    return Err(err);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Starts `thread_count` threads that process update requests,
+    /// replacing any that were already running
+    pub fn spawn_workers(&mut self, thread_count: usize) -> Result<()> {
+        self.shutdown_workers();
+
+        // a queue can't be reopened once its workers are stopped
+        if self.requests.is_closed() {
+            self.requests = Arc::new(RequestQueue::new(self.max_pending));
+        }
+
+        let pool = WorkerPool::spawn(
+            &self.name,
+            thread_count,
+            self.requests.clone(),
+            self.data_msg_tx.clone(),
+        )?;
+        self.workers = Some(pool);
+
+        Ok(())
+    }
+
+    /// Cancels the pending update requests and waits for the worker
+    /// threads to finish the requests they're processing. Requests
+    /// are refused until `spawn_workers` is called again.
+    pub fn shutdown_workers(&mut self) {
+        if let Some(pool) = self.workers.take() {
+            pool.shutdown();
+        }
+        self.requests.close();
+        self.pending.clear();
+    }
+
+    /// The number of update requests that can be pending before new
+    /// ones are refused, or displace pending requests of lower
+    /// priority
+    pub fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending;
+        self.requests.set_capacity(max_pending);
     }
 
+    pub const DEFAULT_MAX_PENDING: usize = 4096;
+
     pub fn new(
         engine: &mut VkEngine,
         usage: vk::BufferUsageFlags,
@@ -381,14 +1358,75 @@ where
         elem_size: usize,
         block_size: usize,
         block_capacity: usize,
+    ) -> Result<Self> {
+        Self::new_impl(
+            engine,
+            usage,
+            name,
+            elem_size,
+            block_size,
+            block_capacity,
+            None,
+        )
+    }
+
+    /// Creates a cache whose buffer is in device-local memory, with a
+    /// staging buffer of `staging_size` bytes; see `record_uploads`.
+    /// The buffer can also be used as a transfer source and
+    /// destination, for the uploads and for growing.
+    ///
+    /// The staging buffer must hold at least one block, and should
+    /// hold all the blocks that may be updated over the frames in
+    /// flight to avoid delaying uploads.
+    pub fn new_device_local(
+        engine: &mut VkEngine,
+        usage: vk::BufferUsageFlags,
+        name: &str,
+        elem_size: usize,
+        block_size: usize,
+        block_capacity: usize,
+        staging_size: usize,
+    ) -> Result<Self> {
+        Self::new_impl(
+            engine,
+            usage
+                | vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::TRANSFER_SRC,
+            name,
+            elem_size,
+            block_size,
+            block_capacity,
+            Some(staging_size),
+        )
+    }
+
+    fn new_impl(
+        engine: &mut VkEngine,
+        usage: vk::BufferUsageFlags,
+        name: &str,
+        elem_size: usize,
+        block_size: usize,
+        block_capacity: usize,
+        staging_size: Option<usize>,
     ) -> Result<Self> {
         let cache = BufferCache::new(elem_size, block_size, block_capacity);
 
         let capacity = cache.buffer_size();
 
-        let (buffer, desc_set) =
+        if let Some(staging_size) = staging_size {
+            let block_bytes = elem_size * block_size;
+            if staging_size < block_bytes {
+                anyhow::bail!(
+                    "GPU cache error: staging buffer of {} bytes can't hold a block of {} bytes",
+                    staging_size,
+                    block_bytes
+                );
+            }
+        }
+
+        let (buffer, desc_set, staging) =
             engine.with_allocators(|ctx, res, alloc| {
-                let mem_loc = gpu_allocator::MemoryLocation::CpuToGpu;
+                let mem_loc = Self::memory_location(staging_size.is_some());
 
                 let buffer = res.allocate_buffer(
                     ctx,
@@ -406,12 +1444,30 @@ where
 
                 let set_ix = res.insert_desc_set(desc_set);
 
-                Ok((buf_ix, set_ix))
+                let staging = if let Some(staging_size) = staging_size {
+                    let staging_name = format!("{} staging", name);
+                    let staging = res.allocate_buffer(
+                        ctx,
+                        alloc,
+                        gpu_allocator::MemoryLocation::CpuToGpu,
+                        1,
+                        staging_size,
+                        vk::BufferUsageFlags::TRANSFER_SRC,
+                        Some(&staging_name),
+                    )?;
+
+                    Some(StagedUploads {
+                        buffer: res.insert_buffer(staging),
+                        ring: StagingRing::new(staging_size),
+                        batch: UploadBatch::default(),
+                    })
+                } else {
+                    None
+                };
+
+                Ok((buf_ix, set_ix, staging))
             })?;
 
-        let (update_request_tx, update_request_rx) =
-            crossbeam::channel::unbounded();
-
         let (data_msg_tx, data_msg_rx) = crossbeam::channel::unbounded();
 
         Ok(Self {
@@ -423,11 +1479,19 @@ where
 
             cache,
 
+            staging,
+            retired: Vec::new(),
+            pending_grow: None,
+
             data_msg_tx,
             data_msg_rx,
 
-            update_request_tx,
-            update_request_rx,
+            requests: Arc::new(RequestQueue::new(Self::DEFAULT_MAX_PENDING)),
+            workers: None,
+            pending: PendingRequests::default(),
+            max_pending: Self::DEFAULT_MAX_PENDING,
+
+            upload_stats: CacheStats::default(),
         })
     }
 
@@ -438,28 +1502,57 @@ where
     where
         K: std::fmt::Debug,
     {
-        let buffer = &mut res[self.buffer];
+        while let Ok(msg) = self.data_msg_rx.try_recv() {
+            // the key may have been unbound or evicted, and possibly
+            // bound again, while its data was being computed
+            match self.cache.generation(&msg.key) {
+                None => {
+                    log::debug!("received data for unbound key, ignoring");
+                    self.upload_stats.dropped_unbound += 1;
+                    continue;
+                }
+                Some(current) if current != msg.generation => {
+                    log::debug!("received stale data, ignoring");
+                    self.upload_stats.dropped_stale += 1;
+                    continue;
+                }
+                Some(_) => (),
+            }
 
-        let slice = buffer
-            .mapped_slice_mut()
-            .expect("GPU cache buffer must be host-accessible");
+            self.pending.complete(&msg.key, msg.generation);
 
-        while let Ok(msg) = self.data_msg_rx.try_recv() {
             let range = self
                 .cache
-                .get_range(&msg.key)
+                .block_range(&msg.key)
                 .ok_or(anyhow!("GPU cache error: unbound key {:?}", msg.key))?;
 
             if range.len() == msg.data.len() {
+                self.upload_stats.uploads += 1;
+                self.upload_stats.bytes_uploaded += msg.data.len() as u64;
+
+                if let Some(staged) = self.staging.as_mut() {
+                    // the signal is called once the copy is recorded
+                    staged.batch.write(range.start, msg.data, msg.and_then);
+                    continue;
+                }
+
+                let slice = res[self.buffer]
+                    .mapped_slice_mut()
+                    .expect("GPU cache buffer must be host-accessible");
                 slice[range].clone_from_slice(&msg.data);
+
                 if let Some(signal) = msg.and_then {
                     signal();
                 }
             } else {
                 log::debug!("received data of incorrect width, ignoring");
+                self.upload_stats.dropped_wrong_width += 1;
             }
         }
 
+        // requests that failed or were displaced send no data
+        self.cancel_unbound();
+
         Ok(())
     }
 
@@ -467,6 +1560,267 @@ where
         &self.cache
     }
 
+    fn memory_location(device_local: bool) -> gpu_allocator::MemoryLocation {
+        if device_local {
+            gpu_allocator::MemoryLocation::GpuOnly
+        } else {
+            gpu_allocator::MemoryLocation::CpuToGpu
+        }
+    }
+
+    pub fn is_device_local(&self) -> bool {
+        self.staging.is_some()
+    }
+
+    /// The number of bytes received by `apply_data_updates` that
+    /// haven't been copied to a device-local buffer yet
+    pub fn pending_upload_bytes(&self) -> usize {
+        self.staging
+            .as_ref()
+            .map(|staged| staged.batch.pending_bytes())
+            .unwrap_or(0)
+    }
+
+    /// Records the copies of the data received by `apply_data_updates`
+    /// from the staging buffer to a device-local cache buffer, with a
+    /// barrier that makes them visible to later commands, and calls
+    /// the signals of the copied data. Does nothing for host-visible
+    /// caches.
+    ///
+    /// If the cache has grown since the last call, the contents of
+    /// the buffer are first copied to the larger buffer, after any
+    /// uploads recorded earlier, which then replaces it. This must be
+    /// recorded before any commands that use the new blocks.
+    ///
+    /// `frame` identifies the submission `cmd` belongs to; the staging
+    /// memory it uses is reused once `reclaim_staging` has been called
+    /// with `frame` or a later frame. Data that doesn't fit in the
+    /// free staging memory is left for a later call.
+    ///
+    /// Returns the number of bytes copied.
+    pub fn record_uploads(
+        &mut self,
+        ctx: &VkContext,
+        res: &mut GpuResources,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+    ) -> Result<usize> {
+        if let Some((buffer, old_size)) = self.pending_grow.take() {
+            self.record_grow(ctx, res, cmd, buffer, old_size)?;
+        }
+
+        let staged = match self.staging.as_mut() {
+            Some(staged) => staged,
+            None => return Ok(0),
+        };
+
+        let slice = res[staged.buffer]
+            .mapped_slice_mut()
+            .expect("GPU cache staging buffer must be host-accessible");
+
+        let copy = staged.batch.stage(&mut staged.ring, slice);
+        staged.ring.end_frame(frame);
+
+        if copy.regions.is_empty() {
+            return Ok(0);
+        }
+
+        let src = res[staged.buffer].buffer;
+        let dst = res[self.buffer].buffer;
+
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(
+                vk::AccessFlags::SHADER_READ
+                    | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                    | vk::AccessFlags::INDEX_READ,
+            )
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(dst)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        let device = ctx.device();
+
+        unsafe {
+            device.cmd_copy_buffer(cmd, src, dst, &copy.regions);
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+
+        for signal in copy.signals {
+            signal();
+        }
+
+        Ok(copy.bytes)
+    }
+
+    // copies the contents of the current buffer to `buffer`, and
+    // replaces it; the earlier copies from staging, recorded in this
+    // or an earlier command buffer, must be done before the copy, and
+    // the copy before the uploads that follow
+    fn record_grow(
+        &mut self,
+        ctx: &VkContext,
+        res: &mut GpuResources,
+        cmd: vk::CommandBuffer,
+        buffer: BufferRes,
+        old_size: usize,
+    ) -> Result<()> {
+        let src = res[self.buffer].buffer;
+        let dst = buffer.buffer;
+
+        let barrier = |src_access, dst_access| {
+            vk::MemoryBarrier::builder()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .build()
+        };
+
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: old_size as u64,
+        };
+
+        let device = ctx.device();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[barrier(
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                )],
+                &[],
+                &[],
+            );
+            device.cmd_copy_buffer(cmd, src, dst, &[region]);
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[barrier(
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_WRITE
+                        | vk::AccessFlags::SHADER_READ
+                        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                        | vk::AccessFlags::INDEX_READ,
+                )],
+                &[],
+                &[],
+            );
+        }
+
+        self.swap_buffer(res, buffer)
+    }
+
+    // the descriptor set is updated in place, so it stays valid for
+    // sublayers that use it
+    fn swap_buffer(
+        &mut self,
+        res: &mut GpuResources,
+        buffer: BufferRes,
+    ) -> Result<()> {
+        self.retired
+            .extend(res.insert_buffer_at(self.buffer, buffer));
+
+        let desc_set = allocate_buffer_desc_set(self.buffer, res)?;
+        let _ = res.insert_desc_set_at(self.desc_set, desc_set);
+
+        Ok(())
+    }
+
+    /// Frees the staging memory used by `record_uploads` for every
+    /// frame up to and including `completed_frame`, which must only be
+    /// called after that frame's fence has signaled
+    pub fn reclaim_staging(&mut self, completed_frame: u64) {
+        if let Some(staged) = self.staging.as_mut() {
+            staged.ring.reclaim(completed_frame);
+        }
+    }
+
+    /// Queues a request for the data of a bound key, stamped with the
+    /// generation of its binding, replacing any earlier request for
+    /// the key that's still pending. The request is cancelled if the
+    /// key is unbound before a worker picks it up.
+    ///
+    /// Returns `None` if the key isn't bound, and `QueueFull` if too
+    /// many requests of the same or higher priority are pending.
+    pub fn request_update<F, G>(
+        &mut self,
+        key: K,
+        priority: Priority,
+        f: F,
+        signal: G,
+    ) -> std::result::Result<Option<CancelToken>, CacheError>
+    where
+        K: Clone,
+        F: FnOnce(&K) -> anyhow::Result<Vec<u8>> + Send + Sync + 'static,
+        G: FnOnce() + Send + Sync + 'static,
+    {
+        let generation = match self.cache.generation(&key) {
+            Some(generation) => generation,
+            None => return Ok(None),
+        };
+
+        let msg = UpdateReqMsg::new(key.clone(), generation, f, signal)
+            .with_priority(priority);
+        let token = msg.cancel_token();
+
+        self.requests.push(msg)?;
+
+        self.pending.insert(key, generation, token.clone());
+
+        Ok(Some(token))
+    }
+
+    // cancels the pending requests of keys that have been unbound or
+    // rebound since the requests were made, and forgets the requests
+    // that have been cancelled, or that failed
+    fn cancel_unbound(&mut self) {
+        let cache = &self.cache;
+        self.pending.retain(|key, generation| {
+            cache.generation(key) == Some(generation)
+        });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let uploads = &self.upload_stats;
+        let requests = self.requests.stats();
+        CacheStats {
+            uploads: uploads.uploads,
+            bytes_uploaded: uploads.bytes_uploaded,
+            dropped_wrong_width: uploads.dropped_wrong_width,
+            dropped_unbound: uploads.dropped_unbound,
+            dropped_stale: uploads.dropped_stale,
+            requests_cancelled: requests.cancelled,
+            requests_rejected: requests.rejected,
+            requests_failed: requests.failed,
+            pending_requests: requests.pending,
+            ..self.cache.stats()
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.cache.reset_stats();
+        self.upload_stats.reset_counters();
+        self.requests.reset_stats();
+    }
+
     pub fn buffer(&self) -> BufferIx {
         self.buffer
     }
@@ -475,21 +1829,136 @@ where
         self.desc_set
     }
 
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.cache.set_eviction_policy(policy);
+    }
+
+    /// With a policy other than `GrowPolicy::Fixed`, binding keys
+    /// when no block is free grows the buffer, see `grow_buffer`
+    pub fn set_grow_policy(&mut self, policy: GrowPolicy) {
+        self.cache.set_grow_policy(policy);
+    }
+
+    /// Binds a single key, evicting another if the cache is full and
+    /// eviction is enabled, or growing the buffer if the grow policy
+    /// allows it; see `BufferCache::bind_block`. A device-local buffer
+    /// is replaced by the next `record_uploads`.
+    pub fn bind_block(
+        &mut self,
+        engine: &mut VkEngine,
+        key: K,
+    ) -> anyhow::Result<bool>
+    where
+        K: Clone,
+    {
+        if let Some(capacity) = self.cache.capacity_to_bind(&key) {
+            self.grow_buffer(engine, capacity)?;
+        }
+        let bound = self.cache.bind_block(key)?;
+        self.cancel_unbound();
+        Ok(bound)
+    }
+
+    /// The keys evicted since the last call, whose data must be
+    /// requested again if they're needed
+    pub fn take_evicted(&mut self) -> Vec<K> {
+        self.cache.take_evicted()
+    }
+
+    /// Keeps `key` bound, even when other views rebind the cache,
+    /// until the lease is dropped
+    pub fn lease(&self, key: &K) -> Option<BlockLease> {
+        self.cache.lease(key)
+    }
+
+    /// Binds `new_keys` and unbinds every other key that isn't
+    /// pinned, see `BufferCache::rebind_blocks`. Like `bind_block`,
+    /// this may grow the buffer.
     pub fn bind_blocks(
         &mut self,
+        engine: &mut VkEngine,
         new_keys: impl IntoIterator<Item = K>,
-    ) -> std::result::Result<(), CacheError>
+    ) -> anyhow::Result<()>
     where
         K: Clone + std::fmt::Debug,
     {
+        let new_keys = new_keys.into_iter().collect::<Vec<_>>();
+        if let Some(capacity) = self.cache.capacity_to_rebind(&new_keys) {
+            self.grow_buffer(engine, capacity)?;
+        }
         let new_keys = self.cache.rebind_blocks(new_keys)?;
         // for key in new_keys {
         //     self.block_state_map
         //         .insert(key, Arc::new(BlockState::Unknown.into()));
         // }
+        self.cancel_unbound();
         Ok(())
     }
 
+    // allocates a buffer of `capacity` blocks, which the cache is
+    // about to grow to, to replace the current one, whose contents
+    // keep their offsets; the cache is only grown if this succeeds
+    //
+    // a host-visible buffer is copied and replaced right away, while
+    // a device-local one is left for `record_uploads`, so that the
+    // copy comes after the uploads to the current buffer that have
+    // already been recorded
+    //
+    // the old buffer may still be in use by frames in flight, so it's
+    // kept until `take_retired_buffers` is called
+    fn grow_buffer(
+        &mut self,
+        engine: &mut VkEngine,
+        capacity: usize,
+    ) -> Result<()> {
+        let block_bytes = self.cache.elem_size * self.cache.block_size;
+        let mem_loc = Self::memory_location(self.is_device_local());
+
+        let mut buffer = engine.with_allocators(|ctx, res, alloc| {
+            res.allocate_buffer(
+                ctx,
+                alloc,
+                mem_loc,
+                self.cache.elem_size,
+                capacity * block_bytes,
+                self.usage,
+                Some(&self.name),
+            )
+        })?;
+
+        if self.is_device_local() {
+            // growing again before the last grow was recorded replaces
+            // the unused buffer, but copies from the current one
+            let old_size = match self.pending_grow.take() {
+                Some((unused, old_size)) => {
+                    self.retired.push(unused);
+                    old_size
+                }
+                None => self.cache.buffer_size(),
+            };
+            self.pending_grow = Some((buffer, old_size));
+            return Ok(());
+        }
+
+        let old_size = self.cache.buffer_size();
+        let old = engine.resources[self.buffer]
+            .mapped_slice_mut()
+            .expect("GPU cache buffer must be host-accessible");
+        let new = buffer
+            .mapped_slice_mut()
+            .expect("GPU cache buffer must be host-accessible");
+        new[..old_size].copy_from_slice(&old[..old_size]);
+
+        self.swap_buffer(&mut engine.resources, buffer)
+    }
+
+    /// The buffers replaced when the cache grew, which must be freed
+    /// once the frames that may use them have finished, e.g. by
+    /// sending them to the clear queue
+    pub fn take_retired_buffers(&mut self) -> Vec<BufferRes> {
+        std::mem::take(&mut self.retired)
+    }
+
     /// keys that haven't been bound are ignored
     pub fn block_ranges<'a>(
         &'a self,
@@ -508,10 +1977,22 @@ where
         new_block_size: usize,
     ) -> anyhow::Result<()> {
         self.cache.reallocate(new_block_count, new_block_size);
+        self.cancel_unbound();
         let capacity = self.cache.buffer_size();
 
+        // every key has been unbound, so the pending data is stale
+        if let Some(staged) = self.staging.as_mut() {
+            staged.batch.clear();
+        }
+
+        let mem_loc = Self::memory_location(self.is_device_local());
+        let pending_grow = self.pending_grow.take();
+
         engine.with_allocators(|ctx, res, alloc| {
-            let mem_loc = gpu_allocator::MemoryLocation::CpuToGpu;
+            // never used by the GPU, so it can be freed right away
+            if let Some((buffer, _)) = pending_grow {
+                res.free_buffer(ctx, alloc, buffer)?;
+            }
 
             let buffer = res.allocate_buffer(
                 ctx,
@@ -566,8 +2047,8 @@ mod tests {
         let r0 = cache.bind_block(k_as[0].clone())?;
 
         assert!(cache.used_blocks() == 1);
-        assert!(cache.used_blocks[0]);
-        assert!(!cache.used_blocks[1]);
+        assert!(!cache.free_blocks.contains(0));
+        assert!(cache.free_blocks.contains(1));
         assert!(!cache.is_empty());
         assert!(!cache.is_full());
 
@@ -684,6 +2165,414 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_free_blocks() -> anyhow::Result<()> {
+        let n = 200_000;
+        let mut cache: BufferCache<usize> = BufferCache::new(4, 2, n);
+
+        let block_bytes = 8;
+
+        cache.rebind_blocks(0..n)?;
+        assert!(cache.is_full());
+        assert!(cache.free_blocks.is_empty());
+
+        let block_of = |cache: &BufferCache<usize>, k: usize| {
+            cache.get_range(&k).unwrap().start / block_bytes
+        };
+
+        // freed blocks are reused lowest first
+        let mut freed = [150_000, 7, 99_999]
+            .into_iter()
+            .map(|k| {
+                let block = block_of(&cache, k);
+                cache.unbind_block(&k);
+                block
+            })
+            .collect::<Vec<_>>();
+        freed.sort();
+
+        for k in n..n + 3 {
+            cache.bind_block(k)?;
+        }
+        let reused =
+            (n..n + 3).map(|k| block_of(&cache, k)).collect::<Vec<_>>();
+        assert_eq!(reused, freed);
+
+        cache.reallocate_blocks(10);
+        assert!(cache.is_empty());
+        assert_eq!(cache.free_blocks.len(), 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pinning() -> anyhow::Result<()> {
+        let elem_size = std::mem::size_of::<u32>();
+        let block_size: usize = 8;
+        let block_capacity = 4;
+
+        let keys = (0..8).collect::<Vec<usize>>();
+
+        let mut cache: BufferCache<usize> =
+            BufferCache::new(elem_size, block_size, block_capacity)
+                .with_eviction(EvictionPolicy::Lru);
+
+        cache.rebind_blocks(keys[0..4].iter().copied())?;
+        assert_eq!(cache.pinned_blocks(), 0);
+
+        // one view pins 0 explicitly, another holds a lease on 1
+        assert_eq!(cache.pin(&keys[0]), Some(1));
+        assert_eq!(cache.pin(&keys[0]), Some(2));
+        let lease = cache.lease(&keys[1]).unwrap();
+        let r1 = cache.get_range(&keys[1]).unwrap();
+        assert_eq!(lease.range(), r1);
+        assert_eq!(cache.pinned_blocks(), 2);
+        assert!(cache.pin(&keys[7]).is_none());
+
+        // pinned keys can't be unbound
+        assert!(cache.unbind_block(&keys[0]).is_none());
+        assert!(cache.is_bound(&keys[0]));
+
+        // and survive a rebind by a different view
+        let new = cache.rebind_blocks(keys[4..6].iter().copied())?;
+        assert_eq!(new.len(), 2);
+        assert!(cache.is_bound(&keys[0]) && cache.is_bound(&keys[1]));
+        assert!(!cache.is_bound(&keys[2]) && !cache.is_bound(&keys[3]));
+        assert_eq!(cache.get_range(&keys[1]), Some(r1.clone()));
+
+        // there's no room for three more keys next to the pinned ones
+        assert_eq!(
+            cache.rebind_blocks(keys[5..8].iter().copied()),
+            Err(CacheError::OutOfBlocks)
+        );
+
+        // and eviction skips them, even though they're the oldest
+        cache.get_range(&keys[4]);
+        cache.get_range(&keys[5]);
+        cache.bind_block(keys[6])?;
+        assert_eq!(cache.take_evicted(), vec![keys[4]]);
+
+        // clones of a lease keep the block pinned
+        let lease2 = lease.clone();
+        assert_eq!(cache.pin_count(&keys[1]), 2);
+        drop(lease);
+        assert!(cache.is_pinned(&keys[1]));
+        drop(lease2);
+        assert!(!cache.is_pinned(&keys[1]));
+
+        assert_eq!(cache.unpin(&keys[0]), Some(1));
+        assert_eq!(cache.unpin(&keys[0]), Some(0));
+        assert_eq!(cache.unpin(&keys[0]), None);
+        assert_eq!(cache.pinned_blocks(), 0);
+
+        // a clone of the cache doesn't share pins with the original
+        cache.pin(&keys[0]);
+        let copy = cache.clone();
+        cache.unpin(&keys[0]);
+        assert!(copy.is_pinned(&keys[0]));
+        assert!(!cache.is_pinned(&keys[0]));
+
+        // leases that outlive a clear don't pin the next binding
+        let lease = cache.lease(&keys[0]).unwrap();
+        cache.clear();
+        cache.bind_block(keys[7])?;
+        assert!(!cache.is_pinned(&keys[7]));
+        assert!(cache.unbind_block(&keys[7]).is_some());
+        drop(lease);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> anyhow::Result<()> {
+        let elem_size = std::mem::size_of::<u32>();
+        let block_size: usize = 8;
+        let block_capacity = 4;
+
+        let mut cache: BufferCache<usize> =
+            BufferCache::new(elem_size, block_size, block_capacity);
+        let mut buffer = vec![0u8; cache.buffer_size()];
+
+        cache.rebind_blocks(0..4)?;
+        assert!(cache.bind_block(4).is_err());
+
+        let before = cache.stats();
+        assert_eq!(before.binds, 4);
+        assert_eq!(before.misses, 5);
+        assert_eq!(before.out_of_blocks, 1);
+        assert_eq!(before.used_blocks, 4);
+        assert_eq!(before.occupancy(), 1.0);
+
+        // writing data isn't a lookup
+        cache.write_block(&mut buffer, &0, &[0u8; 32])?;
+        cache.get_range(&0);
+        cache.get_range(&1);
+        cache.get_range(&9);
+        cache.bind_block(2)?;
+
+        cache.unbind_block(&3);
+        cache.pin(&0);
+
+        cache.set_eviction_policy(EvictionPolicy::Lru);
+        cache.bind_block(5)?;
+        cache.bind_block(6)?;
+
+        let stats = cache.stats();
+        let delta = stats.since(&before);
+        assert_eq!(delta.hits, 3);
+        assert_eq!(delta.misses, 3);
+        assert_eq!(delta.binds, 2);
+        assert_eq!(delta.unbinds, 1);
+        assert_eq!(delta.evictions, 1);
+        assert_eq!(delta.out_of_blocks, 0);
+        assert_eq!(delta.pinned_blocks, 1);
+        assert_eq!(stats.hit_rate(), Some(3.0 / 11.0));
+
+        let text = stats.to_string();
+        assert!(text.contains("4/4 used"));
+        assert!(text.contains("1 pinned"));
+
+        cache.reset_stats();
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses + stats.binds, 0);
+        assert_eq!(stats.hit_rate(), None);
+        assert_eq!(stats.used_blocks, 4);
+        assert_eq!(stats.block_size, block_size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generations() -> anyhow::Result<()> {
+        let mut cache: BufferCache<usize> = BufferCache::new(4, 8, 2);
+
+        cache.bind_block(0)?;
+        cache.bind_block(1)?;
+        let g0 = cache.generation(&0).unwrap();
+        let g1 = cache.generation(&1).unwrap();
+        assert_ne!(g0, g1);
+
+        // binding an already bound key keeps its generation
+        cache.bind_block(0)?;
+        assert_eq!(cache.generation(&0), Some(g0));
+
+        // a request made for the first binding
+        let msg = UpdateReqMsg::new(0, g0, |k| Ok(vec![*k as u8; 32]), || ());
+        assert_eq!(msg.generation(), g0);
+
+        // rebinding the key gets the same block, but a new generation
+        let range = cache.get_range(&0);
+        cache.unbind_block(&0);
+        assert_eq!(cache.generation(&0), None);
+        cache.bind_block(0)?;
+        assert_eq!(cache.get_range(&0), range);
+        let g2 = cache.generation(&0).unwrap();
+        assert!(g2 > g1);
+
+        // so the data of the old request can be told apart
+        let data = (msg.create_payload)(msg.key)?;
+        assert_eq!(data.generation, g0);
+        assert_ne!(Some(data.generation), cache.generation(&data.key));
+
+        // generations are never reused, even after clearing
+        cache.clear();
+        cache.bind_block(0)?;
+        assert!(cache.generation(&0).unwrap() > g2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eviction() -> anyhow::Result<()> {
+        let elem_size = std::mem::size_of::<u32>();
+        let block_size: usize = 8;
+        let block_capacity = 4;
+
+        let keys = (0..8).collect::<Vec<usize>>();
+
+        // without eviction a full cache is an error
+        let mut cache: BufferCache<usize> =
+            BufferCache::new(elem_size, block_size, block_capacity);
+        cache.rebind_blocks(keys[0..4].iter().copied())?;
+        assert_eq!(cache.bind_block(keys[4]), Err(CacheError::OutOfBlocks));
+        assert!(cache.take_evicted().is_empty());
+
+        let mut cache = cache.with_eviction(EvictionPolicy::Lru);
+        assert_eq!(cache.eviction_policy(), EvictionPolicy::Lru);
+
+        // access the keys in order, then 0 again, leaving 1 as the LRU
+        let ranges = keys[0..4]
+            .iter()
+            .map(|k| cache.get_range(k).unwrap())
+            .collect::<Vec<_>>();
+        cache.get_range(&keys[0]);
+
+        assert!(cache.bind_block(keys[4])?);
+        assert_eq!(cache.take_evicted(), vec![keys[1]]);
+        assert_eq!(cache.get_range(&keys[4]), Some(ranges[1].clone()));
+        assert!(cache.is_bound(&keys[0]));
+
+        // rebinding an already bound key counts as a use
+        assert!(!cache.bind_block(keys[3])?);
+        cache.bind_block(keys[5])?;
+        cache.bind_block(keys[6])?;
+        assert_eq!(cache.take_evicted(), vec![keys[2], keys[0]]);
+        assert!(cache.is_full());
+        assert!(cache.take_evicted().is_empty());
+
+        let mut cache: BufferCache<usize> =
+            BufferCache::new(elem_size, block_size, block_capacity)
+                .with_eviction(EvictionPolicy::Lfu);
+        cache.rebind_blocks(keys[0..4].iter().copied())?;
+
+        for (key, uses) in [(0, 3), (1, 1), (2, 2), (3, 2)] {
+            for _ in 0..uses {
+                cache.get_range(&keys[key]);
+            }
+        }
+
+        cache.bind_block(keys[4])?;
+        assert_eq!(cache.take_evicted(), vec![keys[1]]);
+
+        // the new key has the fewest uses
+        cache.bind_block(keys[5])?;
+        assert_eq!(cache.take_evicted(), vec![keys[4]]);
+
+        // the tie between 3 and 5 goes to the least recently used
+        cache.get_range(&keys[5]);
+        cache.get_range(&keys[5]);
+        cache.get_range(&keys[2]);
+        cache.bind_block(keys[6])?;
+        assert_eq!(cache.take_evicted(), vec![keys[3]]);
+        assert!(cache.is_bound(&keys[6]));
+
+        // binding more keys at once than there are blocks can't work
+        assert_eq!(
+            cache.rebind_blocks(keys.iter().copied()),
+            Err(CacheError::OutOfBlocks)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_grow() -> anyhow::Result<()> {
+        let elem_size = 4;
+        let block_size = 2;
+        let block_bytes = elem_size * block_size;
+
+        let mut cache: BufferCache<usize> =
+            BufferCache::new(elem_size, block_size, 4)
+                .with_grow_policy(GrowPolicy::Double { max_blocks: 16 });
+
+        cache.rebind_blocks(0..4)?;
+        assert!(cache.is_full());
+
+        let ranges = (0..4)
+            .map(|k| (cache.get_range(&k), cache.generation(&k)))
+            .collect::<Vec<_>>();
+
+        // binding a key to a full cache doubles its size, and the
+        // bound keys keep their blocks
+        assert_eq!(cache.bind_block(4), Ok(true));
+        assert_eq!(cache.block_capacity(), 8);
+        assert_eq!(cache.buffer_size(), 8 * block_bytes);
+        assert_eq!(cache.get_range(&4), Some(4 * block_bytes..5 * block_bytes));
+        assert!(!cache.is_full());
+
+        cache.rebind_blocks(0..13)?;
+        assert_eq!(cache.block_capacity(), 16);
+        assert_eq!(cache.stats().grows, 2);
+
+        let kept = (0..4)
+            .map(|k| (cache.get_range(&k), cache.generation(&k)))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, ranges);
+
+        // not past the maximum
+        assert_eq!(cache.rebind_blocks(0..17), Err(CacheError::OutOfBlocks));
+        assert_eq!(cache.block_capacity(), 16);
+        assert_eq!(cache.used_blocks(), 13);
+
+        // eviction comes first
+        cache.set_eviction_policy(EvictionPolicy::Lru);
+        cache.rebind_blocks(0..16)?;
+        cache.bind_block(100)?;
+        assert_eq!(cache.take_evicted().len(), 1);
+        assert_eq!(cache.block_capacity(), 16);
+
+        cache.grow_blocks(8);
+        assert_eq!(cache.block_capacity(), 16);
+
+        // reallocating sets the capacity, and with it the buffer size
+        cache.reallocate(6, 3);
+        assert_eq!(cache.block_capacity(), 6);
+        assert_eq!(cache.buffer_size(), 6 * 3 * elem_size);
+        assert!(cache.is_empty());
+
+        cache.set_grow_policy(GrowPolicy::Fixed);
+        cache.set_eviction_policy(EvictionPolicy::None);
+        cache.rebind_blocks(0..6)?;
+        assert!(cache.is_full());
+        assert_eq!(cache.bind_block(6), Err(CacheError::OutOfBlocks));
+
+        cache.reallocate_blocks(2);
+        assert_eq!(cache.block_capacity(), 2);
+        assert_eq!(cache.stats().block_capacity, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_grow_first() -> anyhow::Result<()> {
+        let mut cache: BufferCache<usize> = BufferCache::new(4, 2, 4)
+            .with_grow_policy(GrowPolicy::Double { max_blocks: 16 });
+
+        cache.rebind_blocks(0..4)?;
+        let ranges = (0..4).map(|k| cache.get_range(&k)).collect::<Vec<_>>();
+
+        assert_eq!(cache.capacity_to_bind(&0), None);
+        assert_eq!(cache.capacity_to_bind(&4), Some(8));
+
+        // if growing the buffer fails, the cache isn't touched, and
+        // the bind can be tried again
+        assert_eq!(cache.block_capacity(), 4);
+        assert_eq!(cache.used_blocks(), 4);
+        assert_eq!(cache.stats().grows, 0);
+        assert!(!cache.is_bound(&4));
+        assert_eq!(cache.capacity_to_bind(&4), Some(8));
+
+        // the cache grows to the predicted capacity
+        cache.bind_block(4)?;
+        assert_eq!(cache.block_capacity(), 8);
+        assert_eq!(cache.capacity_to_bind(&5), None);
+        let kept = (0..4).map(|k| cache.get_range(&k)).collect::<Vec<_>>();
+        assert_eq!(kept, ranges);
+
+        // pinned keys that stay bound count toward the capacity
+        let _lease = cache.lease(&0).unwrap();
+        let keys = (1..9).collect::<Vec<_>>();
+        assert_eq!(cache.capacity_to_rebind(&keys[..7]), None);
+        assert_eq!(cache.capacity_to_rebind(&keys), Some(16));
+        cache.rebind_blocks(keys)?;
+        assert_eq!(cache.block_capacity(), 16);
+
+        // past the maximum, the bind fails without growing
+        assert_eq!(
+            cache.capacity_to_rebind(&(0..17).collect::<Vec<_>>()),
+            None
+        );
+
+        // blocks that can be evicted are used before growing
+        cache.rebind_blocks(0..16)?;
+        cache.set_grow_policy(GrowPolicy::Double { max_blocks: 32 });
+        assert_eq!(cache.capacity_to_bind(&16), Some(32));
+        cache.set_eviction_policy(EvictionPolicy::Lru);
+        assert_eq!(cache.capacity_to_bind(&16), None);
+
+        Ok(())
+    }
 }
 
 fn allocate_buffer_desc_set(
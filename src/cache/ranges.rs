@@ -0,0 +1,1329 @@
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Range,
+};
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use raving::vk::{
+    context::VkContext, BufferIx, DescSetIx, GpuResources, VkEngine,
+};
+
+use super::{
+    allocate_buffer_desc_set,
+    staging::{StagedCopy, StagingRing},
+    CacheError,
+};
+
+type Signal = Box<dyn FnOnce() + Send + Sync + 'static>;
+
+/// A move of data within a buffer, produced by `RangeCache::compact`,
+/// in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeMove {
+    pub src: Range<usize>,
+    pub dst: usize,
+}
+
+impl RangeMove {
+    pub fn dst_range(&self) -> Range<usize> {
+        self.dst..self.dst + self.src.len()
+    }
+
+    /// Applies the move to a CPU-side copy of the buffer
+    pub fn apply(&self, buffer: &mut [u8]) {
+        buffer.copy_within(self.src.clone(), self.dst);
+    }
+}
+
+/// The moves from `RangeCache::compact` as two sets of copy regions,
+/// for applying them to a GPU buffer. A move's source and destination
+/// can overlap, which `vkCmdCopyBuffer` doesn't allow, so the moved
+/// ranges are first copied to a staging buffer of `staging_size`
+/// bytes, and then back to their destinations.
+#[derive(Debug, Default, Clone)]
+pub struct StagedMoves {
+    pub to_staging: Vec<vk::BufferCopy>,
+    pub from_staging: Vec<vk::BufferCopy>,
+    pub staging_size: usize,
+}
+
+impl StagedMoves {
+    /// Packs the moved ranges next to each other in the staging
+    /// buffer. Moves of adjacent ranges to adjacent destinations, as
+    /// produced by `compact`, share a region.
+    pub fn new(moves: &[RangeMove]) -> Self {
+        let mut staged = Self::default();
+
+        for mv in moves {
+            let len = mv.src.len() as u64;
+            let staging = staged.staging_size as u64;
+            let (src, dst) = (mv.src.start as u64, mv.dst as u64);
+
+            match (staged.to_staging.last_mut(), staged.from_staging.last_mut())
+            {
+                (Some(to), Some(from))
+                    if to.src_offset + to.size == src
+                        && from.dst_offset + from.size == dst =>
+                {
+                    to.size += len;
+                    from.size += len;
+                }
+                _ => {
+                    staged.to_staging.push(vk::BufferCopy {
+                        src_offset: src,
+                        dst_offset: staging,
+                        size: len,
+                    });
+                    staged.from_staging.push(vk::BufferCopy {
+                        src_offset: staging,
+                        dst_offset: dst,
+                        size: len,
+                    });
+                }
+            }
+
+            staged.staging_size += mv.src.len();
+        }
+
+        staged
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.to_staging.is_empty()
+    }
+
+    /// Records the copies to and from `staging`, which must be at
+    /// least `staging_size` bytes, with the barriers between them.
+    /// `buffer` must have been created with both `TRANSFER_SRC` and
+    /// `TRANSFER_DST` usage, and `staging` is free to reuse once the
+    /// command buffer has finished.
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        staging: vk::Buffer,
+    ) {
+        if self.is_empty() {
+            return;
+        }
+
+        let barrier =
+            |buffer: vk::Buffer, src: vk::AccessFlags, dst: vk::AccessFlags| {
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(src)
+                    .dst_access_mask(dst)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build()
+            };
+
+        // the staging copy must be complete before reading it back,
+        // and before overwriting the ranges it was read from
+        let staged = [
+            barrier(
+                staging,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            barrier(
+                buffer,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        ];
+
+        let moved = barrier(
+            buffer,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ
+                | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                | vk::AccessFlags::INDEX_READ,
+        );
+
+        unsafe {
+            device.cmd_copy_buffer(cmd, buffer, staging, &self.to_staging);
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &staged,
+                &[],
+            );
+            device.cmd_copy_buffer(cmd, staging, buffer, &self.from_staging);
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[moved],
+                &[],
+            );
+        }
+    }
+}
+
+/// Like `BufferCache`, but each key is bound to a range of any number
+/// of elements, rather than to a fixed-size block.
+///
+/// Free space is tracked as a list of ranges that are merged with
+/// their neighbors when freed, and allocations pick the smallest
+/// free range that fits. The free space can still end up split into
+/// ranges too small to use, which `compact` fixes by moving all
+/// bound ranges to the start of the buffer.
+///
+/// This only allocates ranges, and the caller applies the moves from
+/// `compact` to its buffer, with `RangeMove::apply` on the CPU, or
+/// with `StagedMoves` on the GPU. `GpuRangeCache` does the latter
+/// for a buffer that it owns.
+#[derive(Debug, Clone)]
+pub struct RangeCache<K>
+where
+    K: std::hash::Hash + Eq,
+{
+    // all in elements, not bytes
+    range_map: HashMap<K, Range<usize>>,
+
+    free_by_offset: BTreeMap<usize, usize>,
+    free_by_size: BTreeSet<(usize, usize)>,
+
+    elem_size: usize,
+    capacity: usize,
+    used_elems: usize,
+}
+
+impl<K: std::hash::Hash + Eq> RangeCache<K> {
+    /// `capacity` is the size of the buffer in elements
+    pub fn new(elem_size: usize, capacity: usize) -> Self {
+        let mut cache = Self {
+            range_map: HashMap::default(),
+
+            free_by_offset: BTreeMap::default(),
+            free_by_size: BTreeSet::default(),
+
+            elem_size,
+            capacity,
+            used_elems: 0,
+        };
+        cache.insert_free(0, capacity);
+        cache
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn elem_size(&self) -> usize {
+        self.elem_size
+    }
+
+    pub fn used_elems(&self) -> usize {
+        self.used_elems
+    }
+
+    pub fn free_elems(&self) -> usize {
+        self.capacity - self.used_elems
+    }
+
+    /// The size of the largest range that can currently be bound
+    /// without compacting
+    pub fn largest_free(&self) -> usize {
+        self.free_by_size
+            .iter()
+            .next_back()
+            .map(|&(len, _)| len)
+            .unwrap_or(0)
+    }
+
+    /// The number of separate free ranges; 1 (or 0, if full) after
+    /// compaction
+    pub fn free_ranges(&self) -> usize {
+        self.free_by_offset.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.range_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range_map.is_empty()
+    }
+
+    /// Returns the size of the total cache, in bytes
+    pub fn buffer_size(&self) -> usize {
+        self.capacity * self.elem_size
+    }
+
+    pub fn clear(&mut self) {
+        self.range_map.clear();
+        self.free_by_offset.clear();
+        self.free_by_size.clear();
+        self.used_elems = 0;
+        self.insert_free(0, self.capacity);
+    }
+
+    pub fn reallocate(&mut self, new_capacity: usize) {
+        self.capacity = new_capacity;
+        self.clear();
+    }
+
+    pub fn is_bound<Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        self.range_map.contains_key(k)
+    }
+
+    /// The range bound to the key, in bytes
+    pub fn get_range<Q: ?Sized>(&self, k: &Q) -> Option<Range<usize>>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        let range = self.range_map.get(k)?;
+        Some(range.start * self.elem_size..range.end * self.elem_size)
+    }
+
+    /// Binds the key to a range of `len` elements. Returns `Ok(false)`
+    /// if the key was already bound to a range of that length, and
+    /// `Ok(true)` if it was freshly bound, or moved to fit the new
+    /// length, in which case its data must be written again.
+    ///
+    /// If there's no free range large enough the key keeps its old
+    /// range, if any; `CacheError::OutOfSpace` reports whether there
+    /// would be enough space after compacting.
+    ///
+    /// A key bound with a length of 0 takes up no space, and always
+    /// succeeds, with the empty range `0..0`.
+    pub fn bind(
+        &mut self,
+        k: K,
+        len: usize,
+    ) -> std::result::Result<bool, CacheError> {
+        let old = self.range_map.get(&k).cloned();
+
+        if let Some(old) = &old {
+            if old.len() == len {
+                return Ok(false);
+            }
+            self.free(old.clone());
+        }
+
+        if len == 0 {
+            self.range_map.insert(k, 0..0);
+            return Ok(true);
+        }
+
+        match self.allocate(len) {
+            Some(range) => {
+                self.range_map.insert(k, range);
+                Ok(true)
+            }
+            None => {
+                if let Some(old) = old {
+                    self.take(old);
+                }
+                Err(CacheError::OutOfSpace {
+                    requested: len,
+                    free: self.free_elems(),
+                })
+            }
+        }
+    }
+
+    pub fn unbind<Q: ?Sized>(&mut self, k: &Q) -> Option<()>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        let range = self.range_map.remove(k)?;
+        self.free(range);
+        Some(())
+    }
+
+    /// Moves every bound range toward the start of the buffer, in
+    /// order, leaving a single free range at the end. Returns the
+    /// moves, in bytes, that must be applied to the buffer contents.
+    ///
+    /// The moves must be applied in order; each moves data toward the
+    /// start, so its source and destination may overlap, as with
+    /// `copy_within`. On the GPU, where they can't overlap, use
+    /// `StagedMoves`.
+    pub fn compact(&mut self) -> Vec<RangeMove> {
+        let mut ranges = self
+            .range_map
+            .values_mut()
+            .filter(|r| !r.is_empty())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|r| r.start);
+
+        let mut moves = Vec::new();
+        let mut offset = 0;
+
+        for range in ranges {
+            let len = range.len();
+
+            if range.start != offset {
+                moves.push(RangeMove {
+                    src: range.start * self.elem_size
+                        ..range.end * self.elem_size,
+                    dst: offset * self.elem_size,
+                });
+                *range = offset..offset + len;
+            }
+
+            offset += len;
+        }
+
+        self.free_by_offset.clear();
+        self.free_by_size.clear();
+        self.insert_free(offset, self.capacity - offset);
+
+        moves
+    }
+
+    pub fn write<Q: ?Sized>(
+        &self,
+        buffer: &mut [u8],
+        k: &Q,
+        data: &[u8],
+    ) -> Result<()>
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq + std::fmt::Debug,
+    {
+        if buffer.len() != self.buffer_size() {
+            return Err(CacheError::BufferSizeMismatch {
+                actual: buffer.len(),
+                expected: self.buffer_size(),
+            }
+            .into());
+        }
+
+        if data.len() % self.elem_size != 0 {
+            return Err(CacheError::ElemSizeMismatch.into());
+        }
+
+        let range = self
+            .get_range(k)
+            .ok_or(anyhow!("Range cache error: Unbound key {:?}", k))?;
+
+        if data.len() != range.len() {
+            return Err(CacheError::BlockSizeMismatch {
+                actual: data.len() / self.elem_size,
+                expected: range.len() / self.elem_size,
+            }
+            .into());
+        }
+
+        buffer[range].clone_from_slice(data);
+
+        Ok(())
+    }
+
+    fn insert_free(&mut self, offset: usize, len: usize) {
+        if len > 0 {
+            self.free_by_offset.insert(offset, len);
+            self.free_by_size.insert((len, offset));
+        }
+    }
+
+    fn remove_free(&mut self, offset: usize, len: usize) {
+        self.free_by_offset.remove(&offset);
+        self.free_by_size.remove(&(len, offset));
+    }
+
+    // best fit, at the start of the smallest free range that's large
+    // enough
+    fn allocate(&mut self, len: usize) -> Option<Range<usize>> {
+        let &(_, offset) = self.free_by_size.range((len, 0)..).next()?;
+        let range = offset..offset + len;
+        self.take(range.clone());
+        Some(range)
+    }
+
+    // marks a range that's entirely within one free range as used
+    fn take(&mut self, range: Range<usize>) {
+        let (&free_start, &free_len) = self
+            .free_by_offset
+            .range(..=range.start)
+            .next_back()
+            .expect("Range cache: Range to take isn't free");
+        let free_end = free_start + free_len;
+        debug_assert!(range.end <= free_end);
+
+        self.remove_free(free_start, free_len);
+        self.insert_free(free_start, range.start - free_start);
+        self.insert_free(range.end, free_end - range.end);
+
+        self.used_elems += range.len();
+    }
+
+    // merges the range with the free ranges on either side of it
+    fn free(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        self.used_elems -= range.len();
+
+        let mut start = range.start;
+        let mut end = range.end;
+
+        if let Some((&prev, &prev_len)) =
+            self.free_by_offset.range(..start).next_back()
+        {
+            if prev + prev_len == start {
+                self.remove_free(prev, prev_len);
+                start = prev;
+            }
+        }
+
+        if let Some(&next_len) = self.free_by_offset.get(&end) {
+            self.remove_free(end, next_len);
+            end += next_len;
+        }
+
+        self.insert_free(start, end - start);
+    }
+}
+
+/// Data for a key of a `GpuRangeCache`, of any number of elements;
+/// the key is bound to a range of that length when the data arrives
+pub struct RangeDataMsg<K> {
+    key: K,
+    data: Vec<u8>,
+    and_then: Option<Signal>,
+}
+
+impl<K> RangeDataMsg<K> {
+    pub fn new(key: K, data: Vec<u8>) -> Self {
+        Self {
+            key,
+            data,
+            and_then: None,
+        }
+    }
+
+    /// `signal` is called once the copy of the data is recorded
+    pub fn with_signal<G>(mut self, signal: G) -> Self
+    where
+        G: FnOnce() + Send + Sync + 'static,
+    {
+        self.and_then = Some(Box::new(signal));
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RangeUploadStats {
+    pub uploads: u64,
+    pub bytes_uploaded: u64,
+    pub compactions: u64,
+    /// Data that isn't a whole number of elements
+    pub dropped_wrong_width: u64,
+    /// Data that doesn't fit in the buffer, or in the staging buffer
+    pub dropped_out_of_space: u64,
+}
+
+/// The host side of a `GpuRangeCache`: binds each key to a range as
+/// its data arrives, compacting the ranges when the free space is
+/// too fragmented for it, and keeps the compactions and the writes
+/// that are still to be recorded.
+///
+/// Writes are kept by key and only given an offset by `stage`, after
+/// the compactions before it have been recorded, so that a pending
+/// write follows its range when that's moved.
+pub struct RangeUploads<K>
+where
+    K: std::hash::Hash + Eq,
+{
+    ranges: RangeCache<K>,
+    compactions: Vec<StagedMoves>,
+    writes: HashMap<K, (Vec<u8>, Vec<Signal>)>,
+    // the size of the staging buffer, which no write can exceed
+    max_write: usize,
+
+    stats: RangeUploadStats,
+}
+
+impl<K> RangeUploads<K>
+where
+    K: std::hash::Hash + Eq + Clone,
+{
+    /// `capacity` is the size of the buffer in elements, and
+    /// `max_write` the largest write in bytes that can be staged
+    pub fn new(elem_size: usize, capacity: usize, max_write: usize) -> Self {
+        Self {
+            ranges: RangeCache::new(elem_size, capacity),
+            compactions: Vec::new(),
+            writes: HashMap::default(),
+            max_write,
+
+            stats: RangeUploadStats::default(),
+        }
+    }
+
+    pub fn ranges(&self) -> &RangeCache<K> {
+        &self.ranges
+    }
+
+    pub fn stats(&self) -> RangeUploadStats {
+        self.stats
+    }
+
+    /// The number of bytes written that haven't been staged yet
+    pub fn pending_bytes(&self) -> usize {
+        self.writes.values().map(|(data, _)| data.len()).sum()
+    }
+
+    /// Binds the key to a range of the length of `data`, compacting
+    /// first if there's enough free space, but not in one range, and
+    /// queues the write. If the key can't be bound it keeps its old
+    /// range, and any earlier write to it stays pending.
+    pub fn write(
+        &mut self,
+        key: K,
+        data: Vec<u8>,
+        signal: Option<Signal>,
+    ) -> std::result::Result<(), CacheError> {
+        let elem_size = self.ranges.elem_size();
+
+        if data.len() % elem_size != 0 {
+            self.stats.dropped_wrong_width += 1;
+            return Err(CacheError::ElemSizeMismatch);
+        }
+
+        if data.len() > self.max_write {
+            self.stats.dropped_out_of_space += 1;
+            return Err(CacheError::StagingTooSmall {
+                size: data.len(),
+                capacity: self.max_write,
+            });
+        }
+
+        let len = data.len() / elem_size;
+
+        match self.ranges.bind(key.clone(), len) {
+            Ok(_) => (),
+            Err(err @ CacheError::OutOfSpace { .. }) => {
+                // the key's current range is freed when it's rebound
+                let old_len = self
+                    .ranges
+                    .get_range(&key)
+                    .map(|range| range.len() / elem_size)
+                    .unwrap_or(0);
+
+                if self.ranges.free_elems() + old_len < len {
+                    self.stats.dropped_out_of_space += 1;
+                    return Err(err);
+                }
+
+                // its data is written again, so its old range doesn't
+                // need to be moved, and is merged with the rest of the
+                // free space
+                self.ranges.unbind(&key);
+                self.compact();
+                self.ranges.bind(key.clone(), len)?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        let (old_data, signals) = self.writes.entry(key).or_default();
+        *old_data = data;
+        signals.extend(signal);
+
+        Ok(())
+    }
+
+    /// Writes the data of every message in the channel, dropping the
+    /// data that can't be bound
+    pub fn apply_data_updates(
+        &mut self,
+        name: &str,
+        data_msg_rx: &crossbeam::channel::Receiver<RangeDataMsg<K>>,
+    ) where
+        K: std::fmt::Debug,
+    {
+        while let Ok(msg) = data_msg_rx.try_recv() {
+            let key = msg.key.clone();
+            if let Err(err) = self.write(msg.key, msg.data, msg.and_then) {
+                log::debug!("{}: dropped data for {:?}: {}", name, key, err);
+            }
+        }
+    }
+
+    /// Unbinds the key, dropping its pending write
+    pub fn unbind(&mut self, key: &K) -> Option<()> {
+        self.writes.remove(key);
+        self.ranges.unbind(key)
+    }
+
+    /// Moves every bound range to the start of the buffer; the moves
+    /// are recorded before the following writes
+    pub fn compact(&mut self) {
+        let moves = self.ranges.compact();
+        if !moves.is_empty() {
+            self.compactions.push(StagedMoves::new(&moves));
+        }
+        self.stats.compactions += 1;
+    }
+
+    /// The moves of the compactions since the last call, which must
+    /// be applied in order, and before the writes from `stage`
+    pub fn take_compactions(&mut self) -> Vec<StagedMoves> {
+        std::mem::take(&mut self.compactions)
+    }
+
+    /// Copies as many pending writes as fit in the free part of the
+    /// ring into `staging`, at the current ranges of their keys, and
+    /// returns the copy regions for them. Writes that don't fit stay
+    /// pending.
+    pub fn stage(
+        &mut self,
+        ring: &mut StagingRing,
+        staging: &mut [u8],
+    ) -> StagedCopy<Signal> {
+        let mut staged = StagedCopy {
+            regions: Vec::new(),
+            signals: Vec::new(),
+            bytes: 0,
+        };
+
+        let ranges = &self.ranges;
+        let stats = &mut self.stats;
+
+        self.writes.retain(|key, (data, signals)| {
+            let range = match ranges.get_range(key) {
+                Some(range) => range,
+                None => return false,
+            };
+            debug_assert_eq!(range.len(), data.len());
+
+            // an empty range needs no copy
+            if !data.is_empty() {
+                let offset = match ring.alloc(data.len()) {
+                    Some(offset) => offset,
+                    None => return true,
+                };
+
+                staging[offset..offset + data.len()].copy_from_slice(data);
+                staged.regions.push(vk::BufferCopy {
+                    src_offset: offset as u64,
+                    dst_offset: range.start as u64,
+                    size: data.len() as u64,
+                });
+            }
+
+            staged.signals.append(signals);
+            staged.bytes += data.len();
+            stats.uploads += 1;
+            stats.bytes_uploaded += data.len() as u64;
+
+            false
+        });
+
+        staged
+    }
+}
+
+/// A `RangeCache` backed by a device-local GPU buffer, filled with
+/// data of any length for its keys, e.g. the vertices of meshes that
+/// are loaded and unloaded.
+///
+/// Data sent on `data_msg_tx` is bound to a range, and queued, by
+/// `apply_data_updates`; `record_updates` then records the copies
+/// from a staging buffer, after the moves of any compaction that the
+/// binding needed, which go through a scratch buffer the size of the
+/// cache buffer.
+pub struct GpuRangeCache<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    name: String,
+
+    buffer: BufferIx,
+    pub desc_set: DescSetIx,
+
+    staging: BufferIx,
+    ring: StagingRing,
+    scratch: BufferIx,
+
+    uploads: RangeUploads<K>,
+
+    pub data_msg_tx: crossbeam::channel::Sender<RangeDataMsg<K>>,
+    data_msg_rx: crossbeam::channel::Receiver<RangeDataMsg<K>>,
+}
+
+impl<K> GpuRangeCache<K>
+where
+    K: std::hash::Hash + Eq + Clone + Send + Sync + 'static,
+{
+    /// Creates a cache of `capacity` elements, with a staging buffer
+    /// of `staging_size` bytes, which bounds the size of the data for
+    /// a key. The buffer can also be used as a transfer source and
+    /// destination, for the uploads and the compactions.
+    pub fn new(
+        engine: &mut VkEngine,
+        usage: vk::BufferUsageFlags,
+        name: &str,
+        elem_size: usize,
+        capacity: usize,
+        staging_size: usize,
+    ) -> Result<Self> {
+        let transfer = vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST;
+
+        let (buffer, desc_set, staging, scratch) =
+            engine.with_allocators(|ctx, res, alloc| {
+                let buffer = res.allocate_buffer(
+                    ctx,
+                    alloc,
+                    gpu_allocator::MemoryLocation::GpuOnly,
+                    elem_size,
+                    capacity,
+                    usage | transfer,
+                    Some(name),
+                )?;
+                let buf_ix = res.insert_buffer(buffer);
+
+                let desc_set = allocate_buffer_desc_set(buf_ix, res)?;
+                let set_ix = res.insert_desc_set(desc_set);
+
+                let staging_name = format!("{} staging", name);
+                let staging = res.allocate_buffer(
+                    ctx,
+                    alloc,
+                    gpu_allocator::MemoryLocation::CpuToGpu,
+                    1,
+                    staging_size,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    Some(&staging_name),
+                )?;
+
+                let scratch_name = format!("{} scratch", name);
+                let scratch = res.allocate_buffer(
+                    ctx,
+                    alloc,
+                    gpu_allocator::MemoryLocation::GpuOnly,
+                    elem_size,
+                    capacity,
+                    transfer,
+                    Some(&scratch_name),
+                )?;
+
+                Ok((
+                    buf_ix,
+                    set_ix,
+                    res.insert_buffer(staging),
+                    res.insert_buffer(scratch),
+                ))
+            })?;
+
+        let (data_msg_tx, data_msg_rx) = crossbeam::channel::unbounded();
+
+        Ok(Self {
+            name: name.to_string(),
+
+            buffer,
+            desc_set,
+
+            staging,
+            ring: StagingRing::new(staging_size),
+            scratch,
+
+            uploads: RangeUploads::new(elem_size, capacity, staging_size),
+
+            data_msg_tx,
+            data_msg_rx,
+        })
+    }
+
+    /// Binds the keys of the received data to ranges, and queues the
+    /// writes for `record_updates`. Data that can't be bound is
+    /// dropped, and counted in the stats.
+    pub fn apply_data_updates(&mut self)
+    where
+        K: std::fmt::Debug,
+    {
+        self.uploads
+            .apply_data_updates(&self.name, &self.data_msg_rx);
+    }
+
+    /// Records the moves of the compactions since the last call, and
+    /// then the copies of the pending writes from the staging buffer,
+    /// with barriers that make them visible to later commands, and
+    /// calls the signals of the copied data.
+    ///
+    /// `frame` identifies the submission `cmd` belongs to; the staging
+    /// memory it uses is reused once `reclaim_staging` has been called
+    /// with `frame` or a later frame. Data that doesn't fit in the
+    /// free staging memory is left for a later call.
+    ///
+    /// Returns the number of bytes copied from the staging buffer.
+    pub fn record_updates(
+        &mut self,
+        ctx: &VkContext,
+        res: &mut GpuResources,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+    ) -> Result<usize> {
+        let device = ctx.device();
+        let buffer = res[self.buffer].buffer;
+
+        let compactions = self.uploads.take_compactions();
+        if !compactions.is_empty() {
+            // the earlier uploads must be done before their data is
+            // moved, and the earlier commands that read the buffer
+            // before it's overwritten
+            barrier(
+                device,
+                cmd,
+                (
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_READ
+                        | vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+
+            let scratch = res[self.scratch].buffer;
+            for moves in &compactions {
+                moves.record(device, cmd, buffer, scratch);
+            }
+
+            // and the moves before the writes that follow
+            barrier(
+                device,
+                cmd,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+        }
+
+        let slice = res[self.staging]
+            .mapped_slice_mut()
+            .expect("GPU range cache staging buffer must be host-accessible");
+
+        let copy = self.uploads.stage(&mut self.ring, slice);
+        self.ring.end_frame(frame);
+
+        if !copy.regions.is_empty() {
+            let src = res[self.staging].buffer;
+
+            unsafe {
+                device.cmd_copy_buffer(cmd, src, buffer, &copy.regions);
+            }
+
+            barrier(
+                device,
+                cmd,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::SHADER_READ
+                        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                        | vk::AccessFlags::INDEX_READ,
+                ),
+            );
+        }
+
+        for signal in copy.signals {
+            signal();
+        }
+
+        Ok(copy.bytes)
+    }
+
+    /// Frees the staging memory used by `record_updates` for every
+    /// frame up to and including `completed_frame`, which must only be
+    /// called after that frame's fence has signaled
+    pub fn reclaim_staging(&mut self, completed_frame: u64) {
+        self.ring.reclaim(completed_frame);
+    }
+
+    /// Unbinds the key, dropping any of its data that hasn't been
+    /// recorded yet
+    pub fn unbind(&mut self, key: &K) -> Option<()> {
+        self.uploads.unbind(key)
+    }
+
+    /// Compacts the ranges, even if the free space isn't fragmented
+    /// enough for a write to fail; the moves are recorded by the next
+    /// `record_updates`
+    pub fn compact(&mut self) {
+        self.uploads.compact();
+    }
+
+    /// The ranges, which reflect the data received by
+    /// `apply_data_updates`, including the writes and compactions
+    /// that haven't been recorded yet
+    pub fn ranges(&self) -> &RangeCache<K> {
+        self.uploads.ranges()
+    }
+
+    /// The number of bytes received by `apply_data_updates` that
+    /// haven't been copied to the buffer yet
+    pub fn pending_upload_bytes(&self) -> usize {
+        self.uploads.pending_bytes()
+    }
+
+    pub fn stats(&self) -> RangeUploadStats {
+        self.uploads.stats()
+    }
+
+    pub fn buffer(&self) -> BufferIx {
+        self.buffer
+    }
+
+    pub fn desc_set(&self) -> DescSetIx {
+        self.desc_set
+    }
+}
+
+// a global memory barrier, with the stage and access masks of the
+// commands on each side of it
+fn barrier(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+    (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .build();
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            cmd,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // what `vkCmdCopyBuffer` does
+    fn copy_regions(src: &[u8], dst: &mut [u8], regions: &[vk::BufferCopy]) {
+        for r in regions {
+            let (src_offset, dst_offset, len) = (
+                r.src_offset as usize,
+                r.dst_offset as usize,
+                r.size as usize,
+            );
+            dst[dst_offset..dst_offset + len]
+                .copy_from_slice(&src[src_offset..src_offset + len]);
+        }
+    }
+
+    // what `StagedMoves::record` does
+    fn apply_staged(
+        moves: &StagedMoves,
+        buffer: &mut [u8],
+        scratch: &mut [u8],
+    ) {
+        assert!(moves.staging_size <= scratch.len());
+        copy_regions(buffer, scratch, &moves.to_staging);
+        copy_regions(scratch, buffer, &moves.from_staging);
+    }
+
+    #[test]
+    fn test_alloc_free() -> anyhow::Result<()> {
+        let mut cache: RangeCache<&str> = RangeCache::new(4, 100);
+
+        assert!(cache.bind("a", 10)?);
+        assert!(cache.bind("b", 20)?);
+        assert!(cache.bind("c", 30)?);
+        assert!(!cache.bind("b", 20)?);
+
+        assert_eq!(cache.get_range("a"), Some(0..40));
+        assert_eq!(cache.get_range("b"), Some(40..120));
+        assert_eq!(cache.get_range("c"), Some(120..240));
+        assert_eq!(cache.used_elems(), 60);
+        assert_eq!(cache.largest_free(), 40);
+
+        // freeing a and b leaves a single free range in front of c
+        cache.unbind("a");
+        cache.unbind("b");
+        assert_eq!(cache.free_ranges(), 2);
+        assert_eq!(cache.largest_free(), 40);
+
+        // best fit picks the range at the end for 35 elements, and
+        // the one in front for 25
+        cache.bind("d", 35)?;
+        assert_eq!(cache.get_range("d"), Some(240..380));
+        cache.bind("e", 25)?;
+        assert_eq!(cache.get_range("e"), Some(0..100));
+
+        // a range can grow into the free space next to it
+        assert!(cache.bind("e", 30)?);
+        assert_eq!(cache.get_range("e"), Some(0..120));
+        assert!(cache.bind("c", 20)?);
+        assert_eq!(cache.get_range("c"), Some(120..200));
+        assert_eq!(cache.free_elems(), 100 - 30 - 20 - 35);
+
+        // empty ranges take no space, even when the cache is full
+        let free = cache.free_elems();
+        assert!(cache.bind("f", cache.largest_free())?);
+        assert!(cache.bind("h", cache.largest_free())?);
+        assert!(cache.bind("g", 0)?);
+        assert!(!cache.bind("g", 0)?);
+        assert_eq!(cache.get_range("g"), Some(0..0));
+        assert_eq!(cache.free_elems(), 0);
+        cache.write(&mut vec![0u8; cache.buffer_size()], "g", &[])?;
+
+        // and shrinking a range to nothing frees all of it
+        assert!(cache.bind("f", 0)?);
+        cache.unbind("h");
+        assert_eq!(cache.free_elems(), free);
+        cache.unbind("f");
+        cache.unbind("g");
+
+        cache.unbind("c");
+        cache.unbind("d");
+        cache.unbind("e");
+        assert!(cache.is_empty());
+        assert_eq!(cache.free_ranges(), 1);
+        assert_eq!(cache.largest_free(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> anyhow::Result<()> {
+        let elem_size = 4;
+        let mut cache: RangeCache<usize> = RangeCache::new(elem_size, 64);
+        let mut buffer = vec![0u8; cache.buffer_size()];
+
+        let data = |k: usize, len: usize| vec![k as u8 + 1; len * elem_size];
+
+        // fill the cache, then free every other range, so that there's
+        // enough space in total but not in one piece
+        let lens = [5, 3, 8, 7, 6, 9, 4, 6, 5, 11];
+        for (k, &len) in lens.iter().enumerate() {
+            cache.bind(k, len)?;
+            cache.write(&mut buffer, &k, &data(k, len))?;
+        }
+        for k in (0..lens.len()).step_by(2) {
+            cache.unbind(&k);
+        }
+
+        let free = cache.free_elems();
+        assert!(cache.largest_free() < 20);
+        assert_eq!(
+            cache.bind(20, 20),
+            Err(CacheError::OutOfSpace {
+                requested: 20,
+                free,
+            })
+        );
+        assert!(!cache.is_bound(&20));
+
+        // a failed resize keeps the old range
+        let r3 = cache.get_range(&3);
+        assert!(cache.bind(3, 30).is_err());
+        assert_eq!(cache.get_range(&3), r3);
+
+        let moves = cache.compact();
+
+        // going through staging gives the same result as applying the
+        // moves in place, and no copy overlaps itself
+        let staged = StagedMoves::new(&moves);
+        let mut staging = vec![0u8; staged.staging_size];
+        let mut gpu_buffer = buffer.clone();
+        apply_staged(&staged, &mut gpu_buffer, &mut staging);
+
+        for mv in moves {
+            assert!(mv.dst < mv.src.start);
+            mv.apply(&mut buffer);
+        }
+        assert_eq!(
+            &gpu_buffer[..cache.used_elems() * elem_size],
+            &buffer[..cache.used_elems() * elem_size]
+        );
+
+        assert_eq!(cache.free_ranges(), 1);
+        assert_eq!(cache.largest_free(), free);
+
+        let mut end = 0;
+        for k in (1..lens.len()).step_by(2) {
+            let range = cache.get_range(&k).unwrap();
+            assert_eq!(range.start, end);
+            assert_eq!(&buffer[range.clone()], &data(k, lens[k]));
+            end = range.end;
+        }
+
+        assert!(cache.bind(20, 20)?);
+        assert!(cache.write(&mut buffer, &20, &data(20, 19)).is_err());
+        cache.write(&mut buffer, &20, &data(20, 20))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_uploads() -> anyhow::Result<()> {
+        let elem_size = 4;
+        let capacity = 64;
+        let staging_size = 96;
+
+        let mut uploads: RangeUploads<usize> =
+            RangeUploads::new(elem_size, capacity, staging_size);
+        let (data_tx, data_rx) = crossbeam::channel::unbounded();
+
+        // the buffers of a `GpuRangeCache`
+        let mut buffer = vec![0u8; capacity * elem_size];
+        let mut scratch = vec![0u8; capacity * elem_size];
+        let mut staging = vec![0u8; staging_size];
+        let mut ring = StagingRing::new(staging_size);
+
+        let signals = Arc::new(AtomicUsize::new(0));
+        let send = |k: usize, len: usize, v: u8| {
+            let signals = signals.clone();
+            let msg = RangeDataMsg::new(k, vec![v; len * elem_size])
+                .with_signal(move || {
+                    signals.fetch_add(1, Ordering::SeqCst);
+                });
+            data_tx.send(msg).unwrap();
+        };
+
+        // what `GpuRangeCache::record_updates` does, for frames that
+        // finish right away, until every write is copied; returns the
+        // number of frames
+        let mut frame = 0;
+        let mut record = |uploads: &mut RangeUploads<usize>,
+                          buffer: &mut [u8]| {
+            let first = frame;
+            for moves in uploads.take_compactions() {
+                apply_staged(&moves, buffer, &mut scratch);
+            }
+            while uploads.pending_bytes() > 0 {
+                let copy = uploads.stage(&mut ring, &mut staging);
+                assert!(copy.bytes <= staging_size);
+                copy_regions(&staging, buffer, &copy.regions);
+                copy.signals.into_iter().for_each(|signal| signal());
+
+                ring.end_frame(frame);
+                ring.reclaim(frame);
+                frame += 1;
+            }
+            frame - first
+        };
+
+        let check = |uploads: &RangeUploads<usize>,
+                     buffer: &[u8],
+                     expected: &[(usize, usize, u8)]| {
+            assert_eq!(uploads.ranges().len(), expected.len());
+            for &(k, len, v) in expected {
+                let range = uploads.ranges().get_range(&k).unwrap();
+                assert_eq!(range.len(), len * elem_size);
+                assert!(buffer[range].iter().all(|&b| b == v), "key {}", k);
+            }
+        };
+
+        // fill the buffer, more than the staging buffer holds at once
+        let lens = [5, 3, 8, 7, 6, 9, 4, 6, 5, 11];
+        for (k, &len) in lens.iter().enumerate() {
+            send(k, len, k as u8 + 1);
+        }
+        uploads.apply_data_updates("test", &data_rx);
+        assert!(record(&mut uploads, &mut buffer) > 1);
+
+        let mut expected = lens
+            .iter()
+            .enumerate()
+            .map(|(k, &len)| (k, len, k as u8 + 1))
+            .collect::<Vec<_>>();
+        check(&uploads, &buffer, &expected);
+        assert_eq!(uploads.ranges().free_elems(), 0);
+
+        // free every other range, so that the free space is split up
+        for k in (0..lens.len()).step_by(2) {
+            uploads.unbind(&k);
+        }
+        expected.retain(|(k, _, _)| k % 2 == 1);
+        assert!(uploads.ranges().largest_free() < 20);
+
+        // new data for a key that's moved by the compaction, written
+        // before it's recorded; then a range that needs compacting,
+        // and one that shrinks
+        send(1, 3, 100);
+        send(20, 20, 20);
+        send(3, 2, 30);
+        uploads.apply_data_updates("test", &data_rx);
+        assert_eq!(uploads.stats().compactions, 1);
+        expected[0] = (1, 3, 100);
+        expected[1] = (3, 2, 30);
+        expected.push((20, 20, 20));
+
+        record(&mut uploads, &mut buffer);
+        check(&uploads, &buffer, &expected);
+
+        // data that isn't whole elements, that doesn't fit in the
+        // staging buffer, or in the buffer, is dropped, and the keys
+        // keep their old data
+        let free = uploads.ranges().free_elems();
+        data_tx.send(RangeDataMsg::new(5, vec![0; 3]))?;
+        send(7, staging_size / elem_size + 1, 0);
+        send(9, free + lens[9] + 1, 0);
+        uploads.apply_data_updates("test", &data_rx);
+        record(&mut uploads, &mut buffer);
+        check(&uploads, &buffer, &expected);
+
+        let stats = uploads.stats();
+        assert_eq!(stats.dropped_wrong_width, 1);
+        assert_eq!(stats.dropped_out_of_space, 2);
+        assert_eq!(stats.compactions, 1);
+        assert_eq!(stats.uploads, 13);
+        assert_eq!(signals.load(Ordering::SeqCst), 13);
+
+        // a key can grow to fill the buffer, though its old range
+        // isn't next to the free space
+        let free = uploads.ranges().free_elems();
+        send(9, free + lens[9], 90);
+        uploads.apply_data_updates("test", &data_rx);
+        assert_eq!(uploads.stats().compactions, 2);
+        assert_eq!(uploads.ranges().free_elems(), 0);
+        *expected.iter_mut().find(|(k, _, _)| *k == 9).unwrap() =
+            (9, free + lens[9], 90);
+
+        record(&mut uploads, &mut buffer);
+        check(&uploads, &buffer, &expected);
+
+        Ok(())
+    }
+}
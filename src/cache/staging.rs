@@ -0,0 +1,339 @@
+//! Uploads to device-local cache buffers. Writes are gathered in an
+//! `UploadBatch`, laid out in a `StagingRing` in order of destination
+//! offset, and copied with as few `vk::BufferCopy` regions as
+//! possible. The staging memory of a frame is reused once that frame
+//! has finished on the GPU.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use ash::vk;
+
+/// A ring allocator over a staging buffer of `capacity` bytes. Each
+/// allocation belongs to the frame passed to the following
+/// `end_frame`, and is freed by `reclaim` once that frame is done.
+#[derive(Debug, Clone)]
+pub struct StagingRing {
+    capacity: usize,
+
+    // the next allocation starts at `head`, unless it has to wrap
+    // around; the oldest allocation in use starts at `tail`
+    head: usize,
+    tail: usize,
+    used: usize,
+
+    // bytes allocated since the last `end_frame`, including any
+    // skipped at the end of the buffer when wrapping
+    frame_used: usize,
+    // (frame, head at the end of the frame, bytes used), oldest first
+    in_flight: VecDeque<(u64, usize, usize)>,
+}
+
+impl StagingRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+            frame_used: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes allocated and not yet reclaimed
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// The frames whose staging memory hasn't been reclaimed
+    pub fn frames_in_flight(&self) -> usize {
+        self.in_flight.len() + (self.frame_used > 0) as usize
+    }
+
+    /// Returns the offset of `len` contiguous bytes, or `None` if
+    /// there's no room until earlier frames are reclaimed
+    pub fn alloc(&mut self, len: usize) -> Option<usize> {
+        if self.used == 0 {
+            self.head = 0;
+            self.tail = 0;
+        }
+
+        let (offset, skipped) = if self.used == 0 || self.head > self.tail {
+            // free at [head, capacity) and [0, tail)
+            if self.capacity - self.head >= len {
+                (self.head, 0)
+            } else if self.tail >= len {
+                (0, self.capacity - self.head)
+            } else {
+                return None;
+            }
+        } else if self.head < self.tail && self.tail - self.head >= len {
+            (self.head, 0)
+        } else {
+            return None;
+        };
+
+        self.head = offset + len;
+        self.used += skipped + len;
+        self.frame_used += skipped + len;
+
+        Some(offset)
+    }
+
+    /// Assigns the allocations made since the last call to `frame`
+    pub fn end_frame(&mut self, frame: u64) {
+        if self.frame_used > 0 {
+            self.in_flight
+                .push_back((frame, self.head, self.frame_used));
+            self.frame_used = 0;
+        }
+    }
+
+    /// Frees the allocations of every frame up to and including
+    /// `completed`, whose fence must have signaled
+    pub fn reclaim(&mut self, completed: u64) {
+        while let Some(&(frame, end, bytes)) = self.in_flight.front() {
+            if frame > completed {
+                break;
+            }
+            self.in_flight.pop_front();
+            self.tail = end;
+            self.used -= bytes;
+        }
+    }
+}
+
+/// The copy regions produced by `UploadBatch::stage`, and the values
+/// attached to the writes they cover
+pub struct StagedCopy<S> {
+    pub regions: Vec<vk::BufferCopy>,
+    pub signals: Vec<S>,
+    pub bytes: usize,
+}
+
+/// Pending writes to a buffer, each with zero or more attached
+/// values, e.g. callbacks to run once the copy has been recorded.
+/// Writes must not partially overlap; a write to the same offset as
+/// a pending one replaces its data.
+pub struct UploadBatch<S> {
+    writes: BTreeMap<usize, (Vec<u8>, Vec<S>)>,
+    bytes: usize,
+}
+
+impl<S> Default for UploadBatch<S> {
+    fn default() -> Self {
+        Self {
+            writes: BTreeMap::default(),
+            bytes: 0,
+        }
+    }
+}
+
+impl<S> UploadBatch<S> {
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// The total size of the pending writes
+    pub fn pending_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Drops every pending write, e.g. when the destination buffer has
+    /// been reallocated
+    pub fn clear(&mut self) {
+        self.writes.clear();
+        self.bytes = 0;
+    }
+
+    pub fn write(
+        &mut self,
+        dst_offset: usize,
+        data: Vec<u8>,
+        signal: Option<S>,
+    ) {
+        debug_assert!(
+            self.writes
+                .range(..dst_offset)
+                .next_back()
+                .map(|(off, (prev, _))| off + prev.len() <= dst_offset)
+                .unwrap_or(true),
+            "overlapping upload at offset {}",
+            dst_offset
+        );
+
+        self.bytes += data.len();
+
+        let (old_data, signals) = self
+            .writes
+            .entry(dst_offset)
+            .or_insert_with(|| (Vec::new(), Vec::new()));
+
+        self.bytes -= old_data.len();
+        *old_data = data;
+        signals.extend(signal);
+    }
+
+    /// Copies as many pending writes as fit in the free part of the
+    /// ring into `staging`, in order of destination offset, and
+    /// returns the copy regions for them. Writes that are adjacent in
+    /// the destination buffer are adjacent in the staging buffer too,
+    /// unless the ring wraps around between them, and share a region.
+    ///
+    /// Writes that don't fit stay pending.
+    pub fn stage(
+        &mut self,
+        ring: &mut StagingRing,
+        staging: &mut [u8],
+    ) -> StagedCopy<S> {
+        debug_assert_eq!(staging.len(), ring.capacity());
+
+        let mut staged = StagedCopy {
+            regions: Vec::new(),
+            signals: Vec::new(),
+            bytes: 0,
+        };
+
+        while let Some(entry) = self.writes.first_entry() {
+            let dst_offset = *entry.key();
+            let len = entry.get().0.len();
+
+            let src_offset = match ring.alloc(len) {
+                Some(offset) => offset,
+                None => break,
+            };
+
+            let (data, signals) = entry.remove();
+            staging[src_offset..src_offset + len].copy_from_slice(&data);
+
+            let (src, dst) = (src_offset as u64, dst_offset as u64);
+
+            match staged.regions.last_mut() {
+                Some(last)
+                    if last.src_offset + last.size == src
+                        && last.dst_offset + last.size == dst =>
+                {
+                    last.size += len as u64;
+                }
+                _ => staged.regions.push(vk::BufferCopy {
+                    src_offset: src,
+                    dst_offset: dst,
+                    size: len as u64,
+                }),
+            }
+
+            staged.signals.extend(signals);
+            staged.bytes += len;
+            self.bytes -= len;
+        }
+
+        staged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_staging_ring() {
+        let mut ring = StagingRing::new(100);
+
+        assert_eq!(ring.alloc(40), Some(0));
+        assert_eq!(ring.alloc(40), Some(40));
+        ring.end_frame(0);
+
+        assert_eq!(ring.alloc(30), None);
+        assert_eq!(ring.alloc(10), Some(80));
+        ring.end_frame(1);
+        assert_eq!(ring.used(), 90);
+        assert_eq!(ring.frames_in_flight(), 2);
+
+        // once frame 0 is done, an allocation that doesn't fit at the
+        // end wraps around, skipping the last 10 bytes
+        ring.reclaim(0);
+        assert_eq!(ring.used(), 10);
+        assert_eq!(ring.alloc(30), Some(0));
+        assert_eq!(ring.alloc(60), None);
+        assert_eq!(ring.alloc(50), Some(30));
+        ring.end_frame(2);
+        assert_eq!(ring.used(), 100);
+
+        // the skipped bytes are freed along with the frame that
+        // skipped them
+        ring.reclaim(1);
+        assert_eq!(ring.used(), 90);
+        assert_eq!(ring.alloc(10), Some(80));
+        ring.end_frame(3);
+
+        ring.reclaim(2);
+        assert_eq!(ring.used(), 10);
+        assert_eq!(ring.frames_in_flight(), 1);
+
+        ring.reclaim(3);
+        assert_eq!(ring.used(), 0);
+        assert_eq!(ring.frames_in_flight(), 0);
+        assert_eq!(ring.alloc(100), Some(0));
+    }
+
+    #[test]
+    fn test_upload_batch() {
+        let mut ring = StagingRing::new(64);
+        let mut staging = vec![0u8; 64];
+        let mut batch = UploadBatch::default();
+
+        let block = |v: u8| vec![v; 16];
+
+        // blocks 3, 1, 2 and 5 of the destination, with block 2
+        // written twice
+        batch.write(48, block(3), Some(3));
+        batch.write(16, block(1), Some(1));
+        batch.write(32, block(9), Some(9));
+        batch.write(80, block(5), None);
+        batch.write(32, block(2), Some(2));
+        assert_eq!(batch.len(), 4);
+        assert_eq!(batch.pending_bytes(), 64);
+
+        // blocks 1 to 3 share a region
+        let staged = batch.stage(&mut ring, &mut staging);
+        ring.end_frame(0);
+
+        let regions = staged
+            .regions
+            .iter()
+            .map(|r| (r.src_offset, r.dst_offset, r.size))
+            .collect::<Vec<_>>();
+        assert_eq!(regions, vec![(0, 16, 48), (48, 80, 16)]);
+        assert_eq!(staged.signals, vec![1, 9, 2, 3]);
+        assert_eq!(staged.bytes, 64);
+        assert!(batch.is_empty());
+
+        assert_eq!(&staging[..16], &block(1)[..]);
+        assert_eq!(&staging[16..32], &block(2)[..]);
+        assert_eq!(&staging[48..], &block(5)[..]);
+
+        // the ring is full until frame 0 is done, so nothing is
+        // staged and the writes wait for the next frame
+        batch.write(0, block(0), Some(0));
+        batch.write(16, block(1), Some(1));
+        let staged = batch.stage(&mut ring, &mut staging);
+        assert!(staged.regions.is_empty());
+        assert_eq!(batch.len(), 2);
+
+        ring.reclaim(0);
+        let staged = batch.stage(&mut ring, &mut staging);
+        assert_eq!(staged.regions.len(), 1);
+        assert_eq!(staged.regions[0].size, 32);
+        assert_eq!(staged.signals, vec![0, 1]);
+        assert_eq!(batch.pending_bytes(), 0);
+    }
+}
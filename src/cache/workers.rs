@@ -0,0 +1,562 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::{CacheError, DataMsg, UpdateReqMsg};
+
+/// The order in which update requests are processed; within a
+/// priority, requests are processed in the order they were made
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Data that may be needed soon, e.g. for the keys just outside
+    /// the view
+    Prefetch,
+    #[default]
+    Normal,
+    /// Data that's needed for the current frame
+    Visible,
+}
+
+/// A flag shared between an update request and whoever made it.
+/// Requests that are cancelled before a worker picks them up are
+/// skipped. Workers cancel the requests whose payload function
+/// fails, so a request that's cancelled won't send any data.
+#[derive(Debug, Default, Clone)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub pending: usize,
+    /// Requests skipped because they were cancelled
+    pub cancelled: u64,
+    /// Requests refused, or displaced by higher priority requests,
+    /// because the queue was full
+    pub rejected: u64,
+    /// Requests whose payload function returned an error
+    pub failed: u64,
+}
+
+struct QueueState<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    // the first entry is the oldest request of the highest priority
+    requests: BTreeMap<(Reverse<Priority>, u64), UpdateReqMsg<K>>,
+    next_seq: u64,
+    capacity: usize,
+    closed: bool,
+    stats: QueueStats,
+}
+
+/// A bounded priority queue of update requests, shared between a
+/// `GpuBufferCache` and its workers
+pub struct RequestQueue<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    state: Mutex<QueueState<K>>,
+    available: Condvar,
+}
+
+impl<K> RequestQueue<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                requests: BTreeMap::default(),
+                next_seq: 0,
+                capacity,
+                closed: false,
+                stats: QueueStats::default(),
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().requests.is_empty()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        self.state.lock().capacity = capacity;
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock();
+        QueueStats {
+            pending: state.requests.len(),
+            ..state.stats
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.state.lock().stats = QueueStats::default();
+    }
+
+    /// Queues a request. If the queue is full, the newest of the
+    /// lowest priority requests is cancelled to make room, if its
+    /// priority is lower than that of the new request; otherwise
+    /// the new request is cancelled and refused.
+    pub fn push(
+        &self,
+        msg: UpdateReqMsg<K>,
+    ) -> std::result::Result<(), CacheError> {
+        let mut state = self.state.lock();
+
+        if state.closed {
+            msg.cancel.cancel();
+            return Err(CacheError::WorkersStopped);
+        }
+
+        if state.requests.len() >= state.capacity {
+            let lowest = state.requests.keys().next_back().copied();
+
+            match lowest {
+                Some(key @ (Reverse(priority), _))
+                    if priority < msg.priority =>
+                {
+                    if let Some(old) = state.requests.remove(&key) {
+                        old.cancel.cancel();
+                    }
+                    state.stats.rejected += 1;
+                }
+                _ => {
+                    msg.cancel.cancel();
+                    state.stats.rejected += 1;
+                    return Err(CacheError::QueueFull);
+                }
+            }
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.requests.insert((Reverse(msg.priority), seq), msg);
+
+        drop(state);
+        self.available.notify_one();
+
+        Ok(())
+    }
+
+    /// Takes the highest priority request that hasn't been cancelled,
+    /// blocking until there is one. Returns `None` once the queue
+    /// has been closed.
+    pub fn pop(&self) -> Option<UpdateReqMsg<K>> {
+        let mut state = self.state.lock();
+
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            while let Some(key) = state.requests.keys().next().copied() {
+                let msg = state.requests.remove(&key)?;
+                if msg.cancel.is_cancelled() {
+                    state.stats.cancelled += 1;
+                } else {
+                    return Some(msg);
+                }
+            }
+
+            self.available.wait(&mut state);
+        }
+    }
+
+    /// Cancels every queued request and wakes up the workers, which
+    /// then stop; requests that are already being processed are
+    /// finished
+    pub fn close(&self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+
+        let requests = std::mem::take(&mut state.requests);
+        state.stats.cancelled += requests.len() as u64;
+        for msg in requests.values() {
+            msg.cancel.cancel();
+        }
+
+        drop(state);
+        self.available.notify_all();
+    }
+
+    pub(super) fn record_failure(&self) {
+        self.state.lock().stats.failed += 1;
+    }
+}
+
+/// The latest update request for each key of a `GpuBufferCache`,
+/// with the generation of the binding it was made for, so that it
+/// can be cancelled if the key is unbound. An entry is dropped when
+/// its data arrives, or by `retain` once the request is cancelled,
+/// which includes requests displaced from a full queue and those
+/// whose payload function failed.
+pub struct PendingRequests<K>
+where
+    K: std::hash::Hash + Eq,
+{
+    requests: HashMap<K, (u64, CancelToken)>,
+}
+
+impl<K> Default for PendingRequests<K>
+where
+    K: std::hash::Hash + Eq,
+{
+    fn default() -> Self {
+        Self {
+            requests: HashMap::default(),
+        }
+    }
+}
+
+impl<K> PendingRequests<K>
+where
+    K: std::hash::Hash + Eq,
+{
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.requests.contains_key(key)
+    }
+
+    /// Records a request, cancelling the earlier one for the key
+    pub fn insert(&mut self, key: K, generation: u64, token: CancelToken) {
+        if let Some((_, old)) = self.requests.insert(key, (generation, token)) {
+            old.cancel();
+        }
+    }
+
+    /// Drops the entry of a request whose data has arrived, unless
+    /// a newer request has been made for the key
+    pub fn complete(&mut self, key: &K, generation: u64) {
+        if matches!(
+            self.requests.get(key),
+            Some((current, _)) if *current == generation
+        ) {
+            self.requests.remove(key);
+        }
+    }
+
+    /// Cancels the requests for which `bound` returns false, given
+    /// the key and generation, and drops the cancelled requests
+    pub fn retain(&mut self, mut bound: impl FnMut(&K, u64) -> bool) {
+        self.requests.retain(|key, (generation, token)| {
+            if !bound(key, *generation) {
+                token.cancel();
+            }
+            !token.is_cancelled()
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.requests.clear();
+    }
+}
+
+/// Threads that take requests from a `RequestQueue` and send the
+/// resulting data to a `GpuBufferCache`. Dropping the pool closes
+/// the queue and waits for the threads to finish their current
+/// requests.
+pub struct WorkerPool<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    queue: Arc<RequestQueue<K>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<K> WorkerPool<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    pub fn spawn(
+        name: &str,
+        thread_count: usize,
+        queue: Arc<RequestQueue<K>>,
+        data_tx: crossbeam::channel::Sender<DataMsg<K>>,
+    ) -> anyhow::Result<Self> {
+        let mut pool = Self {
+            queue: queue.clone(),
+            threads: Vec::with_capacity(thread_count),
+        };
+
+        for i in 0..thread_count {
+            let queue = queue.clone();
+            let data_tx = data_tx.clone();
+
+            let thread = std::thread::Builder::new()
+                .name(format!("{} worker {}", name, i))
+                .spawn(move || worker_loop(&queue, &data_tx))?;
+
+            pool.threads.push(thread);
+        }
+
+        Ok(pool)
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Closes the queue and waits for the threads to stop
+    pub fn shutdown(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        self.queue.close();
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                log::error!("GPU cache worker panicked");
+            }
+        }
+    }
+}
+
+impl<K> Drop for WorkerPool<K>
+where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+fn worker_loop<K>(
+    queue: &RequestQueue<K>,
+    data_tx: &crossbeam::channel::Sender<DataMsg<K>>,
+) where
+    K: std::hash::Hash + Eq + Send + Sync + 'static,
+{
+    while let Some(msg) = queue.pop() {
+        match (msg.create_payload)(msg.key) {
+            Ok(data) => {
+                // the cache has been dropped
+                if data_tx.send(data).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                log::warn!("GPU cache worker error: {:?}", err);
+                msg.cancel.cancel();
+                queue.record_failure();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn request(
+        key: usize,
+        priority: Priority,
+    ) -> (UpdateReqMsg<usize>, CancelToken) {
+        let msg = UpdateReqMsg::new(key, 1, |k| Ok(vec![*k as u8]), || ())
+            .with_priority(priority);
+        let token = msg.cancel_token();
+        (msg, token)
+    }
+
+    #[test]
+    fn test_queue_order() -> anyhow::Result<()> {
+        let queue = RequestQueue::new(4);
+
+        queue.push(request(0, Priority::Prefetch).0)?;
+        queue.push(request(1, Priority::Normal).0)?;
+        queue.push(request(2, Priority::Visible).0)?;
+        queue.push(request(3, Priority::Normal).0)?;
+
+        // full; a prefetch request is refused, while a visible one
+        // displaces the pending prefetch request
+        let (msg, token) = request(4, Priority::Prefetch);
+        assert_eq!(queue.push(msg), Err(CacheError::QueueFull));
+        assert!(token.is_cancelled());
+        queue.push(request(5, Priority::Visible).0)?;
+        assert_eq!(queue.len(), 4);
+
+        // cancelled requests are skipped
+        let (msg, token) = request(6, Priority::Visible);
+        queue.set_capacity(5);
+        queue.push(msg)?;
+        token.cancel();
+
+        let order =
+            (0..4).map(|_| queue.pop().unwrap().key).collect::<Vec<_>>();
+        assert_eq!(order, vec![2, 5, 1, 3]);
+        assert!(queue.is_empty());
+
+        let stats = queue.stats();
+        assert_eq!(stats.rejected, 2);
+        assert_eq!(stats.cancelled, 1);
+
+        queue.close();
+        assert!(queue.pop().is_none());
+        assert_eq!(
+            queue.push(request(7, Priority::Visible).0),
+            Err(CacheError::WorkersStopped)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_worker_pool() -> anyhow::Result<()> {
+        let queue = Arc::new(RequestQueue::new(1024));
+        let (data_tx, data_rx) = crossbeam::channel::unbounded();
+
+        let pool = WorkerPool::spawn("test", 4, queue.clone(), data_tx)?;
+        assert_eq!(pool.thread_count(), 4);
+
+        let n = 200;
+        for key in 0..n {
+            let msg = UpdateReqMsg::new(
+                key,
+                1,
+                |&k: &usize| {
+                    if k % 50 == 0 {
+                        anyhow::bail!("no data for {}", k);
+                    }
+                    Ok(k.to_le_bytes().to_vec())
+                },
+                || (),
+            );
+            queue.push(msg)?;
+        }
+
+        let timeout = std::time::Duration::from_secs(10);
+
+        let mut received = 0;
+        while received < n - n / 50 {
+            let msg = data_rx.recv_timeout(timeout)?;
+            assert_eq!(msg.data, msg.key.to_le_bytes());
+            received += 1;
+        }
+
+        // the failing requests may still be running
+        let start = std::time::Instant::now();
+        while queue.stats().failed < (n / 50) as u64 {
+            assert!(start.elapsed() < timeout);
+            std::thread::yield_now();
+        }
+
+        pool.shutdown();
+        assert!(queue.is_closed());
+        assert!(data_rx.try_recv().is_err());
+
+        // requests made after shutdown are refused, not lost
+        let (msg, token) = request(0, Priority::Visible);
+        assert!(queue.push(msg).is_err());
+        assert!(token.is_cancelled());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_requests() -> anyhow::Result<()> {
+        let queue = Arc::new(RequestQueue::new(4));
+        let mut pending = PendingRequests::default();
+
+        // fills the queue before there are workers, so that the last
+        // request displaces a prefetch request
+        let mut push = |msg: UpdateReqMsg<usize>| -> anyhow::Result<()> {
+            let (key, generation) = (msg.key, msg.generation);
+            pending.insert(key, generation, msg.cancel_token());
+            queue.push(msg)?;
+            Ok(())
+        };
+
+        for key in 0..4 {
+            let msg = UpdateReqMsg::new(
+                key,
+                1,
+                |&k: &usize| {
+                    if k % 2 == 1 {
+                        anyhow::bail!("no data for {}", k);
+                    }
+                    Ok(vec![k as u8])
+                },
+                || (),
+            );
+            let priority = if key == 0 {
+                Priority::Prefetch
+            } else {
+                Priority::Normal
+            };
+            push(msg.with_priority(priority))?;
+        }
+        push(request(4, Priority::Visible).0)?;
+        assert_eq!(pending.len(), 5);
+
+        let (data_tx, data_rx) = crossbeam::channel::unbounded();
+        let pool = WorkerPool::spawn("test", 2, queue.clone(), data_tx)?;
+
+        let timeout = std::time::Duration::from_secs(10);
+        for _ in 0..2 {
+            let msg = data_rx.recv_timeout(timeout)?;
+            pending.complete(&msg.key, msg.generation);
+        }
+
+        let start = std::time::Instant::now();
+        while queue.stats().failed < 2 {
+            assert!(start.elapsed() < timeout);
+            std::thread::yield_now();
+        }
+        pool.shutdown();
+
+        // the displaced and the failed requests are left, until
+        // they're found to be cancelled
+        assert_eq!(pending.len(), 3);
+        pending.retain(|_, _| true);
+        assert!(pending.is_empty());
+
+        // a newer request for a key isn't dropped by the data of an
+        // older one, and unbound keys are cancelled
+        let (old, new) = (CancelToken::default(), CancelToken::default());
+        pending.insert(0, 1, old.clone());
+        pending.insert(0, 2, new.clone());
+        assert!(old.is_cancelled());
+        pending.complete(&0, 1);
+        assert!(pending.contains(&0));
+        pending.retain(|_, generation| generation != 2);
+        assert!(new.is_cancelled());
+        assert!(pending.is_empty());
+
+        Ok(())
+    }
+}
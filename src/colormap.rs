@@ -0,0 +1,164 @@
+use palette::{Gradient, LinSrgb, Srgb};
+
+/// Maps scalar values to RGBA colors, by interpolating between a
+/// list of sRGB control colors in linear space
+pub struct Colormap {
+    gradient: Gradient<LinSrgb>,
+}
+
+impl Colormap {
+    pub fn new(colors: impl IntoIterator<Item = [u8; 3]>) -> Self {
+        let gradient = Gradient::new(colors.into_iter().map(|[r, g, b]| {
+            Srgb::new(r, g, b).into_format::<f32>().into_linear()
+        }));
+        Self { gradient }
+    }
+
+    /// Perceptually uniform, for values without a natural midpoint
+    pub fn viridis() -> Self {
+        Self::new([
+            [0x44, 0x01, 0x54],
+            [0x48, 0x28, 0x78],
+            [0x3e, 0x4a, 0x89],
+            [0x31, 0x68, 0x8e],
+            [0x26, 0x82, 0x8e],
+            [0x1f, 0x9e, 0x89],
+            [0x35, 0xb7, 0x79],
+            [0x6d, 0xcd, 0x59],
+            [0xb4, 0xde, 0x2c],
+            [0xfd, 0xe7, 0x25],
+        ])
+    }
+
+    /// Blue to white to red, for signed values such as curvature
+    pub fn diverging() -> Self {
+        Self::new([
+            [0x3b, 0x4c, 0xc0],
+            [0x8d, 0xb0, 0xfe],
+            [0xdd, 0xdd, 0xdd],
+            [0xf4, 0x9a, 0x7b],
+            [0xb4, 0x04, 0x26],
+        ])
+    }
+
+    /// `t` is clamped to `[0, 1]`
+    pub fn color(&self, t: f32) -> [f32; 4] {
+        let t = if t.is_nan() { 0.5 } else { t.clamp(0.0, 1.0) };
+        let rgb = Srgb::from_linear(self.gradient.get(t));
+        [rgb.red, rgb.green, rgb.blue, 1.0]
+    }
+
+    /// Maps each value linearly from `range` to the colormap. If
+    /// `range` is `None`, the minimum and maximum of the values are
+    /// used.
+    pub fn map_values(
+        &self,
+        values: &[f32],
+        range: Option<[f32; 2]>,
+    ) -> Vec<[f32; 4]> {
+        let [min, max] = range.unwrap_or_else(|| value_range(values));
+        let width = max - min;
+
+        values
+            .iter()
+            .map(|&v| {
+                let t = if width > 0.0 { (v - min) / width } else { 0.5 };
+                self.color(t)
+            })
+            .collect()
+    }
+}
+
+/// The minimum and maximum of the finite values
+pub fn value_range(values: &[f32]) -> [f32; 2] {
+    let mut range = [0.0f32; 2];
+    let mut finite = values.iter().filter(|v| v.is_finite());
+
+    if let Some(&first) = finite.next() {
+        range = [first, first];
+        for &v in finite {
+            range[0] = range[0].min(v);
+            range[1] = range[1].max(v);
+        }
+    }
+
+    range
+}
+
+/// A range centered on zero that contains all finite values, so
+/// that zero maps to the middle of a diverging colormap
+pub fn symmetric_range(values: &[f32]) -> [f32; 2] {
+    let [min, max] = value_range(values);
+    let m = min.abs().max(max.abs());
+    [-m, m]
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn assert_close(a: [f32; 4], b: [f32; 4]) {
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-3, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn srgb([r, g, b]: [u8; 3]) -> [f32; 4] {
+        [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+    }
+
+    #[test]
+    fn test_color() {
+        let map = Colormap::diverging();
+
+        // the ends and the middle are control colors
+        assert_close(map.color(0.0), srgb([0x3b, 0x4c, 0xc0]));
+        assert_close(map.color(0.5), srgb([0xdd, 0xdd, 0xdd]));
+        assert_close(map.color(1.0), srgb([0xb4, 0x04, 0x26]));
+
+        assert_eq!(map.color(-1.0), map.color(0.0));
+        assert_eq!(map.color(2.0), map.color(1.0));
+        assert_eq!(map.color(f32::NAN), map.color(0.5));
+    }
+
+    #[test]
+    fn test_map_values() {
+        let map = Colormap::viridis();
+
+        let colors = map.map_values(&[1.0, 3.0, 2.0, f32::NAN], None);
+        assert_eq!(colors[0], map.color(0.0));
+        assert_eq!(colors[1], map.color(1.0));
+        assert_eq!(colors[2], map.color(0.5));
+        assert_eq!(colors[3], map.color(0.5));
+
+        // values outside the given range are clamped
+        let colors = map.map_values(&[-5.0, 0.0, 5.0], Some([-1.0, 1.0]));
+        assert_eq!(colors[0], map.color(0.0));
+        assert_eq!(colors[1], map.color(0.5));
+        assert_eq!(colors[2], map.color(1.0));
+
+        // a constant input has no width to scale by
+        let colors = map.map_values(&[4.0, 4.0], None);
+        assert!(colors.iter().all(|&c| c == map.color(0.5)));
+        assert!(map.map_values(&[], None).is_empty());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let values = [-1.0, 3.0, f32::NAN, f32::NEG_INFINITY, 0.5];
+        assert_eq!(value_range(&values), [-1.0, 3.0]);
+        assert_eq!(symmetric_range(&values), [-3.0, 3.0]);
+        assert_eq!(symmetric_range(&[-4.0, -2.0]), [-4.0, 4.0]);
+
+        // without finite values, the range is empty, and everything
+        // maps to the middle
+        let values = [f32::NAN, f32::INFINITY];
+        assert_eq!(value_range(&values), [0.0, 0.0]);
+        assert_eq!(symmetric_range(&values), [0.0, 0.0]);
+
+        let map = Colormap::diverging();
+        let colors = map.map_values(&values, Some(symmetric_range(&values)));
+        assert!(colors.iter().all(|&c| c == map.color(0.5)));
+    }
+}
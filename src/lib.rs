@@ -4,7 +4,10 @@ pub mod curve;
 pub mod vector_field;
 
 pub mod cache;
+pub mod colormap;
 pub mod mesh;
+pub mod sampling;
+pub mod vertex;
 
 // pub mod label_space;
 pub mod sublayers;
@@ -1,4 +1,4 @@
-use nalgebra_glm::{vec3, Vec2};
+use nalgebra_glm::{vec2, vec3, Vec2, Vec3};
 use raving::compositor::label_space::LabelSpace;
 use raving::compositor::{Compositor, SublayerAllocMsg};
 use raving::script::console::frame::Resolvable;
@@ -6,6 +6,13 @@ use raving::vk::{
     BufferIx, BufferRes, DescSetIx, FenceIx, ImageIx, ImageViewIx, SemaphoreIx,
     VkEngine, WindowResources,
 };
+use raving_viz::mesh::bounds::{chunk_bounds, visible_chunks, Frustum};
+use raving_viz::mesh::camera::{
+    CameraController, CameraInput, OrbitController, Projection,
+};
+use raving_viz::mesh::camera_path::{CameraPath, PathPlayer};
+use raving_viz::mesh::{Curvature, Smoothing};
+use raving_viz::vertex::{LineVertex, MeshVertex};
 
 use ash::vk;
 
@@ -35,6 +42,27 @@ struct Args {
     // /// image path to display,
     // #[argh(positional)]
     // pub img_path: PathBuf,
+    /// mesh file (OBJ, PLY or STL) to display instead of the
+    /// sampled disc
+    #[argh(option)]
+    pub mesh: Option<PathBuf>,
+
+    /// use an orthographic projection
+    #[argh(switch)]
+    pub orthographic: bool,
+
+    /// smooth the sampled disc with a few steps of Taubin smoothing
+    #[argh(switch)]
+    pub smooth: bool,
+
+    /// color the surface by its curvature: mean, gaussian, max or min
+    #[argh(option)]
+    pub curvature: Option<Curvature>,
+
+    /// camera path file to play back in a loop, instead of
+    /// controlling the camera with the mouse
+    #[argh(option)]
+    pub camera_path: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -77,6 +105,26 @@ fn main() -> Result<()> {
 
     let mut camera = raving_viz::mesh::Camera::new(&mut engine)?;
 
+    let mut orbit =
+        OrbitController::from_eye(vec3(10.0, 3.0, 0.0), vec3(0.0, 0.5, 0.0));
+    camera.apply(&orbit);
+
+    // the orthographic view is sized to match what the perspective
+    // view shows at the orbit target
+    let perspective = Projection::default();
+    if args.orthographic {
+        let height = perspective.view_height(orbit.distance);
+        camera.projection = Projection::orthographic(height, 0.0, 1000.0);
+    }
+
+    let mut path_player = if let Some(path) = &args.camera_path {
+        let mut player = PathPlayer::new(CameraPath::load(path)?);
+        player.looping = true;
+        Some(player)
+    } else {
+        None
+    };
+
     camera.write_uniform(&mut engine.resources, [width as f32, height as f32]);
 
     let (clear_queue_tx, clear_queue_rx) =
@@ -171,7 +219,7 @@ fn main() -> Result<()> {
 
     raving_viz::sublayers::add_sublayer_defs(&mut engine, &mut compositor)?;
 
-    {
+    let (bvh, indices, triangle_bounds, mut index_buffer) = {
         compositor.new_layer("main_layer", 0, true);
 
         compositor.sublayer_alloc_tx.send(SublayerAllocMsg::new(
@@ -198,12 +246,46 @@ fn main() -> Result<()> {
         compositor.allocate_sublayers(&mut engine)?;
 
         let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        let bvh = if let Some(path) = &args.mesh {
+            raving_viz::mesh::mesh_file(
+                &mut vertices,
+                &mut indices,
+                path,
+                args.curvature,
+            )?
+        } else {
+            let smoothing = args.smooth.then_some(Smoothing::Taubin {
+                iterations: 5,
+                lambda: 0.5,
+                mu: -0.53,
+                fix_boundary: true,
+            });
+
+            raving_viz::mesh::sampled_disc(
+                &mut vertices,
+                &mut indices,
+                &raving_viz::sampling::SurfaceSampling {
+                    smoothing,
+                    ..Default::default()
+                },
+                args.curvature,
+            )?
+        };
 
-        let indices = raving_viz::mesh::sampled_disc(
+        // the bounds of chunks of the index list, so that the chunks
+        // of triangles outside the view can be left out
+        let positions = indices
+            .iter()
+            .map(|&i| Vec3::from(vertices[i as usize].pos))
+            .collect::<Vec<_>>();
+        let triangle_bounds = chunk_bounds(&positions, TRIANGLE_CHUNK_SIZE);
+
+        let index_buffer = raving_viz::mesh::index_buffer(
             &mut engine,
             &clear_queue_tx,
-            &mut vertices,
-            1000,
+            indices.iter().copied(),
         )?;
         /*
         let indices = raving_viz::mesh::index_buffer(
@@ -222,13 +304,11 @@ fn main() -> Result<()> {
 
         compositor.with_layer("main_layer", |layer| {
             if let Some(sublayer) = layer.get_sublayer_mut("triangles") {
-                // if let Some(sublayer) = layer.get_sublayer_mut("lines") {
-
-                // raving_viz::mesh::cube(&mut vertices);
-
                 sublayer.draw_data_mut().try_for_each(|data| {
-                    data.set_indices(Some(indices));
-                    data.update_vertices_array(vertices.iter().copied())
+                    data.set_indices(Some(index_buffer));
+                    data.update_vertices_array(
+                        vertices.iter().map(MeshVertex::to_bytes),
+                    )
                 })?;
             }
 
@@ -260,7 +340,16 @@ fn main() -> Result<()> {
             Ok(())
         })?;
         */
-    }
+
+        (bvh, indices, triangle_bounds, index_buffer)
+    };
+
+    // the view-projection of the last culling, so that the triangles
+    // are only culled again when the camera has moved, and the chunks
+    // in the index buffer, all of them at first, so that it's only
+    // replaced when a chunk enters or leaves the view
+    let mut culled_view_proj = None;
+    let mut shown_chunks = (0..triangle_bounds.len()).collect::<Vec<_>>();
 
     // let mut main_layer = compositor.new_layer(name, depth, enabled)
 
@@ -275,7 +364,7 @@ fn main() -> Result<()> {
         })?;
     }
 
-    let mut vertices: Vec<[u8; 40]> = Vec::new();
+    let mut vertices: Vec<LineVertex> = Vec::new();
 
     {
         let x_dist = rand_distr::Normal::from_mean_cv(0.5, 0.3)?;
@@ -302,6 +391,16 @@ fn main() -> Result<()> {
 
     let mut frame_i = 0;
 
+    // accumulated between frames, and applied to the camera
+    let mut camera_input = CameraInput::default();
+    let mut rotating = false;
+    let mut panning = false;
+
+    // in window pixels, and where the left button was last pressed,
+    // to tell clicks from drags
+    let mut cursor = Vec2::zeros();
+    let mut press_pos = Vec2::zeros();
+
     event_loop.run(move |event, _, control_flow| {
         *control_flow = winit::event_loop::ControlFlow::Poll;
 
@@ -390,7 +489,7 @@ fn main() -> Result<()> {
                         if let Some(sublayer) = layer.get_sublayer_mut("lines")
                         {
                             sublayer.update_vertices_array(
-                                vertices.iter().copied(),
+                                vertices.iter().map(LineVertex::to_bytes),
                             )?;
                         }
 
@@ -399,29 +498,56 @@ fn main() -> Result<()> {
                     .unwrap();
                 */
 
-                {
-                    let t = start.elapsed().as_secs_f32();
+                if let Some(player) = &mut path_player {
+                    player.update(&camera_input, dt);
+                    if let Some(pose) = player.pose() {
+                        pose.apply(&mut camera);
+                    }
+                } else {
+                    orbit.update(&camera_input, dt);
+                    camera.apply(&orbit);
+
+                    if let Projection::Orthographic { height, .. } =
+                        &mut camera.projection
+                    {
+                        *height = perspective.view_height(orbit.distance);
+                    }
+                }
 
-                    let r = 10.0;
+                camera_input = CameraInput {
+                    cursor: camera_input.cursor,
+                    ..CameraInput::default()
+                };
 
-                    let x = t.cos() * r;
-                    let y = t.sin() * r;
+                camera.write_uniform(
+                    &mut engine.resources,
+                    [width as f32, height as f32],
+                );
 
-                    camera.write_uniform_fixed(
-                        &mut engine.resources,
-                        // vec3(10.0, -4.0, 10.0),
-                        vec3(x, 3.0, y),
-                        vec3(0.0, 0.5, 0.0),
-                        // vec3(0.5, 0.5, 0.5),
-                        [width as f32, height as f32],
-                    );
+                let view_proj =
+                    camera.view_projection([width as f32, height as f32]);
+                if culled_view_proj != Some(view_proj) {
+                    culled_view_proj = Some(view_proj);
+
+                    let frustum = Frustum::from_matrix(&view_proj);
+                    let visible = visible_chunks(&frustum, &triangle_bounds)
+                        .collect::<Vec<_>>();
+
+                    if visible != shown_chunks {
+                        if let Err(e) = upload_visible_triangles(
+                            &mut engine,
+                            &mut compositor,
+                            &clear_queue_tx,
+                            &mut index_buffer,
+                            &indices,
+                            &visible,
+                        ) {
+                            log::error!("Compositor error: {:?}", e);
+                        }
+                        shown_chunks = visible;
+                    }
                 }
 
-                // camera.write_uniform(
-                //     &mut engine.resources,
-                //     [width as f32, height as f32],
-                // );
-
                 if let Ok((img, view)) = engine.draw_compositor(
                     &compositor,
                     [0.3, 0.3, 0.3],
@@ -449,8 +575,13 @@ fn main() -> Result<()> {
             Event::DeviceEvent { event, .. } => {
                 if let winit::event::DeviceEvent::MouseMotion { delta } = event
                 {
-                    // camera.rotate_hor(delta.0 as f32 / 500.0);
-                    // camera.rotate_ver(-delta.1 as f32 / 1000.0);
+                    let delta = vec2(delta.0 as f32, delta.1 as f32);
+                    if rotating {
+                        camera_input.rotate += delta;
+                    }
+                    if panning {
+                        camera_input.pan += delta;
+                    }
                 }
             }
             Event::WindowEvent { event, .. } => {
@@ -468,15 +599,52 @@ fn main() -> Result<()> {
                     //     }
                     // }
                     WindowEvent::MouseInput { button, state, .. } => {
-                        if button == winit::event::MouseButton::Left
-                            && state == winit::event::ElementState::Pressed
-                        // && !mouse_clicked
-                        {
-                            log::error!("mouse clicked!");
-                            // mouse_clicked = true;
+                        use winit::event::{ElementState, MouseButton};
+
+                        let pressed = state == ElementState::Pressed;
+
+                        if button == MouseButton::Left {
+                            if pressed {
+                                press_pos = cursor;
+                            } else if (cursor - press_pos).norm() < 3.0 {
+                                let [width, height] = swapchain_dims.load();
+                                let dims = [width as f32, height as f32];
+                                let ray = camera.ray(cursor, dims);
+
+                                if let Some(hit) = bvh.pick(&ray) {
+                                    log::info!(
+                                        "picked face {} at {:?}, vertex {}",
+                                        hit.face.0,
+                                        hit.point.as_slice(),
+                                        hit.nearest_vertex().0,
+                                    );
+                                }
+                            }
                         }
+
+                        match button {
+                            MouseButton::Left => rotating = pressed,
+                            MouseButton::Right | MouseButton::Middle => {
+                                panning = pressed
+                            }
+                            _ => (),
+                        }
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        use winit::event::MouseScrollDelta;
+
+                        camera_input.zoom += match delta {
+                            MouseScrollDelta::LineDelta(_, y) => y,
+                            MouseScrollDelta::PixelDelta(pos) => {
+                                pos.y as f32 / 20.0
+                            }
+                        };
                     }
                     WindowEvent::CursorMoved { position, .. } => {
+                        let [width, height] = swapchain_dims.load();
+                        cursor = vec2(position.x as f32, position.y as f32);
+                        camera_input.cursor =
+                            cursor - vec2(width as f32, height as f32) / 2.0;
                         /*
                         let [width, height] = swapchain_dims.load();
                         let x = position.x as f32 / width as f32;
@@ -571,6 +739,51 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Indices per chunk of triangles that is culled as a whole
+const TRIANGLE_CHUNK_SIZE: usize = 3 * 512;
+
+// replaces the index buffer of the "triangles" sublayer with the
+// `visible` chunks of `indices`; the vertices stay as they are. With
+// no chunks visible, the old buffer is kept but nothing is drawn
+fn upload_visible_triangles(
+    engine: &mut VkEngine,
+    compositor: &mut Compositor,
+    clear_queue: &crossbeam::channel::Sender<
+        Box<dyn std::any::Any + Send + Sync>,
+    >,
+    index_buffer: &mut (BufferIx, usize),
+    indices: &[u32],
+    visible: &[usize],
+) -> Result<()> {
+    if visible.is_empty() {
+        index_buffer.1 = 0;
+    } else {
+        let visible_indices = visible.iter().flat_map(|&ix| {
+            let start = ix * TRIANGLE_CHUNK_SIZE;
+            let end = (start + TRIANGLE_CHUNK_SIZE).min(indices.len());
+            indices[start..end].iter().copied()
+        });
+
+        let new_buffer = raving_viz::mesh::index_buffer(
+            engine,
+            clear_queue,
+            visible_indices,
+        )?;
+        let (old_buffer, _) = std::mem::replace(index_buffer, new_buffer);
+        clear_queue.send(Box::new(old_buffer))?;
+    }
+
+    compositor.with_layer("main_layer", |layer| {
+        if let Some(sublayer) = layer.get_sublayer_mut("triangles") {
+            sublayer
+                .draw_data_mut()
+                .for_each(|data| data.set_indices(Some(*index_buffer)));
+        }
+
+        Ok(())
+    })
+}
+
 fn empty_clear_queue(
     engine: &mut VkEngine,
     clear_queue_rx: &crossbeam::channel::Receiver<
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 use ash::vk;
 
 use nalgebra::Point3;
-use nalgebra_glm::{mat4, vec2, vec3, vec4, Mat4, Vec2, Vec3};
+use nalgebra_glm::{mat4, vec3, vec4, Mat4, Vec3};
 use raving::vk::{
     descriptor::DescriptorLayoutInfo, BufferIx, DescSetIx, GpuResources,
     VkEngine,
@@ -11,6 +11,26 @@ use raving::vk::{
 use rspirv_reflect::DescriptorInfo;
 use rustc_hash::FxHashMap;
 
+use crate::colormap::Colormap;
+use crate::sampling::SurfaceSampling;
+use crate::vertex::MeshVertex;
+
+pub use differential::Curvature;
+pub use smoothing::Smoothing;
+
+pub mod bounds;
+pub mod camera;
+pub mod camera_path;
+mod differential;
+pub mod io;
+mod ops;
+pub mod picking;
+pub mod primitives;
+mod smoothing;
+mod subdivision;
+#[cfg(test)]
+pub(crate) mod test_meshes;
+
 pub struct Camera {
     eye: Vec3,
 
@@ -18,52 +38,13 @@ pub struct Camera {
     v: Vec3, // points up
     n: Vec3, // points back
 
+    pub projection: camera::Projection,
+
     pub buffer: BufferIx,
     pub desc_set: DescSetIx,
 }
 
 impl Camera {
-    /*
-    pub fn move_fwd(&mut self, dt: f32, dist: f32) {
-        dbg!(self.n);
-        dbg!(self.eye);
-
-        // let fwd = nalgebra_glm::cross(&self.u, &self.v);
-        // let fwd = nalgebra_glm::cross(&self.n, &self.v);
-        let fwd = nalgebra_glm::cross(&self.u, &self.n);
-
-        self.eye += fwd * dist * dt;
-
-        // self.eye += self.n * dist * dt;
-        // nalgebra_glm::translate(m, v)
-        // self.eye.append_translation(shift)
-    }
-
-    pub fn move_right(&mut self, dt: f32, dist: f32) {
-        self.eye += self.u * dist * dt;
-    }
-
-    pub fn rotate_hor(&mut self, angle: f32) {
-        // let u = nalgebra_glm::rotate_vec3(&self.u, angle, &self.v);
-        // let n = nalgebra_glm::rotate_vec3(&self.n, angle, &self.v);
-        let v = nalgebra_glm::cross(&self.u, &self.n);
-        let u = nalgebra_glm::rotate_vec3(&self.u, angle, &v);
-        let n = nalgebra_glm::rotate_vec3(&self.n, angle, &v);
-        self.u = u;
-        self.n = n;
-    }
-
-    pub fn rotate_ver(&mut self, angle: f32) {
-        // let v = nalgebra_glm::rotate_vec3(&self.v, angle, &self.u);
-        // let n = nalgebra_glm::rotate_vec3(&self.n, angle, &self.u);
-        let u = nalgebra_glm::cross(&self.v, &self.n);
-        let v = nalgebra_glm::rotate_vec3(&self.v, angle, &u);
-        let n = nalgebra_glm::rotate_vec3(&self.n, angle, &u);
-        self.v = v;
-        self.n = n;
-    }
-    */
-
     pub fn new(engine: &mut VkEngine) -> anyhow::Result<Self> {
         let eye = vec3(0f32, 0.7, -10.0);
         let u = vec3(1f32, 0.0, 0.0);
@@ -78,8 +59,8 @@ impl Camera {
                     ctx,
                     alloc,
                     gpu_allocator::MemoryLocation::CpuToGpu,
-                    4,  // f32
-                    16, // 4x4 matrix
+                    4,      // f32
+                    2 * 16, // view and projection 4x4 matrices
                     usage,
                     Some("camera uniform buffer"),
                 )?;
@@ -99,6 +80,8 @@ impl Camera {
             v,
             n,
 
+            projection: camera::Projection::default(),
+
             buffer,
             desc_set,
         };
@@ -113,52 +96,25 @@ impl Camera {
         tgt: Vec3,
         dims: [f32; 2],
     ) {
-        let mat = nalgebra_glm::look_at_rh(&eye, &tgt, &vec3(0f32, 1.0, 0.0));
-
-        // let mat = mat.try_inverse().unwrap();
-
-        let [width, height] = dims;
-        let proj =
-            nalgebra_glm::perspective_fov(1.4f32, width, height, 1.0, 1000.0);
-
-        let mat = proj * mat;
-
-        let buf = &mut res[self.buffer];
-        if let Some(slice) = buf.mapped_slice_mut() {
-            slice.clone_from_slice(bytemuck::cast_slice(mat.as_slice()));
-        }
+        let view = nalgebra_glm::look_at_rh(&eye, &tgt, &vec3(0f32, 1.0, 0.0));
+        let proj = self.projection_matrix(dims);
+        self.write_matrices(res, &view, &proj);
     }
 
+    /// Writes the view and projection matrices, in that order, to the
+    /// uniform buffer
     pub fn write_uniform(&self, res: &mut GpuResources, dims: [f32; 2]) {
-        let [width, height] = dims;
-        let buf = &mut res[self.buffer];
-
-        #[rustfmt::skip]
-        let translate = mat4(
-                 1.0, 0.0, 0.0, -self.eye.x,
-                 0.0, 1.0, 0.0, -self.eye.y,
-                 0.0, 0.0, 1.0, -self.eye.z,
-                 0.0, 0.0, 0.0,         1.0);
-
-        let u = self.u;
-        let v = self.v;
-        let n = self.n;
-
-        #[rustfmt::skip]
-        let rot_align = mat4(
-            u.x, u.y, u.z, 0.0,
-            v.x, v.y, v.z, 0.0,
-            n.x, n.y, n.z, 0.0,
-            0.0, 0.0, 0.0, 1.0);
-
-        let proj =
-            nalgebra_glm::perspective_fov(1.4, width, height, 1.0, 1000.0);
-
-        // let mat = proj * rot_align * translate;
-        let mat = proj * translate * rot_align;
+        let view = self.view_matrix();
+        let proj = self.projection_matrix(dims);
+        self.write_matrices(res, &view, &proj);
+    }
 
+    fn write_matrices(&self, res: &mut GpuResources, view: &Mat4, proj: &Mat4) {
+        let buf = &mut res[self.buffer];
         if let Some(slice) = buf.mapped_slice_mut() {
-            slice.clone_from_slice(bytemuck::cast_slice(mat.as_slice()));
+            let (view_bytes, proj_bytes) = slice.split_at_mut(64);
+            view_bytes.clone_from_slice(bytemuck::cast_slice(view.as_slice()));
+            proj_bytes.clone_from_slice(bytemuck::cast_slice(proj.as_slice()));
         }
     }
 }
@@ -170,115 +126,53 @@ pub struct Uniform {
 }
 */
 
+/// Samples a surface as described by `sampling`, filling `buf` and
+/// `indices` with vertex and index data for the `tri-3d` sublayer,
+/// and returning a BVH for picking. With `curvature`, the vertices
+/// are colored by that curvature rather than by `sampling.color`.
 pub fn sampled_disc(
-    engine: &mut VkEngine,
-    clear_queue: &crossbeam::channel::Sender<
-        Box<dyn std::any::Any + Send + Sync>,
-    >,
-    buf: &mut Vec<[u8; 40]>,
-    count: usize,
-) -> anyhow::Result<(BufferIx, usize)> {
-    use rand::prelude::*;
-    use rand_distr::{Normal, StandardNormal};
-    // use rand_distr::
-
-    buf.clear();
-
-    let mut colors = Vec::new();
-
-    let rgba = |r, g, b| {
-        let r = r as f32 / 255.0;
-        let g = g as f32 / 255.0;
-        let b = b as f32 / 255.0;
-        [r, g, b, 1.0]
-    };
-
-    colors.push(rgba(0xff, 0xff, 0xff));
-    colors.push(rgba(0x1f, 0x77, 0xb4));
-    colors.push(rgba(0xff, 0x7f, 0x0e));
-    colors.push(rgba(0x2c, 0xa0, 0x2c));
-    colors.push(rgba(0xd6, 0x27, 0x28));
-    colors.push(rgba(0x94, 0x67, 0xbd));
-    colors.push(rgba(0x8c, 0x56, 0x4b));
-    colors.push(rgba(0xe3, 0x77, 0xc2));
-    colors.push(rgba(0x7f, 0x7f, 0x7f));
-    colors.push(rgba(0xbc, 0xbd, 0x22));
-    colors.push(rgba(0x17, 0xbe, 0xcf));
-
-    let mut get_color = {
-        let mut i = 0;
-        move || {
-            let color = colors[i % colors.len()];
-            i += 1;
-            color
-        }
-    };
-
-    let mut tri_indices: Vec<usize> = Vec::new();
-
-    let mut gen_point = {
-        use delaunator::Point;
-
-        let mut rng = rand::thread_rng();
-        let distr = Normal::new(0.0, 0.5)?;
-
-        move || loop {
-            let x = distr.sample(&mut rng);
-            let y = distr.sample(&mut rng);
-
-            let v = vec2(x, y);
-
-            if v.norm() <= 1.0 {
-                return Point { x, y };
-            }
-        }
-    };
-
-    let mut rng = rand::thread_rng();
-
-    let mut tri_points: Vec<delaunator::Point> = Vec::new();
-    let mut points: Vec<Vec2> = Vec::new();
-
-    for i in 0..count {
-        let p = gen_point();
-        let v = vec2(p.x as f32, p.y as f32);
-        points.push(v);
-        tri_points.push(p);
-    }
-
-    log::warn!("tri_points.len() = {}", tri_points.len());
-
-    let mut vx = |x: f32, y: f32, z: f32| {
-        let mut v0 = [0u8; 40];
-        v0[0..12].clone_from_slice(bytemuck::cast_slice(&[x, y, z]));
-        v0[12..24].clone_from_slice(bytemuck::cast_slice(&[1f32, 0.0, 0.0]));
-
-        v0[24..40].clone_from_slice(bytemuck::cast_slice(&get_color()));
-        v0
-    };
-
-    for point in points {
-        // let o = vec2(0.5f32, 0.5);
-        let p = vec2(point.x, point.y);
-        // let z = (o - p).norm();
-        // let z = p.norm();
-
-        let z = p.x * p.x + p.y * p.y;
+    buf: &mut Vec<MeshVertex>,
+    indices: &mut Vec<u32>,
+    sampling: &SurfaceSampling,
+    curvature: Option<Curvature>,
+) -> anyhow::Result<picking::Bvh> {
+    let mesh = sampling.mesh()?;
+
+    let colors = curvature
+        .map(|kind| mesh.curvature_colors(kind, &Colormap::diverging()))
+        .transpose()?;
+
+    mesh.vertex_data(Shading::Smooth, buf, indices, |v| match &colors {
+        Some(colors) => colors[v.id().0],
+        None => (sampling.color)(v.id().0, v.center()),
+    });
+
+    Ok(picking::Bvh::new(&mesh))
+}
 
-        buf.push(vx(point.x, z, point.y));
-        // buf.push(vx(point.x, 1.0, point.y));
+/// Loads an OBJ, PLY or STL file, filling `buf` and `indices` with
+/// vertex and index data for the `tri-3d` sublayer, and returning a
+/// BVH for picking. With `curvature`, the vertices are colored by
+/// that curvature, which needs a manifold surface, rather than with
+/// the colors in the file.
+pub fn mesh_file(
+    buf: &mut Vec<MeshVertex>,
+    indices: &mut Vec<u32>,
+    path: impl AsRef<std::path::Path>,
+    curvature: Option<Curvature>,
+) -> anyhow::Result<picking::Bvh> {
+    let mut mesh = io::TriangleMesh::load(path)?;
+
+    if let Some(kind) = curvature {
+        mesh.colors = mesh
+            .to_halfedge()?
+            .curvature_colors(kind, &Colormap::diverging())?;
     }
 
-    let result = delaunator::triangulate(&tri_points);
-    log::warn!("result.triangles.len() = {}", result.triangles.len());
+    mesh.vertex_data(buf, indices);
 
-    index_buffer(
-        engine,
-        clear_queue,
-        result.triangles.into_iter().map(|s| s as u32),
-        // result.triangles.into_iter().rev().map(|s| s as u32),
-        // tri_indices.into_iter().map(|s| s as u32),
-    )
+    let bvh = picking::Bvh::from_triangles(&mesh.positions, &mesh.triangles);
+    Ok(bvh)
 }
 
 pub fn index_buffer(
@@ -328,15 +222,13 @@ pub fn index_buffer(
     Ok((ix_buf, ix_count))
 }
 
-pub fn cube(buf: &mut Vec<[u8; 40]>) {
+pub fn cube(buf: &mut Vec<MeshVertex>) {
     buf.clear();
 
-    let vx = |[x, y, z]: [f32; 3]| {
-        let mut v0 = [0u8; 40];
-        v0[0..12].clone_from_slice(bytemuck::cast_slice(&[x, y, z]));
-        v0[12..24].clone_from_slice(bytemuck::cast_slice(&[1f32, 0.0, 0.0]));
-        v0[24..40].clone_from_slice(bytemuck::cast_slice(&[x, y, z, 1.0]));
-        v0
+    let vx = |[x, y, z]: [f32; 3]| MeshVertex {
+        pos: [x, y, z],
+        normal: [1.0, 0.0, 0.0],
+        color: [x, y, z, 1.0],
     };
 
     buf.extend(
@@ -387,7 +279,7 @@ impl HalfedgeId {
 
 macro_rules! define_id {
     ($name:ident) => {
-        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
         pub struct $name(pub usize);
 
         impl $name {
@@ -408,7 +300,7 @@ define_id!(VertexId);
 define_id!(EdgeId);
 define_id!(FaceId);
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct Vertex {
     id: VertexId,
     pos: Vec3,
@@ -416,13 +308,13 @@ pub struct Vertex {
     halfedge: HalfedgeId,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Edge {
     id: EdgeId,
     halfedge: HalfedgeId,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Face {
     id: FaceId,
     halfedge: HalfedgeId,
@@ -440,7 +332,7 @@ impl Face {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Halfedge {
     id: HalfedgeId,
 
@@ -467,6 +359,69 @@ impl Halfedge {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshError {
+    VertexOutOfBounds {
+        face: usize,
+        vertex: usize,
+        vertex_count: usize,
+    },
+    DegenerateFace {
+        face: usize,
+    },
+    DuplicateHalfedge {
+        from: usize,
+        to: usize,
+    },
+    NonManifoldVertex {
+        vertex: usize,
+    },
+    BoundaryEdge {
+        edge: usize,
+    },
+    NonTriangleFace {
+        face: usize,
+    },
+    NonManifoldResult {
+        edge: usize,
+    },
+}
+
+impl std::fmt::Display for MeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshError::VertexOutOfBounds {
+                face,
+                vertex,
+                vertex_count,
+            } => {
+                write!(f, "Halfedge mesh error: Face {} refers to vertex {}, but there are only {} vertices", face, vertex, vertex_count)
+            }
+            MeshError::DegenerateFace { face } => {
+                write!(f, "Halfedge mesh error: Face {} has fewer than three distinct vertices", face)
+            }
+            MeshError::DuplicateHalfedge { from, to } => {
+                write!(f, "Halfedge mesh error: Directed edge {} -> {} appears in more than one face (non-manifold edge or inconsistent orientation)", from, to)
+            }
+            MeshError::NonManifoldVertex { vertex } => {
+                write!(f, "Halfedge mesh error: Vertex {} is shared by more than one fan of faces", vertex)
+            }
+            MeshError::BoundaryEdge { edge } => {
+                write!(f, "Halfedge mesh error: Operation not supported on boundary edge {}", edge)
+            }
+            MeshError::NonTriangleFace { face } => {
+                write!(f, "Halfedge mesh error: Operation requires face {} to be a triangle", face)
+            }
+            MeshError::NonManifoldResult { edge } => {
+                write!(f, "Halfedge mesh error: Operation on edge {} would make the mesh non-manifold", edge)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+#[derive(Debug, Clone)]
 pub struct HalfedgeMesh {
     halfedges: Vec<Halfedge>,
 
@@ -476,15 +431,36 @@ pub struct HalfedgeMesh {
 }
 
 impl HalfedgeMesh {
+    /// Builds a halfedge mesh from a list of consistently oriented
+    /// triangles, e.g. the output of `delaunator::triangulate`.
+    ///
+    /// Every boundary loop is closed with a face that has
+    /// `is_boundary` set. Non-manifold input results in a
+    /// `MeshError`; vertices that aren't used by any triangle are
+    /// kept, but have no halfedge.
     pub fn from_triangles(
         points: impl IntoIterator<Item = Vec3>,
         triangles: impl IntoIterator<Item = [usize; 3]>,
     ) -> anyhow::Result<Self> {
+        Self::from_polygons(points, triangles)
+    }
+
+    /// Like `from_triangles`, but the faces can have any number of
+    /// sides
+    pub fn from_polygons<P>(
+        points: impl IntoIterator<Item = Vec3>,
+        polygons: impl IntoIterator<Item = P>,
+    ) -> anyhow::Result<Self>
+    where
+        P: AsRef<[usize]>,
+    {
         let mut halfedges: Vec<Halfedge> = Vec::new();
         let mut edges: Vec<Edge> = Vec::new();
 
         let points = points.into_iter().collect::<Vec<_>>();
-        let triangles = triangles.into_iter().collect::<Vec<_>>();
+        let polygons = polygons.into_iter().collect::<Vec<_>>();
+
+        let vertex_count = points.len();
 
         let mut vertices = points
             .iter()
@@ -496,46 +472,54 @@ impl HalfedgeMesh {
             })
             .collect::<Vec<_>>();
 
-        let face_count = triangles.len();
-
         let mut pair_to_halfedge: FxHashMap<(VertexId, VertexId), HalfedgeId> =
             FxHashMap::default();
 
-        let mut faces: Vec<Face> = (0..face_count)
-            .map(|ix| {
-                let id = FaceId(ix);
-                Face {
-                    id,
-                    halfedge: HalfedgeId::null(),
-                    is_boundary: false,
+        let mut faces: Vec<Face> = Vec::with_capacity(polygons.len());
+
+        for (poly_ix, poly) in polygons.iter().enumerate() {
+            let poly = poly.as_ref();
+
+            if let Some(&vertex) = poly.iter().find(|&&v| v >= vertex_count) {
+                return Err(MeshError::VertexOutOfBounds {
+                    face: poly_ix,
+                    vertex,
+                    vertex_count,
                 }
-            })
-            .collect();
+                .into());
+            }
 
-        // let mut faces: Vec<Face> = Vec::new();
+            let repeats_vertex = poly
+                .iter()
+                .enumerate()
+                .any(|(i, v)| poly[i + 1..].contains(v));
 
-        // let mut new_face = |is_boundary: bool| {
-        // };
+            if poly.len() < 3 || repeats_vertex {
+                return Err(MeshError::DegenerateFace { face: poly_ix }.into());
+            }
 
-        for &tri in &triangles {
             let mut face_halfedges: Vec<HalfedgeId> = Vec::new();
 
             let f_id = FaceId(faces.len());
             let mut face = Face::new(f_id, false);
 
-            let degree = tri.len();
+            let degree = poly.len();
 
             for index in 0..degree {
-                let ix_a = VertexId(tri[index]);
-                let ix_b = VertexId(tri[(index + 1) % degree]);
+                let ix_a = VertexId(poly[index]);
+                let ix_b = VertexId(poly[(index + 1) % degree]);
 
                 let ab = (ix_a, ix_b);
 
-                // let he = new_halfedge();
-
                 let he_id = HalfedgeId(halfedges.len());
 
-                pair_to_halfedge.insert(ab, he_id);
+                if pair_to_halfedge.insert(ab, he_id).is_some() {
+                    return Err(MeshError::DuplicateHalfedge {
+                        from: ix_a.0,
+                        to: ix_b.0,
+                    }
+                    .into());
+                }
 
                 let mut h_ab = Halfedge::new(he_id);
 
@@ -550,7 +534,7 @@ impl HalfedgeMesh {
                 let ba = (ix_b, ix_a);
 
                 if let Some(h_ba_i) = pair_to_halfedge.get(&ba) {
-                    let mut h_ba = &mut halfedges[h_ba_i.0];
+                    let h_ba = &mut halfedges[h_ba_i.0];
 
                     h_ab.twin = h_ba.id;
                     h_ba.twin = h_ab.id;
@@ -566,8 +550,6 @@ impl HalfedgeMesh {
                     };
 
                     edges.push(edge);
-
-                    //
                 } else {
                     // later, halfedges with null twins will be used
                     // as boundaries
@@ -575,7 +557,6 @@ impl HalfedgeMesh {
                 }
 
                 halfedges.push(h_ab);
-                // if
             }
 
             for i in 0..degree {
@@ -590,37 +571,544 @@ impl HalfedgeMesh {
             faces.push(face);
         }
 
-        todo!();
+        // every interior halfedge without a twin gets a boundary
+        // halfedge pointing in the opposite direction
+        let interior_count = halfedges.len();
+
+        let mut boundary_out: FxHashMap<VertexId, HalfedgeId> =
+            FxHashMap::default();
+
+        for ix in 0..interior_count {
+            let h = halfedges[ix];
+
+            if !h.twin.is_null() {
+                continue;
+            }
+
+            let to = halfedges[h.next.0].vertex;
+
+            let b_id = HalfedgeId(halfedges.len());
+            let edge_id = EdgeId(edges.len());
+
+            let mut h_b = Halfedge::new(b_id);
+            h_b.twin = h.id;
+            h_b.vertex = to;
+            h_b.edge = edge_id;
+
+            halfedges[ix].twin = b_id;
+            halfedges[ix].edge = edge_id;
+
+            edges.push(Edge {
+                id: edge_id,
+                halfedge: h.id,
+            });
+
+            // a manifold boundary vertex has exactly one outgoing
+            // boundary halfedge
+            if boundary_out.insert(to, b_id).is_some() {
+                return Err(
+                    MeshError::NonManifoldVertex { vertex: to.0 }.into()
+                );
+            }
+
+            halfedges.push(h_b);
+        }
+
+        // boundary loops run opposite to the faces they border; at
+        // each vertex there are as many incoming as outgoing
+        // twinless halfedges, so the lookup can't fail
+        for ix in interior_count..halfedges.len() {
+            let target = halfedges[halfedges[ix].twin.0].vertex;
+            halfedges[ix].next = boundary_out[&target];
+        }
+
+        for ix in interior_count..halfedges.len() {
+            if !halfedges[ix].face.is_null() {
+                continue;
+            }
+
+            let f_id = FaceId(faces.len());
+            let mut face = Face::new(f_id, true);
+            face.halfedge = HalfedgeId(ix);
+
+            let mut he = HalfedgeId(ix);
+            loop {
+                halfedges[he.0].face = f_id;
+                he = halfedges[he.0].next;
+
+                if he.0 == ix {
+                    break;
+                }
+            }
+
+            faces.push(face);
+        }
+
+        // each vertex must be reachable as a single fan, i.e. walking
+        // the outgoing halfedges from `vertex.halfedge` must visit all
+        // of them
+        let mut out_degree = vec![0usize; vertex_count];
+        for h in halfedges.iter() {
+            out_degree[h.vertex.0] += 1;
+        }
+
+        for vertex in vertices.iter() {
+            if vertex.halfedge.is_null() {
+                continue;
+            }
+
+            let mut count = 0;
+            let mut he = vertex.halfedge;
+
+            loop {
+                count += 1;
+                he = halfedges[halfedges[he.0].twin.0].next;
+
+                if he == vertex.halfedge {
+                    break;
+                }
+            }
+
+            if count != out_degree[vertex.id.0] {
+                return Err(MeshError::NonManifoldVertex {
+                    vertex: vertex.id.0,
+                }
+                .into());
+            }
+        }
+
+        Ok(Self {
+            halfedges,
+            vertices,
+            edges,
+            faces,
+        })
+    }
+
+    /// Number of vertices, including ones not used by any face
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
     }
 
-    pub fn vertex_data(&self, buf: &mut Vec<[u8; 40]>) {
-        todo!();
+    pub fn halfedge_count(&self) -> usize {
+        self.halfedges.len()
+    }
+
+    /// Number of faces, not counting the boundary loops
+    pub fn face_count(&self) -> usize {
+        self.faces.iter().filter(|f| !f.is_boundary).count()
+    }
+
+    /// Number of boundary loops
+    pub fn boundary_count(&self) -> usize {
+        self.faces.iter().filter(|f| f.is_boundary).count()
+    }
+
+    pub fn vertex(&self, id: VertexId) -> VertexRef<'_> {
+        VertexRef {
+            mesh: self,
+            vertex: &self.vertices[id.0],
+        }
+    }
+
+    pub fn vertices(&self) -> impl Iterator<Item = VertexRef<'_>> + '_ {
+        self.vertices
+            .iter()
+            .map(move |vertex| VertexRef { mesh: self, vertex })
+    }
+
+    /// Iterates over the non-boundary faces
+    pub fn face_ids(&self) -> impl Iterator<Item = FaceId> + '_ {
+        self.faces.iter().filter(|f| !f.is_boundary).map(|f| f.id)
+    }
+
+    pub fn is_boundary_face(&self, face: FaceId) -> bool {
+        self.faces[face.0].is_boundary
+    }
+
+    /// The halfedges of a face (or boundary loop), following `next`
+    pub fn face_halfedges(&self, face: FaceId) -> FaceHalfedges<'_> {
+        let start = self.faces[face.0].halfedge;
+        FaceHalfedges {
+            mesh: self,
+            start,
+            current: (!start.is_null()).then_some(start),
+        }
+    }
+
+    pub fn face_vertices(
+        &self,
+        face: FaceId,
+    ) -> impl Iterator<Item = VertexId> + '_ {
+        self.face_halfedges(face)
+            .map(move |he| self.halfedges[he.0].vertex)
+    }
+
+    pub fn face_degree(&self, face: FaceId) -> usize {
+        self.face_halfedges(face).count()
+    }
+
+    /// Normal of the face scaled by its area, using Newell's method
+    /// so that non-planar polygons are handled too
+    pub fn face_area_vector(&self, face: FaceId) -> Vec3 {
+        let mut sum = Vec3::zeros();
+
+        for he in self.face_halfedges(face) {
+            let p0 = self.vertices[self.halfedges[he.0].vertex.0].pos;
+            let p1 = self.vertices[self.halfedge_target(he).0].pos;
+            sum += p0.cross(&p1);
+        }
+
+        sum * 0.5
+    }
+
+    pub fn face_area(&self, face: FaceId) -> f32 {
+        self.face_area_vector(face).norm()
+    }
+
+    pub fn face_normal(&self, face: FaceId) -> Vec3 {
+        normalize_or_zero(self.face_area_vector(face))
+    }
+
+    pub fn face_center(&self, face: FaceId) -> Vec3 {
+        let mut sum = Vec3::zeros();
+        let mut count = 0;
+
+        for v in self.face_vertices(face) {
+            sum += self.vertices[v.0].pos;
+            count += 1;
+        }
+
+        sum / count.max(1) as f32
+    }
+
+    fn check_triangles(&self) -> Result<(), MeshError> {
+        match self.face_ids().find(|&f| self.face_degree(f) != 3) {
+            Some(face) => Err(MeshError::NonTriangleFace { face: face.0 }),
+            None => Ok(()),
+        }
+    }
+
+    fn pos(&self, vertex: VertexId) -> Vec3 {
+        self.vertices[vertex.0].pos
+    }
+
+    // the vertex of a triangle that isn't on the halfedge
+    fn opposite_vertex(&self, he: HalfedgeId) -> VertexId {
+        let prev = self.halfedge_prev(he);
+        self.halfedges[prev.0].vertex
+    }
+
+    fn halfedge_target(&self, he: HalfedgeId) -> VertexId {
+        self.halfedges[self.halfedges[he.0].next.0].vertex
+    }
+
+    // walks around the face, so this is linear in the face degree
+    fn halfedge_prev(&self, he: HalfedgeId) -> HalfedgeId {
+        let mut prev = he;
+        loop {
+            let next = self.halfedges[prev.0].next;
+            if next == he {
+                return prev;
+            }
+            prev = next;
+        }
+    }
+
+    pub fn edge_ids(&self) -> impl Iterator<Item = EdgeId> + '_ {
+        self.edges.iter().map(|e| e.id)
+    }
+
+    /// The endpoints of the edge, in the direction of
+    /// `edge.halfedge`
+    pub fn edge_vertices(&self, edge: EdgeId) -> [VertexId; 2] {
+        let he = self.edges[edge.0].halfedge;
+        [self.halfedges[he.0].vertex, self.halfedge_target(he)]
+    }
+
+    pub fn edge_on_boundary(&self, edge: EdgeId) -> bool {
+        let he = self.halfedges[self.edges[edge.0].halfedge.0];
+        let twin = self.halfedges[he.twin.0];
+        self.faces[he.face.0].is_boundary || self.faces[twin.face.0].is_boundary
+    }
+
+    pub fn edge_length(&self, edge: EdgeId) -> f32 {
+        let [a, b] = self.edge_vertices(edge);
+        (self.vertices[a.0].pos - self.vertices[b.0].pos).norm()
+    }
+
+    /// Returns the edge between `a` and `b`, if there is one
+    pub fn find_edge(&self, a: VertexId, b: VertexId) -> Option<EdgeId> {
+        self.vertex(a)
+            .outgoing_halfedges()
+            .find(|&he| self.halfedge_target(he) == b)
+            .map(|he| self.halfedges[he.0].edge)
+    }
+
+    /// The interior angle of the face corner at the source vertex of
+    /// `he`
+    pub fn corner_angle(&self, he: HalfedgeId) -> f32 {
+        let v = self.vertices[self.halfedges[he.0].vertex.0].pos;
+        let next = self.vertices[self.halfedge_target(he).0].pos;
+        let prev = self.vertices
+            [self.halfedges[self.halfedge_prev(he).0].vertex.0]
+            .pos;
+        (next - v).angle(&(prev - v))
+    }
+
+    /// Fills `buf` with vertices in the `[pos; normal; rgba]` layout
+    /// used by the `tri-3d` sublayer, and `indices` with the
+    /// corresponding triangle list, ready for `index_buffer`.
+    ///
+    /// With `Shading::Smooth`, each mesh vertex is emitted once, in
+    /// order, with its area-weighted normal. With `Shading::Flat`,
+    /// the vertices are duplicated for each face, and use the face
+    /// normal. Faces with more than three sides are triangulated as
+    /// fans.
+    pub fn vertex_data<F>(
+        &self,
+        shading: Shading,
+        buf: &mut Vec<MeshVertex>,
+        indices: &mut Vec<u32>,
+        mut color: F,
+    ) where
+        F: FnMut(VertexRef<'_>) -> [f32; 4],
+    {
+        buf.clear();
+        indices.clear();
+
+        match shading {
+            Shading::Smooth => {
+                for vertex in self.vertices() {
+                    let c = color(vertex);
+                    buf.push(MeshVertex::new(
+                        vertex.center(),
+                        vertex.normal(),
+                        c,
+                    ));
+                }
+
+                for face in self.face_ids() {
+                    let mut face_verts = self.face_vertices(face);
+                    if let Some(first) = face_verts.next() {
+                        fan_indices(
+                            indices,
+                            first.0 as u32,
+                            face_verts.map(|v| v.0 as u32),
+                        );
+                    }
+                }
+            }
+            Shading::Flat => {
+                for face in self.face_ids() {
+                    let normal = self.face_normal(face);
+                    let first = buf.len() as u32;
+
+                    for v in self.face_vertices(face) {
+                        let vertex = self.vertex(v);
+                        let c = color(vertex);
+                        buf.push(MeshVertex::new(vertex.center(), normal, c));
+                    }
+
+                    let last = buf.len() as u32;
+                    fan_indices(indices, first, (first + 1)..last);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shading {
+    Smooth,
+    Flat,
+}
+
+// triangulates a polygon as a fan around `first`
+fn fan_indices(
+    indices: &mut Vec<u32>,
+    first: u32,
+    rest: impl IntoIterator<Item = u32>,
+) {
+    let mut rest = rest.into_iter();
+
+    if let Some(mut prev) = rest.next() {
+        for next in rest {
+            indices.extend([first, prev, next]);
+            prev = next;
+        }
     }
 }
 
 impl Vertex {
+    pub fn id(&self) -> VertexId {
+        self.id
+    }
+
+    pub fn pos(&self) -> Vec3 {
+        self.pos
+    }
+}
+
+/// A vertex together with the mesh it belongs to, so that queries
+/// that need the surrounding topology can be answered
+#[derive(Clone, Copy)]
+pub struct VertexRef<'a> {
+    mesh: &'a HalfedgeMesh,
+    vertex: &'a Vertex,
+}
+
+impl<'a> VertexRef<'a> {
+    pub fn id(&self) -> VertexId {
+        self.vertex.id
+    }
+
+    pub fn mesh(&self) -> &'a HalfedgeMesh {
+        self.mesh
+    }
+
+    /// The halfedges pointing away from this vertex, including the
+    /// boundary halfedge if the vertex is on the boundary
+    pub fn outgoing_halfedges(&self) -> OutgoingHalfedges<'a> {
+        let start = self.vertex.halfedge;
+        OutgoingHalfedges {
+            mesh: self.mesh,
+            start,
+            current: (!start.is_null()).then_some(start),
+        }
+    }
+
+    /// The non-boundary faces around this vertex
+    pub fn faces(&self) -> impl Iterator<Item = FaceId> + 'a {
+        let mesh = self.mesh;
+        self.outgoing_halfedges().filter_map(move |he| {
+            let face = mesh.halfedges[he.0].face;
+            (!mesh.faces[face.0].is_boundary).then_some(face)
+        })
+    }
+
+    pub fn neighbors(&self) -> impl Iterator<Item = VertexId> + 'a {
+        let mesh = self.mesh;
+        self.outgoing_halfedges()
+            .map(move |he| mesh.halfedge_target(he))
+    }
+
     pub fn on_boundary(&self) -> bool {
-        todo!();
+        self.outgoing_halfedges().any(|he| {
+            let face = self.mesh.halfedges[he.0].face;
+            self.mesh.faces[face.0].is_boundary
+        })
     }
 
+    /// Number of edges incident to the vertex
     pub fn degree(&self) -> usize {
-        todo!();
+        self.outgoing_halfedges().count()
     }
 
+    /// Same as `normal_area_weighted`
     pub fn normal(&self) -> Vec3 {
-        todo!();
+        self.normal_area_weighted()
+    }
+
+    /// Sum of the adjacent face normals, weighted by face area
+    pub fn normal_area_weighted(&self) -> Vec3 {
+        let sum = self
+            .faces()
+            .fold(Vec3::zeros(), |acc, f| acc + self.mesh.face_area_vector(f));
+        normalize_or_zero(sum)
     }
 
+    /// Sum of the adjacent face normals, weighted by the interior
+    /// angle of each face at this vertex
+    pub fn normal_angle_weighted(&self) -> Vec3 {
+        let mesh = self.mesh;
+        let mut sum = Vec3::zeros();
+
+        for he in self.outgoing_halfedges() {
+            let face = mesh.halfedges[he.0].face;
+            if mesh.faces[face.0].is_boundary {
+                continue;
+            }
+
+            let angle = mesh.corner_angle(he);
+            sum += mesh.face_normal(face) * angle;
+        }
+
+        normalize_or_zero(sum)
+    }
+
+    /// The position of the vertex
     pub fn center(&self) -> Vec3 {
-        todo!();
+        self.vertex.pos
     }
 
+    /// The average position of the neighboring vertices
     pub fn neighborhood_center(&self) -> Vec3 {
-        todo!();
+        let mut sum = Vec3::zeros();
+        let mut count = 0;
+
+        for v in self.neighbors() {
+            sum += self.mesh.vertices[v.0].pos;
+            count += 1;
+        }
+
+        if count == 0 {
+            self.vertex.pos
+        } else {
+            sum / count as f32
+        }
+    }
+}
+
+/// Iterates over the halfedges leaving a vertex, by repeatedly
+/// stepping to `twin.next`
+pub struct OutgoingHalfedges<'a> {
+    mesh: &'a HalfedgeMesh,
+    start: HalfedgeId,
+    current: Option<HalfedgeId>,
+}
+
+impl<'a> Iterator for OutgoingHalfedges<'a> {
+    type Item = HalfedgeId;
+
+    fn next(&mut self) -> Option<HalfedgeId> {
+        let he = self.current?;
+        let twin = self.mesh.halfedges[he.0].twin;
+        let next = self.mesh.halfedges[twin.0].next;
+        self.current = (next != self.start).then_some(next);
+        Some(he)
     }
+}
+
+/// Iterates over the halfedges of a face, by repeatedly stepping to
+/// `next`
+pub struct FaceHalfedges<'a> {
+    mesh: &'a HalfedgeMesh,
+    start: HalfedgeId,
+    current: Option<HalfedgeId>,
+}
 
-    pub fn id(&self) -> usize {
-        todo!();
+impl<'a> Iterator for FaceHalfedges<'a> {
+    type Item = HalfedgeId;
+
+    fn next(&mut self) -> Option<HalfedgeId> {
+        let he = self.current?;
+        let next = self.mesh.halfedges[he.0].next;
+        self.current = (next != self.start).then_some(next);
+        Some(he)
+    }
+}
+
+fn normalize_or_zero(v: Vec3) -> Vec3 {
+    let len = v.norm();
+    if len > f32::EPSILON {
+        v / len
+    } else {
+        Vec3::zeros()
     }
 }
 
@@ -644,6 +1132,157 @@ pub struct Vertex {
 
 */
 
+#[cfg(test)]
+mod tests {
+
+    use super::test_meshes::{grid, tetrahedron};
+    use super::*;
+
+    fn mesh_error(result: anyhow::Result<HalfedgeMesh>) -> MeshError {
+        *result.err().unwrap().downcast_ref::<MeshError>().unwrap()
+    }
+
+    #[test]
+    fn test_from_triangles() {
+        let grid = grid(3);
+
+        assert_eq!(grid.vertex_count(), 9);
+        assert_eq!(grid.face_count(), 8);
+        assert_eq!(grid.edge_count(), 16);
+        assert_eq!(grid.halfedge_count(), 32);
+        assert_eq!(grid.boundary_count(), 1);
+
+        for h in grid.halfedges.iter() {
+            let twin = grid.halfedges[h.twin.0];
+            assert_eq!(twin.twin, h.id);
+            assert_eq!(twin.edge, h.edge);
+            assert_eq!(grid.halfedges[twin.next.0].vertex, h.vertex);
+            assert!(!h.face.is_null());
+        }
+
+        let boundary = grid.faces.iter().find(|f| f.is_boundary).unwrap();
+        let mut he = boundary.halfedge;
+        let mut loop_len = 0;
+        loop {
+            assert_eq!(grid.halfedges[he.0].face, boundary.id);
+            loop_len += 1;
+            he = grid.halfedges[he.0].next;
+            if he == boundary.halfedge {
+                break;
+            }
+        }
+        assert_eq!(loop_len, 8);
+
+        let tet = tetrahedron();
+        assert_eq!(tet.face_count(), 4);
+        assert_eq!(tet.edge_count(), 6);
+        assert_eq!(tet.boundary_count(), 0);
+    }
+
+    #[test]
+    fn test_vertex_queries() {
+        let grid = grid(3);
+
+        let center = grid.vertex(VertexId(4));
+        assert!(!center.on_boundary());
+        assert_eq!(center.degree(), 6);
+        assert_eq!(center.faces().count(), 6);
+        assert_eq!(center.center(), vec3(1.0, 0.0, 1.0));
+        assert!((center.neighborhood_center() - center.center()).norm() < 1e-6);
+
+        let mut neighbors = center.neighbors().map(|v| v.0).collect::<Vec<_>>();
+        neighbors.sort();
+        assert_eq!(neighbors, vec![1, 2, 3, 5, 6, 7]);
+
+        let corner = grid.vertex(VertexId(0));
+        assert!(corner.on_boundary());
+        assert_eq!(corner.degree(), 2);
+        assert_eq!(corner.faces().count(), 1);
+
+        for vertex in grid.vertices() {
+            let up = vec3(0.0, 1.0, 0.0);
+            assert!((vertex.normal_area_weighted() - up).norm() < 1e-6);
+            assert!((vertex.normal_angle_weighted() - up).norm() < 1e-6);
+        }
+
+        let tet = tetrahedron();
+        let apex = tet.vertex(VertexId(0));
+        assert_eq!(apex.degree(), 3);
+        assert!(!apex.on_boundary());
+
+        let expected = -vec3(1.0, 1.0, 1.0).normalize();
+        assert!((apex.normal_angle_weighted() - expected).norm() < 1e-6);
+        assert!((apex.normal_area_weighted() - expected).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_vertex_data() {
+        let grid = grid(3);
+
+        let mut buf = Vec::new();
+        let mut indices = Vec::new();
+
+        let red = [1.0, 0.0, 0.0, 1.0];
+
+        grid.vertex_data(Shading::Smooth, &mut buf, &mut indices, |_| red);
+        assert_eq!(buf.len(), 9);
+        assert_eq!(indices.len(), 24);
+        assert!(indices.iter().all(|&i| (i as usize) < buf.len()));
+
+        grid.vertex_data(Shading::Flat, &mut buf, &mut indices, |_| red);
+        assert_eq!(buf.len(), 24);
+        assert_eq!(indices, (0..24).collect::<Vec<u32>>());
+
+        for vertex in buf.iter() {
+            assert_eq!(vertex.normal, [0.0, 1.0, 0.0]);
+            assert_eq!(vertex.color, red);
+        }
+    }
+
+    #[test]
+    fn test_from_triangles_errors() {
+        let points = (0..5).map(|i| vec3(i as f32, 0.0, 0.0));
+
+        let out_of_bounds =
+            HalfedgeMesh::from_triangles(points.clone(), [[0, 1, 5]]);
+        assert_eq!(
+            mesh_error(out_of_bounds),
+            MeshError::VertexOutOfBounds {
+                face: 0,
+                vertex: 5,
+                vertex_count: 5
+            }
+        );
+
+        let degenerate =
+            HalfedgeMesh::from_triangles(points.clone(), [[0, 1, 1]]);
+        assert_eq!(
+            mesh_error(degenerate),
+            MeshError::DegenerateFace { face: 0 }
+        );
+
+        // the second triangle is flipped relative to the first
+        let flipped = HalfedgeMesh::from_triangles(
+            points.clone(),
+            [[0, 1, 2], [0, 1, 3]],
+        );
+        assert_eq!(
+            mesh_error(flipped),
+            MeshError::DuplicateHalfedge { from: 0, to: 1 }
+        );
+
+        // two triangles touching only at vertex 0
+        let bowtie = HalfedgeMesh::from_triangles(
+            points.clone(),
+            [[0, 1, 2], [0, 3, 4]],
+        );
+        assert_eq!(
+            mesh_error(bowtie),
+            MeshError::NonManifoldVertex { vertex: 0 }
+        );
+    }
+}
+
 fn allocate_uniform_desc_set(
     res: &mut GpuResources,
     buffer: BufferIx,
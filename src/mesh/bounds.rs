@@ -0,0 +1,423 @@
+use nalgebra_glm::{vec3, Mat4, Vec3, Vec4};
+
+use super::{io::TriangleMesh, Camera, HalfedgeMesh};
+
+/// Axis-aligned bounding box. The empty box has `min` at infinity and
+/// `max` at negative infinity, so that extending it with a point
+/// gives a box around just that point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::repeat(f32::INFINITY),
+            max: Vec3::repeat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut aabb = Self::empty();
+        for p in points {
+            aabb.extend(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+            || self.min.y > self.max.y
+            || self.min.z > self.max.z
+    }
+
+    pub fn extend(&mut self, p: Vec3) {
+        self.min = self.min.inf(&p);
+        self.max = self.max.sup(&p);
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        (0..3).all(|i| self.min[i] <= p[i] && p[i] <= self.max[i])
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let [a, b] = [self.min, self.max];
+        [
+            vec3(a.x, a.y, a.z),
+            vec3(b.x, a.y, a.z),
+            vec3(a.x, b.y, a.z),
+            vec3(b.x, b.y, a.z),
+            vec3(a.x, a.y, b.z),
+            vec3(b.x, a.y, b.z),
+            vec3(a.x, b.y, b.z),
+            vec3(b.x, b.y, b.z),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// A sphere around the center of the points' bounding box; not
+    /// the smallest enclosing sphere, but close to it for most data,
+    /// and cheap to compute. `None` if there are no points.
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = Vec3>,
+        I::IntoIter: Clone,
+    {
+        let points = points.into_iter();
+        let aabb = Aabb::from_points(points.clone());
+        if aabb.is_empty() {
+            return None;
+        }
+
+        let center = aabb.center();
+        let radius = points.map(|p| (p - center).norm()).fold(0.0, f32::max);
+
+        Some(Self { center, radius })
+    }
+
+    /// The sphere through the corners of the box
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        Self {
+            center: aabb.center(),
+            radius: aabb.size().norm() * 0.5,
+        }
+    }
+}
+
+/// The plane `normal . p + d = 0`, with the positive side in front
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    // normalizes the plane given by the coefficients of a row of a
+    // view-projection matrix
+    fn from_row(row: Vec4) -> Self {
+        let len = row.xyz().norm();
+        let len = if len > 0.0 { len } else { 1.0 };
+        Self {
+            normal: row.xyz() / len,
+            d: row.w / len,
+        }
+    }
+
+    pub fn signed_distance(&self, p: Vec3) -> f32 {
+        self.normal.dot(&p) + self.d
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// The region of space visible through a projection, as six planes
+/// facing inwards: left, right, top, bottom, near, and far, in
+/// Vulkan clip space where Y points down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a world-to-clip transform, for clip
+    /// space depth in `[0, 1]` as produced by `camera::Projection`,
+    /// either way around
+    pub fn from_matrix(view_proj: &Mat4) -> Self {
+        let row = |i: usize| -> Vec4 { view_proj.row(i).transpose() };
+        let [x, y, z, w] = [row(0), row(1), row(2), row(3)];
+
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z].map(Plane::from_row),
+        }
+    }
+
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        self.planes.iter().all(|pl| pl.signed_distance(p) >= 0.0)
+    }
+
+    /// Conservative; spheres near the corners of the frustum can
+    /// intersect even if they're outside
+    pub fn classify_sphere(&self, sphere: &BoundingSphere) -> Containment {
+        let mut result = Containment::Inside;
+
+        for plane in &self.planes {
+            let dist = plane.signed_distance(sphere.center);
+            if dist < -sphere.radius {
+                return Containment::Outside;
+            } else if dist < sphere.radius {
+                result = Containment::Intersecting;
+            }
+        }
+
+        result
+    }
+
+    /// Conservative like `classify_sphere`
+    pub fn classify_aabb(&self, aabb: &Aabb) -> Containment {
+        if aabb.is_empty() {
+            return Containment::Outside;
+        }
+
+        let mut result = Containment::Inside;
+
+        for plane in &self.planes {
+            // the corners furthest along and against the normal
+            let pick = |toward: bool| {
+                Vec3::from_fn(|i, _| {
+                    if (plane.normal[i] >= 0.0) == toward {
+                        aabb.max[i]
+                    } else {
+                        aabb.min[i]
+                    }
+                })
+            };
+
+            if plane.signed_distance(pick(true)) < 0.0 {
+                return Containment::Outside;
+            } else if plane.signed_distance(pick(false)) < 0.0 {
+                result = Containment::Intersecting;
+            }
+        }
+
+        result
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.classify_sphere(sphere) != Containment::Outside
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.classify_aabb(aabb) != Containment::Outside
+    }
+}
+
+/// Bounding boxes of consecutive chunks of `chunk_size` points, e.g.
+/// the tiles of a point cloud, so that chunks outside the view can
+/// be skipped when filling vertex buffers
+pub fn chunk_bounds(points: &[Vec3], chunk_size: usize) -> Vec<Aabb> {
+    points
+        .chunks(chunk_size.max(1))
+        .map(|chunk| Aabb::from_points(chunk.iter().copied()))
+        .collect()
+}
+
+/// The indices of the chunks, with bounds as given by `chunk_bounds`,
+/// that are at least partly inside the frustum
+pub fn visible_chunks<'a>(
+    frustum: &'a Frustum,
+    bounds: &'a [Aabb],
+) -> impl Iterator<Item = usize> + 'a {
+    bounds
+        .iter()
+        .enumerate()
+        .filter(|(_, aabb)| frustum.intersects_aabb(aabb))
+        .map(|(ix, _)| ix)
+}
+
+/// The items in the visible chunks of `items`, split into chunks of
+/// `chunk_size` as by `chunk_bounds`, e.g. the indices of a triangle
+/// list with a chunk size that's a multiple of 3
+pub fn cull_chunks<'a, T>(
+    frustum: &'a Frustum,
+    bounds: &'a [Aabb],
+    items: &'a [T],
+    chunk_size: usize,
+) -> impl Iterator<Item = &'a T> + 'a {
+    let chunk_size = chunk_size.max(1);
+    visible_chunks(frustum, bounds).flat_map(move |ix| {
+        let start = ix * chunk_size;
+        let end = (start + chunk_size).min(items.len());
+        &items[start.min(end)..end]
+    })
+}
+
+impl Camera {
+    /// The view frustum for a viewport of `dims` pixels
+    pub fn frustum(&self, dims: [f32; 2]) -> Frustum {
+        Frustum::from_matrix(&self.view_projection(dims))
+    }
+}
+
+impl HalfedgeMesh {
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.vertices.iter().map(|v| v.pos))
+    }
+}
+
+impl TriangleMesh {
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.positions.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::camera::{view_matrix, Projection};
+
+    #[test]
+    fn test_bounds() {
+        let points = [
+            vec3(1.0, -2.0, 0.5),
+            vec3(-1.0, 0.0, 3.0),
+            vec3(0.0, 4.0, -0.5),
+        ];
+
+        let aabb = Aabb::from_points(points);
+        assert_eq!(aabb.min, vec3(-1.0, -2.0, -0.5));
+        assert_eq!(aabb.max, vec3(1.0, 4.0, 3.0));
+        assert!(points.iter().all(|&p| aabb.contains(p)));
+        assert!(aabb.corners().iter().all(|&p| aabb.contains(p)));
+
+        assert!(Aabb::empty().is_empty());
+        assert_eq!(Aabb::empty().union(&aabb), aabb);
+
+        let sphere = BoundingSphere::from_points(points).unwrap();
+        assert_eq!(sphere.center, aabb.center());
+        assert!(points
+            .iter()
+            .all(|p| (p - sphere.center).norm() <= sphere.radius + 1e-6));
+        assert!(
+            sphere.radius <= BoundingSphere::from_aabb(&aabb).radius + 1e-6
+        );
+        assert!(BoundingSphere::from_points(std::iter::empty()).is_none());
+
+        let chunks = chunk_bounds(&points, 2);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1], Aabb::from_points([points[2]]));
+    }
+
+    #[test]
+    fn test_frustum() {
+        // looking down -Z from the origin
+        let view =
+            view_matrix(Vec3::zeros(), [Vec3::x(), Vec3::y(), Vec3::z()]);
+        let dims = [800.0, 600.0];
+
+        let reversed = Projection::Perspective {
+            fov_y: 1.0,
+            near: 0.5,
+            far: 100.0,
+            reversed_z: true,
+        };
+
+        for proj in [
+            Projection::perspective(1.0, 0.5, 100.0),
+            reversed,
+            Projection::orthographic(10.0, 0.5, 100.0),
+        ] {
+            let frustum = Frustum::from_matrix(&(proj.matrix(dims) * view));
+
+            // the near and far planes, in some order
+            let mut depths = frustum.planes[4..]
+                .iter()
+                .map(|pl| -pl.d / pl.normal.z)
+                .collect::<Vec<_>>();
+            depths.sort_by(f32::total_cmp);
+            assert!((depths[0] + 100.0).abs() < 1e-2);
+            assert!((depths[1] + 0.5).abs() < 1e-4);
+
+            assert!(frustum.contains_point(vec3(0.0, 0.0, -10.0)));
+            assert!(!frustum.contains_point(vec3(0.0, 0.0, 1.0)));
+            assert!(!frustum.contains_point(vec3(0.0, 0.0, -200.0)));
+            assert!(!frustum.contains_point(vec3(0.0, 30.0, -10.0)));
+
+            // the visible height at the distance of the box, which is
+            // wider than it is tall
+            let top = proj.view_height(10.0) * 0.5;
+
+            let inside =
+                Aabb::new(vec3(-1.0, -1.0, -11.0), vec3(1.0, 1.0, -9.0));
+            let straddling = Aabb::new(
+                vec3(-1.0, top - 0.5, -10.5),
+                vec3(1.0, top + 0.5, -9.5),
+            );
+            let above = Aabb::new(
+                vec3(-1.0, top + 1.0, -10.5),
+                vec3(1.0, top + 2.0, -9.5),
+            );
+            let behind = Aabb::new(vec3(-1.0, -1.0, 1.0), vec3(1.0, 1.0, 2.0));
+
+            assert_eq!(frustum.classify_aabb(&inside), Containment::Inside);
+            assert_eq!(
+                frustum.classify_aabb(&straddling),
+                Containment::Intersecting
+            );
+            assert_eq!(frustum.classify_aabb(&above), Containment::Outside);
+            assert_eq!(frustum.classify_aabb(&behind), Containment::Outside);
+            assert!(!frustum.intersects_aabb(&Aabb::empty()));
+
+            let sphere = |aabb: &Aabb| BoundingSphere::from_aabb(aabb);
+            assert_eq!(
+                frustum.classify_sphere(&sphere(&inside)),
+                Containment::Inside
+            );
+            assert!(frustum.intersects_sphere(&sphere(&straddling)));
+            assert!(!frustum.intersects_sphere(&sphere(&behind)));
+
+            // chunks of two points each: in view, above it, straddling
+            // the top, and behind the camera
+            let points = [
+                inside.min,
+                inside.max,
+                above.min,
+                above.max,
+                straddling.min,
+                straddling.max,
+                behind.min,
+                behind.max,
+            ];
+            let bounds = chunk_bounds(&points, 2);
+            let visible = visible_chunks(&frustum, &bounds).collect::<Vec<_>>();
+            assert_eq!(visible, vec![0, 2]);
+
+            let culled = cull_chunks(&frustum, &bounds, &points, 2)
+                .copied()
+                .collect::<Vec<_>>();
+            assert_eq!(
+                culled,
+                vec![points[0], points[1], points[4], points[5]]
+            );
+        }
+    }
+}
@@ -0,0 +1,649 @@
+use nalgebra_glm::{vec2, vec3, Mat4, Vec2, Vec3};
+
+use super::{normalize_or_zero, Camera};
+
+/// Vertical field of view of the default perspective projection, in
+/// radians
+const DEFAULT_FOV_Y: f32 = 1.4;
+
+/// Keeps orbit and fly controllers from looking straight up or down,
+/// where yaw is undefined
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Maps view space, with the camera looking down -Z and Y up, to
+/// Vulkan clip space, with Y down and depth in `[0, 1]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view, in radians
+        fov_y: f32,
+        near: f32,
+        far: f32,
+        /// Maps the near plane to depth 1 and the far plane to 0,
+        /// which spreads floating point depth precision more evenly
+        reversed_z: bool,
+    },
+    /// Parallel projection, so that distances on screen don't depend
+    /// on the distance to the camera
+    Orthographic {
+        /// The visible height in world units at zoom 1
+        height: f32,
+        /// Magnification; the visible height is `height / zoom`
+        zoom: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self::perspective(DEFAULT_FOV_Y, 1.0, 1000.0)
+    }
+}
+
+impl Projection {
+    pub fn perspective(fov_y: f32, near: f32, far: f32) -> Self {
+        Self::Perspective {
+            fov_y,
+            near,
+            far,
+            reversed_z: false,
+        }
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+        Self::Orthographic {
+            height,
+            zoom: 1.0,
+            near,
+            far,
+        }
+    }
+
+    /// The height of the visible region at `distance` in front of the
+    /// camera, in world units
+    pub fn view_height(&self, distance: f32) -> f32 {
+        match *self {
+            Self::Perspective { fov_y, .. } => {
+                2.0 * distance * (fov_y * 0.5).tan()
+            }
+            Self::Orthographic { height, zoom, .. } => height / zoom,
+        }
+    }
+
+    /// The projection matrix for a viewport of `dims` pixels
+    pub fn matrix(&self, dims: [f32; 2]) -> Mat4 {
+        let [width, height] = dims;
+        let aspect = width / height;
+
+        let mut m = match *self {
+            Self::Perspective {
+                fov_y,
+                near,
+                far,
+                reversed_z,
+            } => {
+                // swapping the planes is all it takes to reverse Z
+                let (near, far) =
+                    if reversed_z { (far, near) } else { (near, far) };
+                nalgebra_glm::perspective_rh_zo(aspect, fov_y, near, far)
+            }
+            Self::Orthographic { near, far, .. } => {
+                let top = self.view_height(0.0) * 0.5;
+                let right = top * aspect;
+                nalgebra_glm::ortho_rh_zo(-right, right, -top, top, near, far)
+            }
+        };
+
+        // the GL matrices have Y up in clip space, and the viewport
+        // isn't flipped
+        m[(1, 1)] = -m[(1, 1)];
+        m
+    }
+}
+
+/// Input for camera controllers, accumulated over a frame. Deltas are
+/// in window pixels, with Y pointing down.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CameraInput {
+    /// Mouse motion while rotating, e.g. dragging with the left button
+    pub rotate: Vec2,
+    /// Mouse motion while panning, e.g. dragging with the right button
+    pub pan: Vec2,
+    /// Scroll steps; positive zooms in
+    pub zoom: f32,
+    /// The cursor position relative to the center of the window,
+    /// which pan-zoom controllers zoom towards
+    pub cursor: Vec2,
+    /// Movement from held keys in camera space, with X right, Y up
+    /// and Z forward, each in `[-1, 1]`
+    pub movement: Vec3,
+}
+
+impl CameraInput {
+    pub fn is_empty(&self) -> bool {
+        self.rotate == Vec2::zeros()
+            && self.pan == Vec2::zeros()
+            && self.zoom == 0.0
+            && self.movement == Vec3::zeros()
+    }
+}
+
+pub trait CameraController {
+    /// Updates the controller with the input of a frame that took
+    /// `dt` seconds
+    fn update(&mut self, input: &CameraInput, dt: f32);
+
+    /// The eye position, the point looked at, and the up direction
+    fn look_at(&self) -> [Vec3; 3];
+
+    fn view_matrix(&self) -> Mat4 {
+        let [eye, target, up] = self.look_at();
+        nalgebra_glm::look_at_rh(&eye, &target, &up)
+    }
+}
+
+impl Camera {
+    pub fn eye(&self) -> Vec3 {
+        self.eye
+    }
+
+    /// The direction the camera is looking in
+    pub fn forward(&self) -> Vec3 {
+        -self.n
+    }
+
+    /// Points the camera at `target` from `eye`; `up` doesn't have
+    /// to be orthogonal to the view direction
+    pub fn look_at(&mut self, eye: Vec3, target: Vec3, up: Vec3) {
+        let n = normalize_or_zero(eye - target);
+        let u = normalize_or_zero(up.cross(&n));
+
+        self.eye = eye;
+        self.n = n;
+        self.u = u;
+        self.v = n.cross(&u);
+    }
+
+    pub fn apply(&mut self, controller: &impl CameraController) {
+        let [eye, target, up] = controller.look_at();
+        self.look_at(eye, target, up);
+    }
+
+    /// The world-to-view transform, with the camera looking down -Z
+    pub fn view_matrix(&self) -> Mat4 {
+        view_matrix(self.eye, [self.u, self.v, self.n])
+    }
+
+    /// The view-to-clip transform for a viewport of `dims` pixels
+    pub fn projection_matrix(&self, dims: [f32; 2]) -> Mat4 {
+        self.projection.matrix(dims)
+    }
+
+    /// The world-to-clip transform for a viewport of `dims` pixels
+    pub fn view_projection(&self, dims: [f32; 2]) -> Mat4 {
+        self.projection_matrix(dims) * self.view_matrix()
+    }
+}
+
+pub(super) fn view_matrix(eye: Vec3, [u, v, n]: [Vec3; 3]) -> Mat4 {
+    #[rustfmt::skip]
+    let view = Mat4::new(
+        u.x, u.y, u.z, -u.dot(&eye),
+        v.x, v.y, v.z, -v.dot(&eye),
+        n.x, n.y, n.z, -n.dot(&eye),
+        0.0, 0.0, 0.0, 1.0,
+    );
+    view
+}
+
+// the view direction for a yaw around Y and a pitch above the XZ
+// plane; zero yaw and pitch looks down -Z
+fn direction(yaw: f32, pitch: f32) -> Vec3 {
+    let (sin_yaw, cos_yaw) = yaw.sin_cos();
+    let (sin_pitch, cos_pitch) = pitch.sin_cos();
+    vec3(-sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch)
+}
+
+/// Orbits around a target point; rotating moves the eye over a
+/// sphere around the target, panning moves the target in the view
+/// plane, and zooming changes the distance
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+
+    /// Radians per pixel
+    pub rotate_speed: f32,
+    /// Fraction of the distance per pixel
+    pub pan_speed: f32,
+    /// Distance factor per scroll step
+    pub zoom_factor: f32,
+    pub min_distance: f32,
+}
+
+impl OrbitController {
+    pub fn new(target: Vec3, distance: f32) -> Self {
+        Self {
+            target,
+            distance,
+            yaw: 0.0,
+            pitch: 0.0,
+
+            rotate_speed: 0.005,
+            pan_speed: 0.002,
+            zoom_factor: 0.9,
+            min_distance: 0.01,
+        }
+    }
+
+    /// An orbit with the eye at `eye`
+    pub fn from_eye(eye: Vec3, target: Vec3) -> Self {
+        let offset = target - eye;
+        let distance = offset.norm();
+        let dir = normalize_or_zero(offset);
+
+        let mut orbit = Self::new(target, distance);
+        orbit.yaw = (-dir.x).atan2(-dir.z);
+        orbit.pitch = dir.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+        orbit
+    }
+
+    pub fn eye(&self) -> Vec3 {
+        self.target - direction(self.yaw, self.pitch) * self.distance
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, input: &CameraInput, _dt: f32) {
+        self.yaw -= input.rotate.x * self.rotate_speed;
+        self.pitch = (self.pitch - input.rotate.y * self.rotate_speed)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+
+        if input.pan != Vec2::zeros() {
+            let [eye, target, up] = self.look_at();
+            let forward = normalize_or_zero(target - eye);
+            let right = normalize_or_zero(forward.cross(&up));
+            let up = right.cross(&forward);
+
+            // the scene follows the cursor
+            let scale = self.distance * self.pan_speed;
+            self.target += (up * input.pan.y - right * input.pan.x) * scale;
+        }
+
+        self.distance = (self.distance * self.zoom_factor.powf(input.zoom))
+            .max(self.min_distance);
+    }
+
+    fn look_at(&self) -> [Vec3; 3] {
+        [self.eye(), self.target, Vec3::y()]
+    }
+}
+
+/// First-person camera; rotating turns the view, held keys move the
+/// eye, and zooming changes the movement speed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlyController {
+    pub eye: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+
+    /// Units per second
+    pub speed: f32,
+    /// Radians per pixel
+    pub rotate_speed: f32,
+    /// Speed factor per scroll step
+    pub speed_factor: f32,
+}
+
+impl FlyController {
+    pub fn new(eye: Vec3) -> Self {
+        Self {
+            eye,
+            yaw: 0.0,
+            pitch: 0.0,
+
+            speed: 5.0,
+            rotate_speed: 0.003,
+            speed_factor: 1.1,
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        direction(self.yaw, self.pitch)
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, input: &CameraInput, dt: f32) {
+        self.yaw -= input.rotate.x * self.rotate_speed;
+        self.pitch = (self.pitch - input.rotate.y * self.rotate_speed)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+
+        self.speed *= self.speed_factor.powf(input.zoom);
+
+        let forward = self.forward();
+        let right = normalize_or_zero(forward.cross(&Vec3::y()));
+
+        let m = input.movement;
+        let motion = right * m.x + Vec3::y() * m.y + forward * m.z;
+        self.eye += motion * self.speed * dt;
+    }
+
+    fn look_at(&self) -> [Vec3; 3] {
+        [self.eye, self.eye + self.forward(), Vec3::y()]
+    }
+}
+
+/// A 2D view of the XY plane, looking down -Z; panning drags the
+/// plane with the cursor, and zooming keeps the point under the
+/// cursor in place
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanZoomController {
+    /// The point in the center of the view
+    pub center: Vec2,
+    /// World units per pixel
+    pub scale: f32,
+    /// The height of the viewport in pixels
+    pub viewport_height: f32,
+
+    /// Scale factor per scroll step
+    pub zoom_factor: f32,
+}
+
+impl PanZoomController {
+    pub fn new(center: Vec2, scale: f32, viewport_height: f32) -> Self {
+        Self {
+            center,
+            scale,
+            viewport_height,
+            zoom_factor: 0.9,
+        }
+    }
+
+    /// The world position of a point given in pixels relative to the
+    /// center of the viewport
+    pub fn to_world(&self, pixel: Vec2) -> Vec2 {
+        self.center + vec2(pixel.x, -pixel.y) * self.scale
+    }
+
+    /// The height of the visible part of the plane, in world units
+    pub fn view_height(&self) -> f32 {
+        self.scale * self.viewport_height
+    }
+
+    /// An orthographic projection showing the same region of the
+    /// plane as the default perspective does from `look_at`
+    pub fn projection(&self) -> Projection {
+        let distance = self.distance();
+        Projection::orthographic(self.view_height(), 0.0, distance * 2.0)
+    }
+
+    // far enough that the view height fits in the default field of
+    // view
+    fn distance(&self) -> f32 {
+        self.view_height() / Projection::default().view_height(1.0)
+    }
+}
+
+impl CameraController for PanZoomController {
+    fn update(&mut self, input: &CameraInput, _dt: f32) {
+        self.center -= vec2(input.pan.x, -input.pan.y) * self.scale;
+
+        if input.zoom != 0.0 {
+            let anchor = self.to_world(input.cursor);
+            self.scale *= self.zoom_factor.powf(input.zoom);
+            self.center += anchor - self.to_world(input.cursor);
+        }
+    }
+
+    fn look_at(&self) -> [Vec3; 3] {
+        let target = vec3(self.center.x, self.center.y, 0.0);
+        [target + Vec3::z() * self.distance(), target, Vec3::y()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use nalgebra_glm::vec4;
+
+    fn transform(m: &Mat4, p: Vec3) -> Vec3 {
+        let p = m * vec4(p.x, p.y, p.z, 1.0);
+        p.xyz() / p.w
+    }
+
+    #[test]
+    fn test_projection() {
+        let dims = [800.0, 600.0];
+        let view =
+            view_matrix(vec3(1.0, 2.0, 3.0), [Vec3::x(), Vec3::y(), Vec3::z()]);
+
+        let perspective = Projection::perspective(1.0, 0.5, 100.0);
+        let reversed = Projection::Perspective {
+            fov_y: 1.0,
+            near: 0.5,
+            far: 100.0,
+            reversed_z: true,
+        };
+        let orthographic = Projection::orthographic(10.0, 0.5, 100.0);
+
+        for proj in [perspective, reversed, orthographic] {
+            let m = proj.matrix(dims);
+            let view_proj = m * view;
+            let inverse = view_proj.try_inverse().unwrap();
+
+            // points in the view volume round trip through clip space
+            for p in [
+                vec3(1.0, 2.0, -2.0),
+                vec3(2.5, 4.0, -10.0),
+                vec3(-1.0, 1.0, -40.0),
+            ] {
+                let ndc = transform(&view_proj, p);
+                assert!(ndc.xy().abs().max() <= 1.0);
+                assert!((0.0..=1.0).contains(&ndc.z));
+                assert!((transform(&inverse, ndc) - p).norm() < 1e-3);
+            }
+
+            // the top of the view at some distance maps to the top
+            // of the viewport, at NDC Y -1, and the sides follow the
+            // aspect ratio
+            let d = 20.0;
+            let top = proj.view_height(d) * 0.5;
+            let corner = vec3(top * 800.0 / 600.0, top, -d);
+            let ndc = transform(&m, corner);
+            assert!((ndc.xy() - vec2(1.0, -1.0)).norm() < 1e-4);
+
+            let near = transform(&m, vec3(0.0, 0.0, -0.5)).z;
+            let far = transform(&m, vec3(0.0, 0.0, -100.0)).z;
+            if proj == reversed {
+                assert!((near - 1.0).abs() < 1e-5 && far.abs() < 1e-5);
+            } else {
+                assert!(near.abs() < 1e-5 && (far - 1.0).abs() < 1e-5);
+            }
+        }
+
+        // orthographic projections ignore depth, and zooming shrinks
+        // the view
+        let m = orthographic.matrix(dims);
+        let a = transform(&m, vec3(1.0, 2.0, -1.0));
+        let b = transform(&m, vec3(1.0, 2.0, -50.0));
+        assert!((a.xy() - b.xy()).norm() < 1e-6);
+
+        let zoomed = Projection::Orthographic {
+            height: 10.0,
+            zoom: 2.0,
+            near: 0.5,
+            far: 100.0,
+        };
+        assert_eq!(zoomed.view_height(1.0), 5.0);
+        let c = transform(&zoomed.matrix(dims), vec3(1.0, 2.0, -1.0));
+        assert!((c.xy() - a.xy() * 2.0).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_orbit() {
+        let target = vec3(1.0, 0.5, 0.0);
+        let mut orbit = OrbitController::from_eye(vec3(1.0, 0.5, 10.0), target);
+        assert!((orbit.eye() - vec3(1.0, 0.5, 10.0)).norm() < 1e-5);
+
+        // the target is straight ahead
+        let view = orbit.view_matrix();
+        let t = transform(&view, target);
+        assert!((t - vec3(0.0, 0.0, -10.0)).norm() < 1e-5);
+
+        let eye = orbit.eye();
+        let n = (eye - target).normalize();
+        let u = Vec3::y().cross(&n).normalize();
+        assert!((view - view_matrix(eye, [u, n.cross(&u), n])).norm() < 1e-5);
+
+        // rotating keeps the distance, and pitch is clamped
+        orbit.update(
+            &CameraInput {
+                rotate: vec2(100.0, -50.0),
+                ..Default::default()
+            },
+            0.1,
+        );
+        assert!(((orbit.eye() - target).norm() - 10.0).abs() < 1e-4);
+        // dragging up looks up at the target from below
+        assert!(orbit.eye().y < target.y);
+
+        orbit.update(
+            &CameraInput {
+                rotate: vec2(0.0, -1e5),
+                ..Default::default()
+            },
+            0.1,
+        );
+        assert_eq!(orbit.pitch, MAX_PITCH);
+
+        // panning moves the target in the view plane
+        let mut orbit =
+            OrbitController::from_eye(vec3(0.0, 0.0, 10.0), Vec3::zeros());
+        orbit.update(
+            &CameraInput {
+                pan: vec2(10.0, 0.0),
+                ..Default::default()
+            },
+            0.1,
+        );
+        assert!(orbit.target.x < 0.0);
+        assert!(orbit.target.y.abs() < 1e-6 && orbit.target.z.abs() < 1e-6);
+        assert!((orbit.eye().z - 10.0).abs() < 1e-5);
+
+        // zooming in gets closer, but not too close
+        orbit.update(
+            &CameraInput {
+                zoom: 2.0,
+                ..Default::default()
+            },
+            0.1,
+        );
+        assert!((orbit.distance - 10.0 * 0.81).abs() < 1e-4);
+        orbit.update(
+            &CameraInput {
+                zoom: 1000.0,
+                ..Default::default()
+            },
+            0.1,
+        );
+        assert_eq!(orbit.distance, orbit.min_distance);
+    }
+
+    #[test]
+    fn test_fly() {
+        let mut fly = FlyController::new(vec3(0.0, 1.0, 5.0));
+        assert_eq!(fly.forward(), vec3(0.0, 0.0, -1.0));
+
+        let forward = CameraInput {
+            movement: vec3(0.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        fly.update(&forward, 0.5);
+        assert!((fly.eye - vec3(0.0, 1.0, 2.5)).norm() < 1e-5);
+
+        // turning right by a quarter turn, then moving forward and
+        // strafing left
+        let quarter = std::f32::consts::FRAC_PI_2 / fly.rotate_speed;
+        fly.update(
+            &CameraInput {
+                rotate: vec2(quarter, 0.0),
+                ..Default::default()
+            },
+            0.0,
+        );
+        assert!((fly.forward() - vec3(1.0, 0.0, 0.0)).norm() < 1e-5);
+
+        let input = CameraInput {
+            movement: vec3(-1.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        fly.update(&input, 0.2);
+        assert!((fly.eye - vec3(1.0, 1.0, 1.5)).norm() < 1e-5);
+
+        // scrolling changes the speed
+        fly.update(
+            &CameraInput {
+                zoom: 1.0,
+                ..Default::default()
+            },
+            0.0,
+        );
+        assert!((fly.speed - 5.5).abs() < 1e-5);
+
+        // the view matrix is right-handed, with the camera looking
+        // down -Z
+        let view = fly.view_matrix();
+        let ahead = transform(&view, fly.eye + fly.forward() * 2.0);
+        assert!((ahead - vec3(0.0, 0.0, -2.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_pan_zoom() {
+        let mut pz = PanZoomController::new(vec2(0.0, 0.0), 0.01, 600.0);
+        assert!((pz.view_height() - 6.0).abs() < 1e-5);
+
+        // dragging right and down moves the view left and up
+        pz.update(
+            &CameraInput {
+                pan: vec2(100.0, 50.0),
+                ..Default::default()
+            },
+            0.0,
+        );
+        assert!((pz.center - vec2(-1.0, 0.5)).norm() < 1e-5);
+
+        // zooming keeps the point under the cursor fixed
+        let cursor = vec2(120.0, -80.0);
+        let before = pz.to_world(cursor);
+        pz.update(
+            &CameraInput {
+                zoom: 3.0,
+                cursor,
+                ..Default::default()
+            },
+            0.0,
+        );
+        assert!(pz.scale < 0.01);
+        assert!((pz.to_world(cursor) - before).norm() < 1e-5);
+
+        let [eye, target, _] = pz.look_at();
+        assert_eq!(target.xy(), pz.center);
+        assert!(eye.z > 0.0);
+
+        // both projections put the top edge of the viewport in the
+        // same place
+        let dims = [800.0, 600.0];
+        let top = pz.to_world(vec2(0.0, -300.0));
+        let top = vec3(top.x, top.y, 0.0);
+        for proj in [Projection::default(), pz.projection()] {
+            let view_proj = proj.matrix(dims) * pz.view_matrix();
+            let ndc = transform(&view_proj, top);
+            assert!((ndc.xy() - vec2(0.0, -1.0)).norm() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use nalgebra::UnitQuaternion;
+use nalgebra_glm::{vec3, Vec3};
+
+use super::camera::{CameraController, CameraInput, Projection};
+use super::{normalize_or_zero, Camera};
+
+/// How the time between two keyframes maps to progress along the
+/// path; the easing of a keyframe applies until the next one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps `t` in `[0, 1]` to `[0, 1]`, keeping the end points
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (2.0 - 2.0 * t).powi(3) * 0.5
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Easing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Easing::Linear => "linear",
+            Easing::EaseIn => "ease-in",
+            Easing::EaseOut => "ease-out",
+            Easing::EaseInOut => "ease-in-out",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Easing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Easing::Linear),
+            "ease-in" => Ok(Easing::EaseIn),
+            "ease-out" => Ok(Easing::EaseOut),
+            "ease-in-out" => Ok(Easing::EaseInOut),
+            _ => Err(anyhow!("Unknown easing {:?}", s)),
+        }
+    }
+}
+
+/// Where a camera is and what it looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub eye: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    /// Vertical field of view, in radians
+    pub fov_y: f32,
+}
+
+impl CameraPose {
+    pub fn look_at(&self) -> [Vec3; 3] {
+        [self.eye, self.target, self.up]
+    }
+
+    /// Points the camera, and sets the field of view if it has a
+    /// perspective projection
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.look_at(self.eye, self.target, self.up);
+
+        if let Projection::Perspective { fov_y, .. } = &mut camera.projection {
+            *fov_y = self.fov_y;
+        }
+    }
+
+    // rotates the camera's -Z to the view direction, and Y to up
+    fn orientation(&self) -> UnitQuaternion<f32> {
+        let forward = normalize_or_zero(self.target - self.eye);
+        UnitQuaternion::face_towards(&-forward, &self.up)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    /// In seconds
+    pub time: f32,
+    pub pose: CameraPose,
+    /// Used between this keyframe and the next
+    pub easing: Easing,
+}
+
+/// Keyframes sorted by time. Between keyframes, the eye follows a
+/// Catmull-Rom spline, the view direction and up vector are slerped,
+/// and the target distance and field of view are interpolated
+/// linearly.
+///
+/// Paths are stored as text, with one keyframe per line:
+///
+/// ```text
+/// # time  eye  target  up  fov_y  easing
+/// 0.0  10 3 0  0 0.5 0  0 1 0  1.4  ease-in-out
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Inserts a keyframe after any others at the same time
+    pub fn insert(&mut self, keyframe: Keyframe) {
+        let ix = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(ix, keyframe);
+    }
+
+    pub fn start_time(&self) -> f32 {
+        self.keyframes.first().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    pub fn end_time(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.end_time() - self.start_time()
+    }
+
+    /// The pose at `time`, which is clamped to the path; `None` if
+    /// there are no keyframes
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let keys = &self.keyframes;
+        let last = keys.len().checked_sub(1)?;
+
+        // the segment from keyframe `i` to `i + 1`
+        let i = keys
+            .partition_point(|k| k.time <= time)
+            .saturating_sub(1)
+            .min(last.saturating_sub(1));
+        let j = (i + 1).min(last);
+
+        let (k0, k1) = (&keys[i], &keys[j]);
+        let span = k1.time - k0.time;
+        let t = if span > 0.0 {
+            (time - k0.time) / span
+        } else {
+            0.0
+        };
+        let t = k0.easing.apply(t);
+
+        // past the ends, the neighboring keyframes are mirrored, so
+        // evenly spaced keyframes on a line give a straight path
+        let p = |ix: usize| keys[ix].pose.eye;
+        let before = if i > 0 { p(i - 1) } else { p(i) * 2.0 - p(j) };
+        let after = if j < last {
+            p(j + 1)
+        } else {
+            p(j) * 2.0 - p(i)
+        };
+        let eye = catmull_rom([before, p(i), p(j), after], t);
+
+        let (q0, mut q1) = (k0.pose.orientation(), k1.pose.orientation());
+        if q0.coords.dot(&q1.coords) < 0.0 {
+            q1 = UnitQuaternion::new_unchecked(-q1.into_inner());
+        }
+        // slerp is undefined for nearly equal rotations
+        let q = q0
+            .try_slerp(&q1, t, 1e-6)
+            .unwrap_or_else(|| q0.nlerp(&q1, t));
+
+        let distance = |pose: &CameraPose| (pose.target - pose.eye).norm();
+        let distance = lerp(distance(&k0.pose), distance(&k1.pose), t);
+
+        Some(CameraPose {
+            eye,
+            target: eye - q * Vec3::z() * distance,
+            up: q * Vec3::y(),
+            fov_y: lerp(k0.pose.fov_y, k1.pose.fov_y, t),
+        })
+    }
+
+    /// Reads a path in the text format described above; empty lines
+    /// and lines starting with `#` are skipped
+    pub fn read(reader: impl BufRead) -> anyhow::Result<Self> {
+        let mut keyframes = Vec::new();
+
+        for (line_ix, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let keyframe = parse_keyframe(line).with_context(|| {
+                format!("Bad keyframe on line {}", line_ix + 1)
+            })?;
+            keyframes.push(keyframe);
+        }
+
+        Ok(Self::new(keyframes))
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> anyhow::Result<()> {
+        writeln!(writer, "# time  eye  target  up  fov_y  easing")?;
+
+        for k in &self.keyframes {
+            let CameraPose {
+                eye,
+                target,
+                up,
+                fov_y,
+            } = k.pose;
+
+            writeln!(
+                writer,
+                "{}  {} {} {}  {} {} {}  {} {} {}  {}  {}",
+                k.time,
+                eye.x,
+                eye.y,
+                eye.z,
+                target.x,
+                target.y,
+                target.z,
+                up.x,
+                up.y,
+                up.z,
+                fov_y,
+                k.easing
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Opening camera path {:?}", path))?;
+
+        Self::read(BufReader::new(file))
+            .with_context(|| format!("Reading camera path {:?}", path))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Creating camera path {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+
+        self.write(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Plays a camera path back as a camera controller, ignoring input
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathPlayer {
+    pub path: CameraPath,
+    /// The current time on the path, in seconds
+    pub time: f32,
+    /// Playback speed; 1 is real time
+    pub speed: f32,
+    /// Whether to start over after the last keyframe
+    pub looping: bool,
+}
+
+impl PathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        let time = path.start_time();
+        Self {
+            path,
+            time,
+            speed: 1.0,
+            looping: false,
+        }
+    }
+
+    pub fn pose(&self) -> Option<CameraPose> {
+        self.path.sample(self.time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.path.end_time()
+    }
+}
+
+impl CameraController for PathPlayer {
+    fn update(&mut self, _input: &CameraInput, dt: f32) {
+        let start = self.path.start_time();
+        let duration = self.path.duration();
+
+        self.time += dt * self.speed;
+
+        if self.looping && duration > 0.0 {
+            self.time = start + (self.time - start).rem_euclid(duration);
+        } else {
+            self.time = self.time.clamp(start, self.path.end_time());
+        }
+    }
+
+    fn look_at(&self) -> [Vec3; 3] {
+        match self.pose() {
+            Some(pose) => pose.look_at(),
+            None => [Vec3::zeros(), -Vec3::z(), Vec3::y()],
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// uniform Catmull-Rom between `p1` and `p2`
+fn catmull_rom([p0, p1, p2, p3]: [Vec3; 4], t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+fn parse_keyframe(line: &str) -> anyhow::Result<Keyframe> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() != 12 {
+        bail!("Expected 12 fields, found {}", fields.len());
+    }
+
+    let nums = fields[..11]
+        .iter()
+        .map(|f| {
+            f.parse::<f32>()
+                .map_err(|_| anyhow!("Expected a number, found {:?}", f))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let v = |i: usize| vec3(nums[i], nums[i + 1], nums[i + 2]);
+
+    Ok(Keyframe {
+        time: nums[0],
+        pose: CameraPose {
+            eye: v(1),
+            target: v(4),
+            up: v(7),
+            fov_y: nums[10],
+        },
+        easing: fields[11].parse()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn keyframe(time: f32, eye: Vec3, easing: Easing) -> Keyframe {
+        Keyframe {
+            time,
+            pose: CameraPose {
+                eye,
+                target: Vec3::zeros(),
+                up: Vec3::y(),
+                fov_y: 1.0 + time * 0.1,
+            },
+            easing,
+        }
+    }
+
+    fn circle() -> CameraPath {
+        CameraPath::new(vec![
+            keyframe(2.0, vec3(0.0, 0.0, -4.0), Easing::EaseIn),
+            keyframe(0.0, vec3(0.0, 0.0, 4.0), Easing::Linear),
+            keyframe(1.0, vec3(4.0, 0.0, 0.0), Easing::EaseInOut),
+            keyframe(3.0, vec3(-4.0, 0.0, 0.0), Easing::EaseOut),
+        ])
+    }
+
+    #[test]
+    fn test_easing() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6);
+
+            // monotonic
+            let mut prev = 0.0;
+            for i in 1..=20 {
+                let x = easing.apply(i as f32 / 20.0);
+                assert!(x >= prev);
+                prev = x;
+            }
+
+            assert_eq!(easing.to_string().parse::<Easing>().unwrap(), easing);
+        }
+
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert!((Easing::EaseInOut.apply(0.5) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sample() {
+        let path = circle();
+        assert_eq!(path.keyframes()[0].time, 0.0);
+        assert_eq!(path.duration(), 3.0);
+        assert!(CameraPath::default().sample(0.0).is_none());
+
+        // keyframes are hit exactly, and times are clamped
+        for k in path.keyframes() {
+            let pose = path.sample(k.time).unwrap();
+            assert!((pose.eye - k.pose.eye).norm() < 1e-5);
+            assert!((pose.target - k.pose.target).norm() < 1e-4);
+            assert!((pose.up - k.pose.up).norm() < 1e-5);
+            assert!((pose.fov_y - k.pose.fov_y).abs() < 1e-6);
+        }
+        let first = path.keyframes()[0].pose;
+        assert!((path.sample(-5.0).unwrap().eye - first.eye).norm() < 1e-5);
+
+        // between keyframes, the eye stays close to the circle the
+        // keyframes are on, and the camera keeps looking inwards
+        let mut prev = path.sample(0.0).unwrap().eye;
+        for i in 1..=30 {
+            let pose = path.sample(i as f32 * 0.1).unwrap();
+            let radius = pose.eye.norm();
+            assert!(radius > 3.0 && radius < 4.5);
+
+            let forward = (pose.target - pose.eye).normalize();
+            assert!(forward.dot(&-pose.eye.normalize()) > 0.95);
+            assert!((pose.up - Vec3::y()).norm() < 1e-4);
+
+            assert!((pose.eye - prev).norm() < 2.0);
+            prev = pose.eye;
+        }
+
+        // evenly spaced keyframes on a line are followed at constant
+        // speed, from the first segment to the last
+        let line = CameraPath::new(
+            (0..4)
+                .map(|i| {
+                    let x = i as f32;
+                    keyframe(x, vec3(x, 1.0, 0.0), Easing::Linear)
+                })
+                .collect(),
+        );
+        for i in 0..=30 {
+            let t = i as f32 * 0.1;
+            let eye = line.sample(t).unwrap().eye;
+            assert!((eye - vec3(t, 1.0, 0.0)).norm() < 1e-5);
+        }
+
+        // easing slows the start of a segment down
+        let eased = line.sample(2.25).unwrap().eye;
+        let mut keys = line.keyframes().to_vec();
+        keys[2].easing = Easing::EaseIn;
+        let eased_in = CameraPath::new(keys).sample(2.25).unwrap().eye;
+        assert!(eased_in.x < eased.x);
+
+        // a single keyframe is constant
+        let single = CameraPath::new(vec![path.keyframes()[1]]);
+        let pose = single.sample(10.0).unwrap();
+        assert_eq!(pose.eye, path.keyframes()[1].pose.eye);
+
+        // playback
+        let mut player = PathPlayer::new(path.clone());
+        player.update(&CameraInput::default(), 1.5);
+        assert_eq!(player.time, 1.5);
+        player.update(&CameraInput::default(), 10.0);
+        assert!(player.is_finished());
+
+        player.looping = true;
+        player.time = 0.0;
+        player.update(&CameraInput::default(), 4.0);
+        assert!((player.time - 1.0).abs() < 1e-5);
+        assert!(!player.is_finished());
+        let [eye, ..] = player.look_at();
+        assert!((eye - vec3(4.0, 0.0, 0.0)).norm() < 1e-4);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let path = circle();
+
+        let mut buf = Vec::new();
+        path.write(&mut buf).unwrap();
+        let read = CameraPath::read(buf.as_slice()).unwrap();
+        assert_eq!(read, path);
+
+        let text = "\n# comment\n0 1 2 3 0 0 0 0 1 0 1.2 linear\n";
+        let read = CameraPath::read(text.as_bytes()).unwrap();
+        assert_eq!(read.keyframes().len(), 1);
+        assert_eq!(read.keyframes()[0].pose.eye, vec3(1.0, 2.0, 3.0));
+
+        let bad = ["0 1 2 3 0 0 0 0 1 0 1.2", "0 1 2 x 0 0 0 0 1 0 1.2 linear"];
+        for text in bad {
+            assert!(CameraPath::read(text.as_bytes()).is_err());
+        }
+        let text = "0 1 2 3 0 0 0 0 1 0 1.2 bouncy";
+        assert!(CameraPath::read(text.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use nalgebra_glm::Vec3;
+use sprs::{CsMat, TriMat};
+
+use crate::colormap::{symmetric_range, Colormap};
+
+use super::{FaceId, HalfedgeId, HalfedgeMesh, MeshError};
+
+// Discrete differential operators on triangle meshes, following
+// Meyer et al., "Discrete Differential-Geometry Operators for
+// Triangulated 2-Manifolds". The Laplacian is positive
+// semi-definite, i.e. `L[i, i] = sum_j w_ij` and `L[i, j] = -w_ij`
+// with `w_ij = (cot a_ij + cot b_ij) / 2`.
+
+/// A per-vertex curvature, see `HalfedgeMesh::curvature`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curvature {
+    Mean,
+    Gaussian,
+    MaxPrincipal,
+    MinPrincipal,
+}
+
+impl std::str::FromStr for Curvature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Self::Mean),
+            "gaussian" => Ok(Self::Gaussian),
+            "max" => Ok(Self::MaxPrincipal),
+            "min" => Ok(Self::MinPrincipal),
+            _ => Err(format!(
+                "unknown curvature {:?}, expected mean, gaussian, max or min",
+                s
+            )),
+        }
+    }
+}
+
+impl HalfedgeMesh {
+    /// The cotangent weight of each edge, indexed by edge ID. Boundary
+    /// edges only get the contribution of their single triangle.
+    pub fn cotan_weights(&self) -> Result<Vec<f32>, MeshError> {
+        self.check_triangles()?;
+
+        let weights = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let he = edge.halfedge;
+                let twin = self.halfedges[he.0].twin;
+                (self.halfedge_cotan(he) + self.halfedge_cotan(twin)) * 0.5
+            })
+            .collect();
+
+        Ok(weights)
+    }
+
+    /// The cotangent Laplacian, as a `vertex_count` square matrix
+    pub fn cotan_laplacian(&self) -> Result<CsMat<f32>, MeshError> {
+        let weights = self.cotan_weights()?;
+        let n = self.vertices.len();
+
+        let mut tri = TriMat::new((n, n));
+        let mut diagonal = vec![0.0f32; n];
+
+        for (edge, &w) in self.edges.iter().zip(weights.iter()) {
+            let he = self.halfedges[edge.halfedge.0];
+            let i = he.vertex.0;
+            let j = self.halfedge_target(he.id).0;
+
+            tri.add_triplet(i, j, -w);
+            tri.add_triplet(j, i, -w);
+            diagonal[i] += w;
+            diagonal[j] += w;
+        }
+
+        for (i, d) in diagonal.into_iter().enumerate() {
+            tri.add_triplet(i, i, d);
+        }
+
+        Ok(tri.to_csr())
+    }
+
+    /// The lumped mass matrix, with the mixed Voronoi area of each
+    /// vertex on the diagonal
+    pub fn mass_matrix(&self) -> Result<CsMat<f32>, MeshError> {
+        let areas = self.vertex_areas()?;
+        let n = areas.len();
+
+        let mut tri = TriMat::new((n, n));
+        for (i, a) in areas.into_iter().enumerate() {
+            tri.add_triplet(i, i, a);
+        }
+
+        Ok(tri.to_csr())
+    }
+
+    /// The mixed Voronoi area of each vertex; these sum to the total
+    /// surface area
+    pub fn vertex_areas(&self) -> Result<Vec<f32>, MeshError> {
+        self.check_triangles()?;
+
+        let mut areas = vec![0.0f32; self.vertices.len()];
+
+        for face in self.face_ids() {
+            let area = self.face_area(face);
+
+            let hes = self.face_halfedges(face).collect::<Vec<_>>();
+            let obtuse =
+                hes.iter().find(|&&he| self.corner_cos(he) < 0.0).copied();
+
+            for &he in hes.iter() {
+                let v = self.halfedges[he.0].vertex;
+
+                let contribution = match obtuse {
+                    None => {
+                        // the Voronoi region of the corner at v is
+                        // bounded by the two edges leaving v
+                        let prev = self.halfedge_prev(he);
+                        let out_len = self.halfedge_vector(he).norm_squared();
+                        let in_len = self.halfedge_vector(prev).norm_squared();
+                        (out_len * self.halfedge_cotan(he)
+                            + in_len * self.halfedge_cotan(prev))
+                            / 8.0
+                    }
+                    Some(o) if o == he => area / 2.0,
+                    Some(_) => area / 4.0,
+                };
+
+                areas[v.0] += contribution;
+            }
+        }
+
+        Ok(areas)
+    }
+
+    /// The mean curvature normal at each vertex, `2 H n`, pointing
+    /// away from the center of curvature for convex regions of an
+    /// outward-facing surface
+    pub fn mean_curvature_normals(&self) -> Result<Vec<Vec3>, MeshError> {
+        let weights = self.cotan_weights()?;
+        let areas = self.vertex_areas()?;
+
+        let mut lx = vec![Vec3::zeros(); self.vertices.len()];
+
+        for (edge, &w) in self.edges.iter().zip(weights.iter()) {
+            let he = edge.halfedge;
+            let i = self.halfedges[he.0].vertex;
+            let j = self.halfedge_target(he);
+            let d = (self.pos(i) - self.pos(j)) * w;
+            lx[i.0] += d;
+            lx[j.0] -= d;
+        }
+
+        let normals = lx
+            .into_iter()
+            .zip(areas)
+            .map(|(l, a)| if a > 0.0 { l / a } else { Vec3::zeros() })
+            .collect();
+
+        Ok(normals)
+    }
+
+    /// Mean curvature at each vertex, positive for convex regions
+    /// (e.g. `1 / r` on a sphere with outward normals). Boundary and
+    /// isolated vertices get zero.
+    pub fn mean_curvature(&self) -> Result<Vec<f32>, MeshError> {
+        let normals = self.mean_curvature_normals()?;
+
+        let curvature = self
+            .vertices()
+            .zip(normals)
+            .map(|(vertex, hn)| {
+                if vertex.on_boundary() {
+                    0.0
+                } else {
+                    0.5 * hn.dot(&vertex.normal())
+                }
+            })
+            .collect();
+
+        Ok(curvature)
+    }
+
+    /// Gaussian curvature at each vertex, as the angle defect divided
+    /// by the vertex area. Boundary vertices use a defect relative to
+    /// `pi` instead of `2 pi`.
+    pub fn gaussian_curvature(&self) -> Result<Vec<f32>, MeshError> {
+        let areas = self.vertex_areas()?;
+
+        let curvature = self
+            .vertices()
+            .zip(areas)
+            .map(|(vertex, area)| {
+                if area <= 0.0 {
+                    return 0.0;
+                }
+
+                let angle_sum: f32 = vertex
+                    .outgoing_halfedges()
+                    .filter(|he| {
+                        let face = self.halfedges[he.0].face;
+                        !self.faces[face.0].is_boundary
+                    })
+                    .map(|he| self.corner_angle(he))
+                    .sum();
+
+                let full = if vertex.on_boundary() {
+                    std::f32::consts::PI
+                } else {
+                    2.0 * std::f32::consts::PI
+                };
+
+                (full - angle_sum) / area
+            })
+            .collect();
+
+        Ok(curvature)
+    }
+
+    /// The principal curvatures `[k_max, k_min]` at each vertex,
+    /// derived from the mean and Gaussian curvatures
+    pub fn principal_curvatures(&self) -> Result<Vec<[f32; 2]>, MeshError> {
+        let mean = self.mean_curvature()?;
+        let gaussian = self.gaussian_curvature()?;
+
+        let curvatures = mean
+            .into_iter()
+            .zip(gaussian)
+            .map(|(h, k)| {
+                let d = (h * h - k).max(0.0).sqrt();
+                [h + d, h - d]
+            })
+            .collect();
+
+        Ok(curvatures)
+    }
+
+    pub fn curvature(&self, kind: Curvature) -> Result<Vec<f32>, MeshError> {
+        let principal = |ix: usize| -> Result<Vec<f32>, MeshError> {
+            let curvatures = self.principal_curvatures()?;
+            Ok(curvatures.into_iter().map(|k| k[ix]).collect())
+        };
+
+        match kind {
+            Curvature::Mean => self.mean_curvature(),
+            Curvature::Gaussian => self.gaussian_curvature(),
+            Curvature::MaxPrincipal => principal(0),
+            Curvature::MinPrincipal => principal(1),
+        }
+    }
+
+    /// The curvature at each vertex mapped to `colormap`, over a range
+    /// centered on zero, so that flat regions get the middle color.
+    /// Indexed by vertex ID, for the color closure of `vertex_data`.
+    pub fn curvature_colors(
+        &self,
+        kind: Curvature,
+        colormap: &Colormap,
+    ) -> Result<Vec<[f32; 4]>, MeshError> {
+        let values = self.curvature(kind)?;
+        let range = symmetric_range(&values);
+        Ok(colormap.map_values(&values, Some(range)))
+    }
+
+    fn halfedge_vector(&self, he: HalfedgeId) -> Vec3 {
+        let from = self.halfedges[he.0].vertex;
+        self.pos(self.halfedge_target(he)) - self.pos(from)
+    }
+
+    // has the same sign as the cosine of the corner angle at the
+    // source of `he`
+    fn corner_cos(&self, he: HalfedgeId) -> f32 {
+        let out = self.halfedge_vector(he);
+        let back = -self.halfedge_vector(self.halfedge_prev(he));
+        out.dot(&back)
+    }
+
+    // the cotangent of the angle opposite to `he` in its triangle,
+    // or zero if `he` is a boundary halfedge
+    fn halfedge_cotan(&self, he: HalfedgeId) -> f32 {
+        let face: FaceId = self.halfedges[he.0].face;
+        if self.faces[face.0].is_boundary {
+            return 0.0;
+        }
+
+        let o = self.pos(self.opposite_vertex(he));
+        let a = self.pos(self.halfedges[he.0].vertex) - o;
+        let b = self.pos(self.halfedge_target(he)) - o;
+
+        let cross = a.cross(&b).norm();
+        if cross <= f32::EPSILON {
+            0.0
+        } else {
+            a.dot(&b) / cross
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::test_meshes::{grid, sphere};
+
+    #[test]
+    fn test_cotan_laplacian() {
+        let mesh = sphere(2);
+        let n = mesh.vertex_count();
+
+        let lap = mesh.cotan_laplacian().unwrap();
+        assert_eq!(lap.shape(), (n, n));
+
+        for (row_ix, row) in lap.outer_iterator().enumerate() {
+            let sum: f32 = row.iter().map(|(_, v)| v).sum();
+            assert!(sum.abs() < 1e-5);
+
+            for (col_ix, &v) in row.iter() {
+                let t = *lap.get(col_ix, row_ix).unwrap();
+                assert!((v - t).abs() < 1e-6);
+            }
+        }
+
+        let mass = mesh.mass_matrix().unwrap();
+        let total: f32 = mass.diag().data().iter().sum();
+        let surface: f32 = mesh.face_ids().map(|f| mesh.face_area(f)).sum();
+        assert!((total - surface).abs() < 1e-4);
+        // an inscribed polyhedron, slightly smaller than 4 pi r^2
+        let sphere_area = 4.0 * std::f32::consts::PI;
+        assert!(total < sphere_area && total > 0.9 * sphere_area);
+    }
+
+    #[test]
+    fn test_curvature() {
+        let mesh = sphere(3);
+
+        let mean = mesh.mean_curvature().unwrap();
+        let gaussian = mesh.gaussian_curvature().unwrap();
+        let principal = mesh.principal_curvatures().unwrap();
+
+        for ((h, k), [k1, k2]) in mean.iter().zip(&gaussian).zip(&principal) {
+            assert!((h - 1.0).abs() < 0.1, "mean curvature {}", h);
+            assert!((k - 1.0).abs() < 0.1, "gaussian curvature {}", k);
+            assert!(k1 >= k2);
+            assert!((k1 - 1.0).abs() < 0.35 && (k2 - 1.0).abs() < 0.35);
+        }
+
+        // Gauss-Bonnet: the total curvature of a sphere is 4 pi
+        let areas = mesh.vertex_areas().unwrap();
+        let total: f32 = gaussian.iter().zip(areas).map(|(k, a)| k * a).sum();
+        assert!((total - 4.0 * std::f32::consts::PI).abs() < 1e-3);
+
+        // a flat grid has no curvature in its interior
+        let grid = grid(4);
+        let mean = grid.mean_curvature().unwrap();
+        let gaussian = grid.gaussian_curvature().unwrap();
+        for v in [5, 6, 9, 10] {
+            assert!(mean[v].abs() < 1e-5);
+            assert!(gaussian[v].abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_curvature_colors() {
+        let colormap = Colormap::diverging();
+        let mid = colormap.color(0.5);
+        let warm = colormap.color(0.75);
+
+        // the sphere is curved the same way everywhere, so each
+        // vertex is close to the positive end, where the blue
+        // component keeps decreasing
+        let mesh = sphere(3);
+        for kind in [Curvature::Mean, Curvature::Gaussian] {
+            let colors = mesh.curvature_colors(kind, &colormap).unwrap();
+            assert_eq!(colors.len(), mesh.vertex_count());
+            assert!(colors.iter().all(|c| c[2] < warm[2]));
+        }
+
+        // a flat plane has no curvature to scale to
+        let colors = grid(2)
+            .curvature_colors(Curvature::MinPrincipal, &colormap)
+            .unwrap();
+        assert!(colors.iter().all(|&c| c == mid));
+
+        assert_eq!("max".parse(), Ok(Curvature::MaxPrincipal));
+        assert!("mean curvature".parse::<Curvature>().is_err());
+    }
+}
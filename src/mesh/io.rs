@@ -0,0 +1,1084 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use nalgebra_glm::{vec3, Vec3};
+use rustc_hash::FxHashMap;
+
+use super::{normalize_or_zero, HalfedgeMesh};
+use crate::vertex::MeshVertex;
+
+/// Used for vertices without colors when building vertex data
+const DEFAULT_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// An indexed triangle list with optional per-vertex attributes, as
+/// read from and written to OBJ, PLY and STL files.
+///
+/// Unlike `HalfedgeMesh`, any triangle soup can be represented.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    /// Either empty, or one normal per position
+    pub normals: Vec<Vec3>,
+    /// Either empty, or one RGBA color per position
+    pub colors: Vec<[f32; 4]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// Whether PLY and STL files are written as text or binary; OBJ
+/// files are always text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Ascii,
+    Binary,
+}
+
+impl TriangleMesh {
+    /// Fan-triangulates the faces of `mesh`, keeping vertex IDs as
+    /// indices and using area-weighted vertex normals
+    pub fn from_halfedge(mesh: &HalfedgeMesh) -> Self {
+        let positions = mesh.vertices().map(|v| v.center()).collect();
+        let normals = mesh.vertices().map(|v| v.normal()).collect();
+
+        let mut triangles = Vec::with_capacity(mesh.face_count());
+        for face in mesh.face_ids() {
+            let verts = mesh.face_vertices(face).map(|v| v.0);
+            fan_triangles(&mut triangles, verts);
+        }
+
+        Self {
+            positions,
+            normals,
+            colors: Vec::new(),
+            triangles,
+        }
+    }
+
+    /// Builds a `HalfedgeMesh` with the same vertex indices; fails if
+    /// the triangles don't form a manifold surface
+    pub fn to_halfedge(&self) -> anyhow::Result<HalfedgeMesh> {
+        HalfedgeMesh::from_triangles(
+            self.positions.iter().copied(),
+            self.triangles.iter().copied(),
+        )
+    }
+
+    /// The stored normals, or area-weighted normals computed from the
+    /// triangles if there are none
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        if self.normals.len() == self.positions.len() {
+            return self.normals.clone();
+        }
+
+        let mut normals = vec![Vec3::zeros(); self.positions.len()];
+
+        for &[a, b, c] in self.triangles.iter() {
+            let [pa, pb, pc] = [a, b, c].map(|i| self.positions[i]);
+            let n = (pb - pa).cross(&(pc - pa));
+            for i in [a, b, c] {
+                normals[i] += n;
+            }
+        }
+
+        normals.into_iter().map(normalize_or_zero).collect()
+    }
+
+    /// Fills `buf` with vertex data for the `tri-3d` sublayer, and
+    /// `indices` with the triangle indices
+    pub fn vertex_data(
+        &self,
+        buf: &mut Vec<MeshVertex>,
+        indices: &mut Vec<u32>,
+    ) {
+        buf.clear();
+        indices.clear();
+
+        let normals = self.vertex_normals();
+
+        for (i, (&pos, &normal)) in
+            self.positions.iter().zip(normals.iter()).enumerate()
+        {
+            let color = self.colors.get(i).copied().unwrap_or(DEFAULT_COLOR);
+            buf.push(MeshVertex::new(pos, normal, color));
+        }
+
+        indices.extend(self.triangles.iter().flatten().map(|&i| i as u32));
+    }
+
+    /// Reads an OBJ, PLY or STL file, depending on the extension
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Opening mesh file {:?}", path))?;
+        let reader = BufReader::new(file);
+
+        let result = match extension(path).as_deref() {
+            Some("obj") => Self::read_obj(reader),
+            Some("ply") => Self::read_ply(reader),
+            Some("stl") => Self::read_stl(reader),
+            _ => Err(anyhow!("Unknown mesh file extension")),
+        };
+
+        result.with_context(|| format!("Reading mesh file {:?}", path))
+    }
+
+    /// Writes an OBJ, PLY or STL file, depending on the extension
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        encoding: Encoding,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let ext = extension(path);
+        if !matches!(ext.as_deref(), Some("obj" | "ply" | "stl")) {
+            bail!("Unknown mesh file extension: {:?}", path);
+        }
+
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Creating mesh file {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+
+        match ext.as_deref() {
+            Some("obj") => self.write_obj(&mut writer)?,
+            Some("ply") => self.write_ply(&mut writer, encoding)?,
+            _ => self.write_stl(&mut writer, encoding)?,
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let n = self.positions.len();
+
+        if !self.normals.is_empty() && self.normals.len() != n {
+            bail!("Expected {} normals, found {}", n, self.normals.len());
+        }
+
+        if !self.colors.is_empty() && self.colors.len() != n {
+            bail!("Expected {} colors, found {}", n, self.colors.len());
+        }
+
+        for (i, tri) in self.triangles.iter().enumerate() {
+            if let Some(&v) = tri.iter().find(|&&v| v >= n) {
+                bail!(
+                    "Triangle {} refers to vertex {}, but there are only {}",
+                    i,
+                    v,
+                    n
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a Wavefront OBJ file. Polygons are fan-triangulated, and
+    /// `v x y z r g b` vertex colors are supported. Normals are
+    /// assigned to vertices through the face corners that refer to
+    /// them.
+    pub fn read_obj(reader: impl BufRead) -> anyhow::Result<Self> {
+        let mut mesh = Self::default();
+
+        let mut colors: Vec<Option<[f32; 4]>> = Vec::new();
+        let mut file_normals: Vec<Vec3> = Vec::new();
+        let mut normals: Vec<Option<Vec3>> = Vec::new();
+
+        let mut corners = Vec::new();
+
+        for (line_ix, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+
+            let context = || format!("OBJ line {}", line_ix + 1);
+
+            match fields.next() {
+                Some("v") => {
+                    let values = parse_floats(fields).with_context(context)?;
+                    let color = match values.len() {
+                        3 | 4 => None,
+                        6 | 7 => Some([values[3], values[4], values[5], 1.0]),
+                        _ => {
+                            return Err(anyhow!("Bad vertex"))
+                                .with_context(context)
+                        }
+                    };
+                    mesh.positions.push(vec3(values[0], values[1], values[2]));
+                    colors.push(color);
+                    normals.push(None);
+                }
+                Some("vn") => {
+                    let values = parse_floats(fields).with_context(context)?;
+                    if values.len() != 3 {
+                        return Err(anyhow!("Bad normal"))
+                            .with_context(context);
+                    }
+                    file_normals.push(vec3(values[0], values[1], values[2]));
+                }
+                Some("f") => {
+                    corners.clear();
+
+                    for corner in fields {
+                        let mut parts = corner.split('/');
+                        let v = obj_index(parts.next(), mesh.positions.len())
+                            .with_context(context)?;
+
+                        if let Some(n) = parts.nth(1) {
+                            let n = obj_index(Some(n), file_normals.len())
+                                .with_context(context)?;
+                            normals[v] = Some(file_normals[n]);
+                        }
+
+                        corners.push(v);
+                    }
+
+                    if corners.len() < 3 {
+                        return Err(anyhow!("Face with fewer than 3 vertices"))
+                            .with_context(context);
+                    }
+
+                    fan_triangles(&mut mesh.triangles, corners.iter().copied());
+                }
+                // texture coordinates, groups, materials, etc.
+                _ => (),
+            }
+        }
+
+        if colors.iter().all(|c| c.is_some()) {
+            mesh.colors = colors.into_iter().flatten().collect();
+        }
+
+        if normals.iter().all(|n| n.is_some()) {
+            mesh.normals = normals.into_iter().flatten().collect();
+        }
+
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    /// Writes a Wavefront OBJ file, with vertex colors (without
+    /// alpha) and normals if the mesh has them
+    pub fn write_obj(&self, mut writer: impl Write) -> anyhow::Result<()> {
+        self.validate()?;
+
+        for (i, p) in self.positions.iter().enumerate() {
+            write!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+            if let Some([r, g, b, _]) = self.colors.get(i) {
+                write!(writer, " {} {} {}", r, g, b)?;
+            }
+            writeln!(writer)?;
+        }
+
+        for n in self.normals.iter() {
+            writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+
+        let has_normals = !self.normals.is_empty();
+
+        for tri in self.triangles.iter() {
+            write!(writer, "f")?;
+            for i in tri.map(|i| i + 1) {
+                if has_normals {
+                    write!(writer, " {}//{}", i, i)?;
+                } else {
+                    write!(writer, " {}", i)?;
+                }
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads an ASCII or binary PLY file. Vertex positions, normals
+    /// (`nx`, `ny`, `nz`) and colors (`red`, `green`, `blue`, and
+    /// optionally `alpha`) are read, and faces are fan-triangulated.
+    /// Other properties and elements are skipped.
+    pub fn read_ply(mut reader: impl BufRead) -> anyhow::Result<Self> {
+        let header = PlyHeader::read(&mut reader)?;
+        let mut data = PlyData::new(reader, header.format);
+
+        let mut mesh = Self::default();
+
+        for element in header.elements.iter() {
+            match element.name.as_str() {
+                "vertex" => mesh.read_ply_vertices(&mut data, element)?,
+                "face" => mesh.read_ply_faces(&mut data, element)?,
+                _ => {
+                    for _ in 0..element.count {
+                        for prop in element.properties.iter() {
+                            data.skip_property(prop)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    fn read_ply_vertices(
+        &mut self,
+        data: &mut PlyData<impl BufRead>,
+        element: &PlyElement,
+    ) -> anyhow::Result<()> {
+        let has = |name: &str| element.property(name).is_some();
+
+        let has_normals = has("nx") && has("ny") && has("nz");
+        let has_colors = has("red") && has("green") && has("blue");
+
+        // integer color channels range from 0 to 255
+        let color_max = match element.property("red") {
+            Some(PlyProperty::Scalar { ty, .. }) if ty.is_float() => 1.0,
+            _ => 255.0,
+        };
+
+        let mut values: FxHashMap<&str, f64> = FxHashMap::default();
+
+        for _ in 0..element.count {
+            values.clear();
+
+            for prop in element.properties.iter() {
+                match prop {
+                    PlyProperty::Scalar { name, ty } => {
+                        values.insert(name, data.read(*ty)?);
+                    }
+                    list => data.skip_property(list)?,
+                }
+            }
+
+            let get = |name: &str| values.get(name).copied().unwrap_or(0.0);
+            let get_vec3 = |[x, y, z]: [&str; 3]| {
+                vec3(get(x) as f32, get(y) as f32, get(z) as f32)
+            };
+
+            self.positions.push(get_vec3(["x", "y", "z"]));
+
+            if has_normals {
+                self.normals.push(get_vec3(["nx", "ny", "nz"]));
+            }
+
+            if has_colors {
+                let c = |name| (get(name) / color_max) as f32;
+                let alpha = if has("alpha") { c("alpha") } else { 1.0 };
+                self.colors.push([c("red"), c("green"), c("blue"), alpha]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_ply_faces(
+        &mut self,
+        data: &mut PlyData<impl BufRead>,
+        element: &PlyElement,
+    ) -> anyhow::Result<()> {
+        let mut corners = Vec::new();
+
+        for _ in 0..element.count {
+            for prop in element.properties.iter() {
+                match prop {
+                    PlyProperty::List { name, count, item }
+                        if name == "vertex_indices"
+                            || name == "vertex_index" =>
+                    {
+                        let len = data.read(*count)? as usize;
+                        corners.clear();
+                        for _ in 0..len {
+                            corners.push(data.read(*item)? as usize);
+                        }
+                        if len < 3 {
+                            bail!("PLY face with fewer than 3 vertices");
+                        }
+                        fan_triangles(
+                            &mut self.triangles,
+                            corners.iter().copied(),
+                        );
+                    }
+                    other => data.skip_property(other)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a PLY file with float positions and normals, `uchar`
+    /// RGBA colors, and `int` vertex indices
+    pub fn write_ply(
+        &self,
+        mut writer: impl Write,
+        encoding: Encoding,
+    ) -> anyhow::Result<()> {
+        self.validate()?;
+
+        let format = match encoding {
+            Encoding::Ascii => "ascii",
+            Encoding::Binary => "binary_little_endian",
+        };
+
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format {} 1.0", format)?;
+        writeln!(writer, "element vertex {}", self.positions.len())?;
+        for name in ["x", "y", "z"] {
+            writeln!(writer, "property float {}", name)?;
+        }
+        if !self.normals.is_empty() {
+            for name in ["nx", "ny", "nz"] {
+                writeln!(writer, "property float {}", name)?;
+            }
+        }
+        if !self.colors.is_empty() {
+            for name in ["red", "green", "blue", "alpha"] {
+                writeln!(writer, "property uchar {}", name)?;
+            }
+        }
+        writeln!(writer, "element face {}", self.triangles.len())?;
+        writeln!(writer, "property list uchar int vertex_indices")?;
+        writeln!(writer, "end_header")?;
+
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        for (i, p) in self.positions.iter().enumerate() {
+            let mut floats = vec![p.x, p.y, p.z];
+            if let Some(n) = self.normals.get(i) {
+                floats.extend([n.x, n.y, n.z]);
+            }
+            let color = self.colors.get(i).map(|c| c.map(to_u8));
+
+            match encoding {
+                Encoding::Ascii => {
+                    let mut fields = floats
+                        .iter()
+                        .map(|f| f.to_string())
+                        .collect::<Vec<_>>();
+                    if let Some(color) = color {
+                        fields.extend(color.iter().map(|c| c.to_string()));
+                    }
+                    writeln!(writer, "{}", fields.join(" "))?;
+                }
+                Encoding::Binary => {
+                    for f in floats {
+                        writer.write_all(&f.to_le_bytes())?;
+                    }
+                    if let Some(color) = color {
+                        writer.write_all(&color)?;
+                    }
+                }
+            }
+        }
+
+        for &[a, b, c] in self.triangles.iter() {
+            match encoding {
+                Encoding::Ascii => writeln!(writer, "3 {} {} {}", a, b, c)?,
+                Encoding::Binary => {
+                    writer.write_all(&[3])?;
+                    for i in [a, b, c] {
+                        writer.write_all(&(i as i32).to_le_bytes())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads an ASCII or binary STL file. STL stores each triangle
+    /// separately, so vertices with identical positions are merged;
+    /// the per-facet normals are ignored.
+    pub fn read_stl(mut reader: impl BufRead) -> anyhow::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        // binary files may also start with "solid", so the size is
+        // checked first
+        let binary_count = bytes
+            .get(80..84)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+        let is_binary =
+            matches!(binary_count, Some(n) if 84 + n * 50 == bytes.len());
+
+        let corners = if is_binary {
+            bytes[84..]
+                .chunks_exact(50)
+                .flat_map(|facet| {
+                    // skip the normal and the attribute byte count
+                    (0..3).map(move |i| {
+                        let offset = 12 + i * 12;
+                        read_f32_vec3(&facet[offset..offset + 12])
+                    })
+                })
+                .collect::<Vec<_>>()
+        } else {
+            let text = std::str::from_utf8(&bytes)
+                .context("STL file is neither binary nor ASCII")?;
+
+            if !text.trim_start().starts_with("solid") {
+                bail!("ASCII STL file must start with \"solid\"");
+            }
+
+            let mut corners = Vec::new();
+            for (line_ix, line) in text.lines().enumerate() {
+                let mut fields = line.split_whitespace();
+                if fields.next() == Some("vertex") {
+                    let values = parse_floats(fields)
+                        .with_context(|| format!("STL line {}", line_ix + 1))?;
+                    if values.len() != 3 {
+                        bail!("Bad vertex on STL line {}", line_ix + 1);
+                    }
+                    corners.push(vec3(values[0], values[1], values[2]));
+                }
+            }
+
+            if corners.len() % 3 != 0 {
+                bail!("STL vertex count is not a multiple of 3");
+            }
+
+            corners
+        };
+
+        let mut mesh = Self::default();
+        let mut vertex_ix: FxHashMap<[u32; 3], usize> = FxHashMap::default();
+
+        let mut index = |p: Vec3| {
+            let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+            *vertex_ix.entry(key).or_insert_with(|| {
+                mesh.positions.push(p);
+                mesh.positions.len() - 1
+            })
+        };
+
+        let triangles = corners
+            .chunks_exact(3)
+            .map(|t| [index(t[0]), index(t[1]), index(t[2])])
+            .collect();
+        mesh.triangles = triangles;
+
+        mesh.validate()?;
+        Ok(mesh)
+    }
+
+    /// Writes an STL file, with facet normals computed from the
+    /// triangles. Colors and vertex normals are not stored.
+    pub fn write_stl(
+        &self,
+        mut writer: impl Write,
+        encoding: Encoding,
+    ) -> anyhow::Result<()> {
+        self.validate()?;
+
+        let facets = self.triangles.iter().map(|&[a, b, c]| {
+            let [pa, pb, pc] = [a, b, c].map(|i| self.positions[i]);
+            let normal = normalize_or_zero((pb - pa).cross(&(pc - pa)));
+            (normal, [pa, pb, pc])
+        });
+
+        match encoding {
+            Encoding::Ascii => {
+                writeln!(writer, "solid mesh")?;
+                for (n, corners) in facets {
+                    writeln!(writer, "facet normal {} {} {}", n.x, n.y, n.z)?;
+                    writeln!(writer, "  outer loop")?;
+                    for p in corners {
+                        writeln!(writer, "    vertex {} {} {}", p.x, p.y, p.z)?;
+                    }
+                    writeln!(writer, "  endloop")?;
+                    writeln!(writer, "endfacet")?;
+                }
+                writeln!(writer, "endsolid mesh")?;
+            }
+            Encoding::Binary => {
+                writer.write_all(&[0u8; 80])?;
+                writer
+                    .write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+                for (n, corners) in facets {
+                    for v in std::iter::once(n).chain(corners) {
+                        for f in v.iter() {
+                            writer.write_all(&f.to_le_bytes())?;
+                        }
+                    }
+                    writer.write_all(&[0u8; 2])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    let ext = path.extension()?.to_str()?;
+    Some(ext.to_ascii_lowercase())
+}
+
+fn fan_triangles(
+    triangles: &mut Vec<[usize; 3]>,
+    polygon: impl IntoIterator<Item = usize>,
+) {
+    let mut polygon = polygon.into_iter();
+
+    if let (Some(first), Some(mut prev)) = (polygon.next(), polygon.next()) {
+        for next in polygon {
+            triangles.push([first, prev, next]);
+            prev = next;
+        }
+    }
+}
+
+fn parse_floats<'a>(
+    fields: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<Vec<f32>> {
+    fields
+        .map(|f| {
+            f.parse::<f32>()
+                .map_err(|_| anyhow!("Expected a number, found {:?}", f))
+        })
+        .collect()
+}
+
+// OBJ indices start at 1, and negative indices are relative to the
+// end of the list so far
+fn obj_index(field: Option<&str>, len: usize) -> anyhow::Result<usize> {
+    let field = field.ok_or_else(|| anyhow!("Missing index"))?;
+    let i: i64 = field
+        .parse()
+        .map_err(|_| anyhow!("Expected an index, found {:?}", field))?;
+
+    let ix = if i < 0 { len as i64 + i } else { i - 1 };
+
+    if i == 0 || ix < 0 || ix >= len as i64 {
+        bail!("Index {} out of bounds", i);
+    }
+
+    Ok(ix as usize)
+}
+
+fn read_f32_vec3(bytes: &[u8]) -> Vec3 {
+    let f = |i: usize| {
+        f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+    };
+    vec3(f(0), f(4), f(8))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        let ty = match s {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => bail!("Unknown PLY property type {:?}", s),
+        };
+        Ok(ty)
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PlyProperty {
+    Scalar {
+        name: String,
+        ty: PlyType,
+    },
+    List {
+        name: String,
+        count: PlyType,
+        item: PlyType,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    fn property(&self, name: &str) -> Option<&PlyProperty> {
+        self.properties.iter().find(|p| match p {
+            PlyProperty::Scalar { name: n, .. } => n == name,
+            PlyProperty::List { name: n, .. } => n == name,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PlyHeader {
+    format: PlyFormat,
+    elements: Vec<PlyElement>,
+}
+
+impl PlyHeader {
+    fn read(reader: &mut impl BufRead) -> anyhow::Result<Self> {
+        let mut line = String::new();
+
+        let mut next_line = |line: &mut String| -> anyhow::Result<()> {
+            line.clear();
+            if reader.read_line(line)? == 0 {
+                bail!("Unexpected end of PLY header");
+            }
+            Ok(())
+        };
+
+        next_line(&mut line)?;
+        if line.trim_end() != "ply" {
+            bail!("Not a PLY file");
+        }
+
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = Vec::new();
+
+        loop {
+            next_line(&mut line)?;
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+
+            match fields.as_slice() {
+                ["end_header"] => break,
+                ["format", f, _version] => {
+                    format = Some(match *f {
+                        "ascii" => PlyFormat::Ascii,
+                        "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                        "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                        _ => bail!("Unknown PLY format {:?}", f),
+                    });
+                }
+                ["element", name, count] => {
+                    elements.push(PlyElement {
+                        name: name.to_string(),
+                        count: count.parse()?,
+                        properties: Vec::new(),
+                    });
+                }
+                ["property", "list", count, item, name] => {
+                    let element = elements.last_mut().ok_or_else(|| {
+                        anyhow!("PLY property before element")
+                    })?;
+                    element.properties.push(PlyProperty::List {
+                        name: name.to_string(),
+                        count: PlyType::parse(count)?,
+                        item: PlyType::parse(item)?,
+                    });
+                }
+                ["property", ty, name] => {
+                    let element = elements.last_mut().ok_or_else(|| {
+                        anyhow!("PLY property before element")
+                    })?;
+                    element.properties.push(PlyProperty::Scalar {
+                        name: name.to_string(),
+                        ty: PlyType::parse(ty)?,
+                    });
+                }
+                ["comment", ..] | ["obj_info", ..] | [] => (),
+                _ => bail!("Bad PLY header line {:?}", line.trim_end()),
+            }
+        }
+
+        let format =
+            format.ok_or_else(|| anyhow!("PLY header has no format"))?;
+
+        Ok(Self { format, elements })
+    }
+}
+
+// reads property values from the body of a PLY file
+struct PlyData<R> {
+    reader: R,
+    format: PlyFormat,
+    tokens: VecDeque<String>,
+}
+
+impl<R: BufRead> PlyData<R> {
+    fn new(reader: R, format: PlyFormat) -> Self {
+        Self {
+            reader,
+            format,
+            tokens: VecDeque::new(),
+        }
+    }
+
+    fn read(&mut self, ty: PlyType) -> anyhow::Result<f64> {
+        if self.format == PlyFormat::Ascii {
+            let token = self.next_token()?;
+            return token
+                .parse()
+                .map_err(|_| anyhow!("Expected a number, found {:?}", token));
+        }
+
+        let mut bytes = [0u8; 8];
+        let bytes = &mut bytes[..ty.size()];
+        self.reader
+            .read_exact(bytes)
+            .context("Unexpected end of PLY data")?;
+
+        if self.format == PlyFormat::BinaryBigEndian {
+            bytes.reverse();
+        }
+
+        let value = match ty {
+            PlyType::I8 => bytes[0] as i8 as f64,
+            PlyType::U8 => bytes[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::I32 => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                    as f64
+            }
+            PlyType::U32 => {
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                    as f64
+            }
+            PlyType::F32 => {
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                    as f64
+            }
+            PlyType::F64 => {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(bytes);
+                f64::from_le_bytes(b)
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn skip_property(&mut self, prop: &PlyProperty) -> anyhow::Result<()> {
+        match prop {
+            PlyProperty::Scalar { ty, .. } => {
+                self.read(*ty)?;
+            }
+            PlyProperty::List { count, item, .. } => {
+                let len = self.read(*count)? as usize;
+                for _ in 0..len {
+                    self.read(*item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> anyhow::Result<String> {
+        let mut line = String::new();
+
+        while self.tokens.is_empty() {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("Unexpected end of PLY data");
+            }
+            self.tokens
+                .extend(line.split_whitespace().map(String::from));
+        }
+
+        Ok(self.tokens.pop_front().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TETRAHEDRON_OBJ: &str =
+        include_str!("../../fixtures/tetrahedron.obj");
+    const SQUARE_PLY: &str = include_str!("../../fixtures/square.ply");
+    const TETRAHEDRON_STL: &str =
+        include_str!("../../fixtures/tetrahedron.stl");
+
+    fn tetrahedron() -> TriangleMesh {
+        TriangleMesh::read_obj(TETRAHEDRON_OBJ.as_bytes()).unwrap()
+    }
+
+    fn round_trip<W, R>(mesh: &TriangleMesh, write: W, read: R) -> TriangleMesh
+    where
+        W: Fn(&TriangleMesh, &mut Vec<u8>) -> anyhow::Result<()>,
+        R: Fn(&[u8]) -> anyhow::Result<TriangleMesh>,
+    {
+        let mut bytes = Vec::new();
+        write(mesh, &mut bytes).unwrap();
+        read(&bytes).unwrap()
+    }
+
+    // STL files don't keep indices, so triangles are compared by the
+    // positions of their corners
+    fn triangle_positions(mesh: &TriangleMesh) -> Vec<[Vec3; 3]> {
+        mesh.triangles
+            .iter()
+            .map(|t| t.map(|i| mesh.positions[i]))
+            .collect()
+    }
+
+    #[test]
+    fn test_obj() {
+        let mesh = tetrahedron();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles.len(), 4);
+        assert_eq!(mesh.normals.len(), 4);
+        assert_eq!(mesh.colors[1], [1.0, 0.0, 0.0, 1.0]);
+        // the last face uses negative indices
+        assert_eq!(mesh.triangles[3], [1, 2, 3]);
+
+        let read = round_trip(
+            &mesh,
+            |m, w| m.write_obj(w),
+            |b| TriangleMesh::read_obj(b),
+        );
+        assert_eq!(read, mesh);
+
+        // quads are triangulated, and vertices without colors discard
+        // all colors
+        let obj = "v 0 0 0\nv 1 0 0 1 1 1\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+        let quad = TriangleMesh::read_obj(obj.as_bytes()).unwrap();
+        assert_eq!(quad.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(quad.colors.is_empty());
+
+        assert!(
+            TriangleMesh::read_obj("v 0 0 0\nf 1 2 3\n".as_bytes()).is_err()
+        );
+        assert!(TriangleMesh::read_obj("v 0 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_ply() {
+        let square = TriangleMesh::read_ply(SQUARE_PLY.as_bytes()).unwrap();
+        assert_eq!(square.positions.len(), 4);
+        assert_eq!(square.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(square.normals, vec![vec3(0.0, 0.0, 1.0); 4]);
+        assert_eq!(square.colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(square.colors[3], [1.0, 1.0, 1.0, 1.0]);
+
+        for mesh in [square, tetrahedron()] {
+            for encoding in [Encoding::Ascii, Encoding::Binary] {
+                let read = round_trip(
+                    &mesh,
+                    |m, w| m.write_ply(w, encoding),
+                    |b| TriangleMesh::read_ply(b),
+                );
+                assert_eq!(read, mesh);
+            }
+        }
+
+        // big endian, with double positions and extra properties
+        let mut bytes = b"ply\nformat binary_big_endian 1.0\n\
+                          element vertex 3\nproperty double x\n\
+                          property double y\nproperty double z\n\
+                          property ushort confidence\n\
+                          element face 1\n\
+                          property list uchar uint vertex_indices\n\
+                          end_header\n"
+            .to_vec();
+        for (i, p) in [[0.0f64, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .iter()
+            .enumerate()
+        {
+            for x in p {
+                bytes.extend(x.to_be_bytes());
+            }
+            bytes.extend((i as u16).to_be_bytes());
+        }
+        bytes.push(3);
+        for i in [0u32, 1, 2] {
+            bytes.extend(i.to_be_bytes());
+        }
+
+        let tri = TriangleMesh::read_ply(bytes.as_slice()).unwrap();
+        assert_eq!(tri.positions[1], vec3(1.0, 0.0, 0.0));
+        assert_eq!(tri.triangles, vec![[0, 1, 2]]);
+
+        assert!(TriangleMesh::read_ply(&bytes[..bytes.len() - 1]).is_err());
+        assert!(TriangleMesh::read_ply("solid\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_stl() {
+        let stl = TriangleMesh::read_stl(TETRAHEDRON_STL.as_bytes()).unwrap();
+        assert_eq!(stl.positions.len(), 4);
+        assert_eq!(stl.triangles.len(), 4);
+
+        let mesh = tetrahedron();
+        assert_eq!(triangle_positions(&stl), triangle_positions(&mesh));
+
+        for encoding in [Encoding::Ascii, Encoding::Binary] {
+            let read = round_trip(
+                &mesh,
+                |m, w| m.write_stl(w, encoding),
+                |b| TriangleMesh::read_stl(b),
+            );
+            assert_eq!(triangle_positions(&read), triangle_positions(&mesh));
+            assert!(read.normals.is_empty() && read.colors.is_empty());
+        }
+
+        // binary files can start with "solid" too
+        let mut bytes = Vec::new();
+        mesh.write_stl(&mut bytes, Encoding::Binary).unwrap();
+        bytes[..5].copy_from_slice(b"solid");
+        let read = TriangleMesh::read_stl(bytes.as_slice()).unwrap();
+        assert_eq!(read.triangles.len(), 4);
+    }
+
+    #[test]
+    fn test_halfedge_conversion() {
+        let mesh = tetrahedron();
+
+        let halfedge = mesh.to_halfedge().unwrap();
+        assert_eq!(halfedge.vertex_count(), 4);
+        assert_eq!(halfedge.face_count(), 4);
+        assert_eq!(halfedge.boundary_count(), 0);
+
+        let back = TriangleMesh::from_halfedge(&halfedge);
+        assert_eq!(back.positions, mesh.positions);
+        assert_eq!(triangle_positions(&back).len(), 4);
+
+        // the computed normals of a closed, convex mesh point outwards
+        let center = mesh.positions.iter().sum::<Vec3>() / 4.0;
+        for (p, n) in back.positions.iter().zip(back.vertex_normals()) {
+            assert!((p - center).dot(&n) > 0.0);
+        }
+
+        let mut buf = Vec::new();
+        let mut indices = Vec::new();
+        mesh.vertex_data(&mut buf, &mut indices);
+        assert_eq!(buf.len(), 4);
+        assert_eq!(indices.len(), 12);
+    }
+}
@@ -0,0 +1,646 @@
+use nalgebra_glm::Vec3;
+use rustc_hash::FxHashSet;
+
+use super::{
+    Edge, EdgeId, Face, FaceId, Halfedge, HalfedgeId, HalfedgeMesh, MeshError,
+    Vertex, VertexId,
+};
+
+// Local operators for triangle meshes. Elements removed by
+// `collapse_edge` are swap-removed, so the last vertex, edge, face
+// and halfedges take over the IDs of the removed ones; any IDs held
+// across a collapse should be considered invalid.
+
+impl HalfedgeMesh {
+    /// Flips the edge shared by two triangles, so that it connects
+    /// the two vertices opposite to it. The edge keeps its ID.
+    pub fn flip_edge(&mut self, edge: EdgeId) -> Result<(), MeshError> {
+        let h0 = self.edges[edge.0].halfedge;
+        let h3 = self.halfedges[h0.0].twin;
+
+        let f0 = self.halfedges[h0.0].face;
+        let f1 = self.halfedges[h3.0].face;
+
+        if self.faces[f0.0].is_boundary || self.faces[f1.0].is_boundary {
+            return Err(MeshError::BoundaryEdge { edge: edge.0 });
+        }
+
+        self.check_triangle(f0)?;
+        self.check_triangle(f1)?;
+
+        // f0: a -h0-> b -h1-> c -h2-> a
+        // f1: b -h3-> a -h4-> d -h5-> b
+        let h1 = self.halfedges[h0.0].next;
+        let h2 = self.halfedges[h1.0].next;
+        let h4 = self.halfedges[h3.0].next;
+        let h5 = self.halfedges[h4.0].next;
+
+        let a = self.halfedges[h0.0].vertex;
+        let b = self.halfedges[h3.0].vertex;
+        let c = self.halfedges[h2.0].vertex;
+        let d = self.halfedges[h5.0].vertex;
+
+        if c == d || self.find_edge(c, d).is_some() {
+            return Err(MeshError::NonManifoldResult { edge: edge.0 });
+        }
+
+        // f0: d -h0-> c -h2-> a -h4-> d
+        // f1: c -h3-> d -h5-> b -h1-> c
+        self.halfedges[h0.0].vertex = d;
+        self.halfedges[h3.0].vertex = c;
+
+        self.halfedges[h0.0].next = h2;
+        self.halfedges[h2.0].next = h4;
+        self.halfedges[h4.0].next = h0;
+
+        self.halfedges[h3.0].next = h5;
+        self.halfedges[h5.0].next = h1;
+        self.halfedges[h1.0].next = h3;
+
+        self.halfedges[h4.0].face = f0;
+        self.halfedges[h1.0].face = f1;
+
+        self.faces[f0.0].halfedge = h0;
+        self.faces[f1.0].halfedge = h3;
+
+        if self.vertices[a.0].halfedge == h0 {
+            self.vertices[a.0].halfedge = h4;
+        }
+        if self.vertices[b.0].halfedge == h3 {
+            self.vertices[b.0].halfedge = h1;
+        }
+
+        Ok(())
+    }
+
+    /// Splits the edge at its midpoint, and splits each adjacent
+    /// triangle in two by connecting the new vertex to the opposite
+    /// corner. Adjacent faces that aren't triangles only get the new
+    /// vertex inserted. Returns the new vertex.
+    pub fn split_edge(&mut self, edge: EdgeId) -> VertexId {
+        let h = self.edges[edge.0].halfedge;
+        let t = self.halfedges[h.0].twin;
+
+        let a = self.halfedges[h.0].vertex;
+        let b = self.halfedges[t.0].vertex;
+
+        let split_faces = [h, t].map(|he| {
+            let face = self.halfedges[he.0].face;
+            !self.faces[face.0].is_boundary && self.face_degree(face) == 3
+        });
+
+        let pos = (self.vertices[a.0].pos + self.vertices[b.0].pos) * 0.5;
+        let m = self.new_vertex(pos);
+
+        // a -h-> m -h_mb-> b, and b -t-> m -t_ma-> a
+        let h_mb = self.new_halfedge();
+        let t_ma = self.new_halfedge();
+        let new_edge = self.new_edge(h_mb);
+
+        for (he, new_he, to) in [(h, h_mb, b), (t, t_ma, a)] {
+            let next = self.halfedges[he.0].next;
+            let face = self.halfedges[he.0].face;
+
+            let new = &mut self.halfedges[new_he.0];
+            new.vertex = m;
+            new.next = next;
+            new.face = face;
+
+            self.halfedges[he.0].next = new_he;
+
+            debug_assert_eq!(self.halfedge_target(new_he), to);
+        }
+
+        self.halfedges[h.0].twin = t_ma;
+        self.halfedges[t_ma.0].twin = h;
+        self.halfedges[t_ma.0].edge = edge;
+
+        self.halfedges[t.0].twin = h_mb;
+        self.halfedges[h_mb.0].twin = t;
+        self.halfedges[h_mb.0].edge = new_edge;
+        self.halfedges[t.0].edge = new_edge;
+
+        self.edges[edge.0].halfedge = h;
+        self.vertices[m.0].halfedge = h_mb;
+
+        for (he, split) in [h, t].into_iter().zip(split_faces) {
+            if split {
+                self.split_quad_at(he);
+            }
+        }
+
+        m
+    }
+
+    // `into_m` must point into the split vertex of a face with four
+    // sides; the face is cut in two with an edge from the split
+    // vertex to the opposite corner
+    fn split_quad_at(&mut self, into_m: HalfedgeId) {
+        // p: x -> m, q: m -> y, r: y -> c, s: c -> x
+        let p = into_m;
+        let q = self.halfedges[p.0].next;
+        let r = self.halfedges[q.0].next;
+        let s = self.halfedges[r.0].next;
+
+        let m = self.halfedges[q.0].vertex;
+        let c = self.halfedges[s.0].vertex;
+
+        let old_face = self.halfedges[p.0].face;
+        let new_face = self.new_face(q);
+
+        let m_c = self.new_halfedge();
+        let c_m = self.new_halfedge();
+        let edge = self.new_edge(m_c);
+
+        for (he, twin, from) in [(m_c, c_m, m), (c_m, m_c, c)] {
+            let new = &mut self.halfedges[he.0];
+            new.twin = twin;
+            new.vertex = from;
+            new.edge = edge;
+        }
+
+        // old face: p, m_c, s
+        self.halfedges[p.0].next = m_c;
+        self.halfedges[m_c.0].next = s;
+        self.halfedges[m_c.0].face = old_face;
+        self.faces[old_face.0].halfedge = p;
+
+        // new face: q, r, c_m
+        self.halfedges[r.0].next = c_m;
+        self.halfedges[c_m.0].next = q;
+        for he in [q, r, c_m] {
+            self.halfedges[he.0].face = new_face;
+        }
+    }
+
+    /// Collapses the edge into a single vertex at its midpoint,
+    /// removing the adjacent triangles. Returns the remaining vertex.
+    ///
+    /// Fails if the collapse would make the mesh non-manifold,
+    /// i.e. if the endpoints share neighbors other than the
+    /// vertices opposite to the edge, if an interior edge connects
+    /// two boundary vertices, or if a neighboring vertex would be
+    /// left with too few edges.
+    pub fn collapse_edge(
+        &mut self,
+        edge: EdgeId,
+    ) -> Result<VertexId, MeshError> {
+        let h = self.edges[edge.0].halfedge;
+        let t = self.halfedges[h.0].twin;
+
+        let a = self.halfedges[h.0].vertex;
+        let b = self.halfedges[t.0].vertex;
+
+        let f0 = self.halfedges[h.0].face;
+        let f1 = self.halfedges[t.0].face;
+
+        let f0_interior = !self.faces[f0.0].is_boundary;
+        let f1_interior = !self.faces[f1.0].is_boundary;
+
+        let mut opposite = FxHashSet::default();
+
+        for (face, he, interior) in [(f0, h, f0_interior), (f1, t, f1_interior)]
+        {
+            if interior {
+                self.check_triangle(face)?;
+                let prev = self.halfedge_prev(he);
+                opposite.insert(self.halfedges[prev.0].vertex);
+            } else if self.face_degree(face) <= 3 {
+                // the boundary loop would degenerate
+                return Err(MeshError::NonManifoldResult { edge: edge.0 });
+            }
+        }
+
+        let non_manifold = Err(MeshError::NonManifoldResult { edge: edge.0 });
+
+        if f0_interior
+            && f1_interior
+            && self.vertex(a).on_boundary()
+            && self.vertex(b).on_boundary()
+        {
+            return non_manifold;
+        }
+
+        // link condition
+        let a_neighbors = self.vertex(a).neighbors().collect::<FxHashSet<_>>();
+        let common = self
+            .vertex(b)
+            .neighbors()
+            .filter(|v| a_neighbors.contains(v))
+            .collect::<FxHashSet<_>>();
+
+        if common != opposite {
+            return non_manifold;
+        }
+
+        // an opposite vertex loses one edge, and interior vertices
+        // need at least three
+        for &v in opposite.iter() {
+            let vertex = self.vertex(v);
+            if !vertex.on_boundary() && vertex.degree() <= 3 {
+                return non_manifold;
+            }
+        }
+
+        let a_outgoing =
+            self.vertex(a).outgoing_halfedges().collect::<Vec<_>>();
+        let b_outgoing =
+            self.vertex(b).outgoing_halfedges().collect::<Vec<_>>();
+
+        let mut dead_halfedges = vec![h, t];
+        let mut dead_edges = vec![edge];
+        let mut dead_faces = Vec::new();
+
+        for (he, face, interior) in [(h, f0, f0_interior), (t, f1, f1_interior)]
+        {
+            if interior {
+                // the triangle x -he-> y -n1-> o -n2-> x disappears,
+                // and the twins of n1 and n2 become twins
+                let n1 = self.halfedges[he.0].next;
+                let n2 = self.halfedges[n1.0].next;
+
+                let n1_twin = self.halfedges[n1.0].twin;
+                let n2_twin = self.halfedges[n2.0].twin;
+
+                let o = self.halfedges[n2.0].vertex;
+
+                let kept_edge = self.halfedges[n2.0].edge;
+                let dead_edge = self.halfedges[n1.0].edge;
+
+                self.halfedges[n1_twin.0].twin = n2_twin;
+                self.halfedges[n2_twin.0].twin = n1_twin;
+                self.halfedges[n1_twin.0].edge = kept_edge;
+                self.edges[kept_edge.0].halfedge = n2_twin;
+
+                if self.vertices[o.0].halfedge == n2 {
+                    self.vertices[o.0].halfedge = n1_twin;
+                }
+
+                dead_halfedges.extend([n1, n2]);
+                dead_edges.push(dead_edge);
+                dead_faces.push(face);
+            } else {
+                let prev = self.halfedge_prev(he);
+                let next = self.halfedges[he.0].next;
+                self.halfedges[prev.0].next = next;
+
+                if self.faces[face.0].halfedge == he {
+                    self.faces[face.0].halfedge = next;
+                }
+            }
+        }
+
+        for &he in b_outgoing.iter() {
+            self.halfedges[he.0].vertex = a;
+        }
+
+        let alive = a_outgoing
+            .iter()
+            .chain(b_outgoing.iter())
+            .find(|he| !dead_halfedges.contains(he))
+            .copied()
+            .expect("collapsed vertex has no remaining halfedges");
+        self.vertices[a.0].halfedge = alive;
+
+        let pos = (self.vertices[a.0].pos + self.vertices[b.0].pos) * 0.5;
+        self.vertices[a.0].pos = pos;
+
+        let a = self.remove_elements(
+            &dead_halfedges,
+            &dead_edges,
+            &dead_faces,
+            b,
+            a,
+        );
+
+        Ok(a)
+    }
+
+    fn check_triangle(&self, face: FaceId) -> Result<(), MeshError> {
+        if self.face_degree(face) != 3 {
+            return Err(MeshError::NonTriangleFace { face: face.0 });
+        }
+        Ok(())
+    }
+
+    pub(super) fn new_vertex(&mut self, pos: Vec3) -> VertexId {
+        let id = VertexId(self.vertices.len());
+        self.vertices.push(Vertex {
+            id,
+            pos,
+            halfedge: HalfedgeId::null(),
+        });
+        id
+    }
+
+    pub(super) fn new_halfedge(&mut self) -> HalfedgeId {
+        let id = HalfedgeId(self.halfedges.len());
+        self.halfedges.push(Halfedge::new(id));
+        id
+    }
+
+    pub(super) fn new_edge(&mut self, halfedge: HalfedgeId) -> EdgeId {
+        let id = EdgeId(self.edges.len());
+        self.edges.push(Edge { id, halfedge });
+        id
+    }
+
+    pub(super) fn new_face(&mut self, halfedge: HalfedgeId) -> FaceId {
+        let id = FaceId(self.faces.len());
+        let mut face = Face::new(id, false);
+        face.halfedge = halfedge;
+        self.faces.push(face);
+        id
+    }
+
+    // Removes elements that are no longer referenced by the rest of
+    // the mesh, filling the gaps by moving the last elements. Returns
+    // the new ID of `keep`, which may have been moved.
+    fn remove_elements(
+        &mut self,
+        halfedges: &[HalfedgeId],
+        edges: &[EdgeId],
+        faces: &[FaceId],
+        vertex: VertexId,
+        keep: VertexId,
+    ) -> VertexId {
+        // removing in descending order guarantees that the element
+        // being moved is never one that's also about to be removed
+        let mut halfedges = halfedges.to_vec();
+        halfedges.sort_by(|a, b| b.cmp(a));
+        for he in halfedges {
+            self.swap_remove_halfedge(he);
+        }
+
+        let mut edges = edges.to_vec();
+        edges.sort_by(|a, b| b.cmp(a));
+        for edge in edges {
+            let last = EdgeId(self.edges.len() - 1);
+            self.edges.swap_remove(edge.0);
+
+            if edge != last {
+                self.edges[edge.0].id = edge;
+                let he = self.edges[edge.0].halfedge;
+                let twin = self.halfedges[he.0].twin;
+                self.halfedges[he.0].edge = edge;
+                self.halfedges[twin.0].edge = edge;
+            }
+        }
+
+        let mut faces = faces.to_vec();
+        faces.sort_by(|a, b| b.cmp(a));
+        for face in faces {
+            let last = FaceId(self.faces.len() - 1);
+            self.faces.swap_remove(face.0);
+
+            if face != last {
+                self.faces[face.0].id = face;
+                let loop_hes = self.face_halfedges(face).collect::<Vec<_>>();
+                for he in loop_hes {
+                    self.halfedges[he.0].face = face;
+                }
+            }
+        }
+
+        let last = VertexId(self.vertices.len() - 1);
+        self.vertices.swap_remove(vertex.0);
+
+        if vertex != last {
+            self.vertices[vertex.0].id = vertex;
+            let outgoing =
+                self.vertex(vertex).outgoing_halfedges().collect::<Vec<_>>();
+            for he in outgoing {
+                self.halfedges[he.0].vertex = vertex;
+            }
+        }
+
+        if keep == last {
+            vertex
+        } else {
+            keep
+        }
+    }
+
+    fn swap_remove_halfedge(&mut self, he: HalfedgeId) {
+        let last = HalfedgeId(self.halfedges.len() - 1);
+
+        if he == last {
+            self.halfedges.pop();
+            return;
+        }
+
+        // fix everything that refers to `last` before moving it
+        let moved = self.halfedges[last.0];
+        let prev = self.halfedge_prev(last);
+
+        self.halfedges[prev.0].next = he;
+        self.halfedges[moved.twin.0].twin = he;
+
+        if self.vertices[moved.vertex.0].halfedge == last {
+            self.vertices[moved.vertex.0].halfedge = he;
+        }
+        if self.edges[moved.edge.0].halfedge == last {
+            self.edges[moved.edge.0].halfedge = he;
+        }
+        if self.faces[moved.face.0].halfedge == last {
+            self.faces[moved.face.0].halfedge = he;
+        }
+
+        self.halfedges.swap_remove(he.0);
+        self.halfedges[he.0].id = he;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::test_meshes::grid;
+    use nalgebra_glm::vec3;
+
+    fn euler_characteristic(mesh: &HalfedgeMesh) -> isize {
+        // boundary loops are counted as faces, which closes the
+        // surface
+        mesh.vertices.len() as isize - mesh.edges.len() as isize
+            + mesh.faces.len() as isize
+    }
+
+    fn assert_invariants(mesh: &HalfedgeMesh) {
+        for (ix, h) in mesh.halfedges.iter().enumerate() {
+            assert_eq!(h.id.0, ix);
+
+            let twin = mesh.halfedges[h.twin.0];
+            assert_ne!(h.twin, h.id);
+            assert_eq!(twin.twin, h.id);
+            assert_eq!(twin.edge, h.edge);
+            assert_eq!(mesh.halfedge_target(h.id), twin.vertex);
+            assert_ne!(h.vertex, twin.vertex);
+
+            assert_eq!(mesh.halfedges[h.next.0].face, h.face);
+        }
+
+        let mut seen = vec![false; mesh.halfedges.len()];
+
+        for (ix, face) in mesh.faces.iter().enumerate() {
+            assert_eq!(face.id.0, ix);
+
+            let mut degree = 0;
+            for he in mesh.face_halfedges(face.id) {
+                assert_eq!(mesh.halfedges[he.0].face, face.id);
+                assert!(!seen[he.0], "halfedge in more than one face");
+                seen[he.0] = true;
+                degree += 1;
+            }
+
+            assert!(degree >= 3);
+        }
+
+        assert!(seen.into_iter().all(|s| s), "halfedge without a face");
+
+        for (ix, edge) in mesh.edges.iter().enumerate() {
+            assert_eq!(edge.id.0, ix);
+            assert_eq!(mesh.halfedges[edge.halfedge.0].edge, edge.id);
+        }
+
+        let mut out_degree = vec![0; mesh.vertices.len()];
+        for h in mesh.halfedges.iter() {
+            out_degree[h.vertex.0] += 1;
+        }
+
+        for (ix, vertex) in mesh.vertices.iter().enumerate() {
+            assert_eq!(vertex.id.0, ix);
+            assert_eq!(mesh.halfedges[vertex.halfedge.0].vertex, vertex.id);
+            assert_eq!(mesh.vertex(vertex.id).degree(), out_degree[ix]);
+
+            let mut neighbors =
+                mesh.vertex(vertex.id).neighbors().collect::<Vec<_>>();
+            let degree = neighbors.len();
+            neighbors.sort();
+            neighbors.dedup();
+            assert_eq!(neighbors.len(), degree, "duplicate edge");
+        }
+    }
+
+    #[test]
+    fn test_flip_edge() {
+        let mut mesh = grid(3);
+        let chi = euler_characteristic(&mesh);
+
+        // the diagonal between vertices 1 and 3
+        let edge = mesh.find_edge(VertexId(1), VertexId(3)).unwrap();
+        mesh.flip_edge(edge).unwrap();
+
+        assert_invariants(&mesh);
+        assert_eq!(euler_characteristic(&mesh), chi);
+
+        let mut ends = mesh.edge_vertices(edge);
+        ends.sort();
+        assert_eq!(ends, [VertexId(0), VertexId(4)]);
+        assert!(mesh.find_edge(VertexId(1), VertexId(3)).is_none());
+
+        // flipping back restores the original diagonal
+        mesh.flip_edge(edge).unwrap();
+        assert_invariants(&mesh);
+        assert!(mesh.find_edge(VertexId(1), VertexId(3)).is_some());
+
+        let boundary = mesh.find_edge(VertexId(0), VertexId(1)).unwrap();
+        assert_eq!(
+            mesh.flip_edge(boundary),
+            Err(MeshError::BoundaryEdge { edge: boundary.0 })
+        );
+    }
+
+    #[test]
+    fn test_split_edge() {
+        let mut mesh = grid(3);
+        let chi = euler_characteristic(&mesh);
+
+        let interior = mesh.find_edge(VertexId(1), VertexId(4)).unwrap();
+        let m = mesh.split_edge(interior);
+
+        assert_invariants(&mesh);
+        assert_eq!(euler_characteristic(&mesh), chi);
+        assert_eq!(mesh.face_count(), 10);
+        assert_eq!(mesh.vertex(m).degree(), 4);
+        assert_eq!(mesh.vertex(m).center(), vec3(1.0, 0.0, 0.5));
+        assert!(!mesh.vertex(m).on_boundary());
+
+        let boundary = mesh.find_edge(VertexId(0), VertexId(1)).unwrap();
+        let m = mesh.split_edge(boundary);
+
+        assert_invariants(&mesh);
+        assert_eq!(euler_characteristic(&mesh), chi);
+        assert_eq!(mesh.face_count(), 11);
+        assert_eq!(mesh.vertex(m).degree(), 3);
+        assert!(mesh.vertex(m).on_boundary());
+    }
+
+    #[test]
+    fn test_collapse_edge() {
+        let mut mesh = grid(4);
+        let chi = euler_characteristic(&mesh);
+
+        // an interior edge between the two interior vertices 5 and 6
+        let edge = mesh.find_edge(VertexId(5), VertexId(6)).unwrap();
+        let v = mesh.collapse_edge(edge).unwrap();
+
+        assert_invariants(&mesh);
+        assert_eq!(euler_characteristic(&mesh), chi);
+        assert_eq!(mesh.vertex_count(), 15);
+        assert_eq!(mesh.face_count(), 16);
+        assert_eq!(mesh.vertex(v).center(), vec3(1.5, 0.0, 1.0));
+
+        // a boundary edge
+        let edge = mesh.find_edge(VertexId(1), VertexId(2)).unwrap();
+        mesh.collapse_edge(edge).unwrap();
+
+        assert_invariants(&mesh);
+        assert_eq!(euler_characteristic(&mesh), chi);
+        assert_eq!(mesh.face_count(), 15);
+
+        // an interior edge between two boundary vertices would pinch
+        // the surface
+        let mut mesh = grid(3);
+        let edge = mesh.find_edge(VertexId(1), VertexId(3)).unwrap();
+        assert_eq!(
+            mesh.collapse_edge(edge),
+            Err(MeshError::NonManifoldResult { edge: edge.0 })
+        );
+
+        // every collapse on a tetrahedron leaves a degenerate mesh
+        let points = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ];
+        let triangles = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+        let mut tet = HalfedgeMesh::from_triangles(points, triangles).unwrap();
+
+        for edge in 0..6 {
+            assert!(tet.collapse_edge(EdgeId(edge)).is_err());
+        }
+        assert_invariants(&tet);
+    }
+
+    #[test]
+    fn test_collapse_until_stuck() {
+        let mut mesh = grid(6);
+        let chi = euler_characteristic(&mesh);
+
+        loop {
+            let collapsed = (0..mesh.edge_count())
+                .find_map(|e| mesh.collapse_edge(EdgeId(e)).ok());
+
+            if collapsed.is_none() {
+                break;
+            }
+
+            assert_invariants(&mesh);
+            assert_eq!(euler_characteristic(&mesh), chi);
+        }
+
+        // a disc collapses down to a single triangle
+        assert_eq!(mesh.face_count(), 1);
+    }
+}
@@ -0,0 +1,620 @@
+use nalgebra_glm::{vec2, vec3, vec4, Mat4, Vec2, Vec3};
+
+use super::bounds::Aabb;
+use super::camera::Projection;
+use super::{normalize_or_zero, Camera, FaceId, HalfedgeMesh, VertexId};
+
+/// Triangles per BVH leaf
+const LEAF_SIZE: usize = 4;
+
+/// A half-line; `dir` is normalized, so parameters along the ray are
+/// distances from the origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Self {
+            origin,
+            dir: normalize_or_zero(dir),
+        }
+    }
+
+    /// A ray looking into the window at `cursor`, for picking 2D data
+    /// given in window pixels, such as the `vector_field::dot_plot`
+    /// points, with the other functions in this module
+    pub fn screen(cursor: Vec2) -> Self {
+        Self::new(vec3(cursor.x, cursor.y, 1.0), -Vec3::z())
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// The ray parameter and barycentric coordinates of the hit with
+    /// a triangle, from either side
+    pub fn intersect_triangle(&self, [a, b, c]: [Vec3; 3]) -> Option<Hit> {
+        // Möller-Trumbore
+        let ab = b - a;
+        let ac = c - a;
+        let p = self.dir.cross(&ac);
+        let det = ab.dot(&p);
+
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(&ab);
+        let v = self.dir.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = ac.dot(&q) * inv_det;
+        (t >= 0.0).then(|| Hit {
+            t,
+            barycentric: vec3(1.0 - u - v, u, v),
+        })
+    }
+
+    /// The ray parameter and distance of the point on the ray closest
+    /// to `p`
+    pub fn closest_to_point(&self, p: Vec3) -> (f32, f32) {
+        let t = (p - self.origin).dot(&self.dir).max(0.0);
+        (t, (self.at(t) - p).norm())
+    }
+
+    /// The ray parameter, the parameter along the segment in `[0, 1]`,
+    /// and the distance between the closest points of the ray and the
+    /// segment
+    pub fn closest_to_segment(&self, [a, b]: [Vec3; 2]) -> (f32, f32, f32) {
+        let ab = b - a;
+        let len_sq = ab.norm_squared();
+        if len_sq < f32::EPSILON {
+            let (t, dist) = self.closest_to_point(a);
+            return (t, 0.0, dist);
+        }
+
+        let w = self.origin - a;
+        let d_ab = self.dir.dot(&ab);
+        let d_w = self.dir.dot(&w);
+        let ab_w = ab.dot(&w);
+        let denom = len_sq - d_ab * d_ab;
+
+        // the segment parameter for the closest points of the lines,
+        // or either end if they're parallel
+        let mut s = if denom > f32::EPSILON {
+            ((ab_w - d_ab * d_w) / denom).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        // the closest ray parameter to that, and then back again in
+        // case the ray had to be clamped to its origin
+        let mut t = (d_ab * s - d_w).max(0.0);
+        s = ((t * d_ab + ab_w) / len_sq).clamp(0.0, 1.0);
+        t = (d_ab * s - d_w).max(0.0);
+
+        (t, s, (self.at(t) - (a + ab * s)).norm())
+    }
+
+    /// The ray parameter where the ray enters the box, or zero if the
+    /// origin is inside it
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+
+        for i in 0..3 {
+            let inv = 1.0 / self.dir[i];
+            let t0 = (aabb.min[i] - self.origin[i]) * inv;
+            let t1 = (aabb.max[i] - self.origin[i]) * inv;
+            let (t0, t1) = if inv < 0.0 { (t1, t0) } else { (t0, t1) };
+
+            // NaN, from a zero direction and the origin on a slab
+            // plane, leaves the interval as it is
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+}
+
+/// A ray-triangle intersection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub t: f32,
+    pub barycentric: Vec3,
+}
+
+/// A ray hitting a mesh face. Polygons are split into triangle fans,
+/// so `vertices` and `barycentric` refer to the triangle that was hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshHit {
+    pub face: FaceId,
+    pub vertices: [VertexId; 3],
+    pub barycentric: Vec3,
+    pub t: f32,
+    pub point: Vec3,
+}
+
+impl MeshHit {
+    /// The vertex of the hit triangle that is closest to the hit
+    pub fn nearest_vertex(&self) -> VertexId {
+        let b = self.barycentric;
+        if b.x >= b.y && b.x >= b.z {
+            self.vertices[0]
+        } else if b.y >= b.z {
+            self.vertices[1]
+        } else {
+            self.vertices[2]
+        }
+    }
+}
+
+/// A ray passing within some radius of a point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointHit {
+    pub index: usize,
+    pub t: f32,
+    pub distance: f32,
+}
+
+/// A ray passing within some radius of a line segment; `s` is the
+/// parameter along the segment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentHit {
+    pub index: usize,
+    pub t: f32,
+    pub s: f32,
+    pub distance: f32,
+}
+
+/// The first point along the ray that is within `radius` of it
+pub fn pick_points(
+    ray: &Ray,
+    points: impl IntoIterator<Item = Vec3>,
+    radius: f32,
+) -> Option<PointHit> {
+    points
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, p)| {
+            let (t, distance) = ray.closest_to_point(p);
+            (distance <= radius).then_some(PointHit { index, t, distance })
+        })
+        .min_by(|a, b| a.t.total_cmp(&b.t))
+}
+
+/// The first segment along the ray that is within `radius` of it
+pub fn pick_segments(
+    ray: &Ray,
+    segments: impl IntoIterator<Item = [Vec3; 2]>,
+    radius: f32,
+) -> Option<SegmentHit> {
+    segments
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, seg)| {
+            let (t, s, distance) = ray.closest_to_segment(seg);
+            (distance <= radius).then_some(SegmentHit {
+                index,
+                t,
+                s,
+                distance,
+            })
+        })
+        .min_by(|a, b| a.t.total_cmp(&b.t))
+}
+
+impl Camera {
+    /// The ray from the camera through `cursor`, given in window
+    /// pixels from the top left corner, for a window of `dims` pixels
+    pub fn ray(&self, cursor: Vec2, dims: [f32; 2]) -> Ray {
+        cursor_ray(&self.projection, &self.view_matrix(), cursor, dims)
+    }
+}
+
+// unprojects the cursor at the near and far planes
+fn cursor_ray(
+    projection: &Projection,
+    view: &Mat4,
+    cursor: Vec2,
+    dims: [f32; 2],
+) -> Ray {
+    let [width, height] = dims;
+    let ndc = vec2(2.0 * cursor.x / width - 1.0, 2.0 * cursor.y / height - 1.0);
+
+    let (near, far) = match projection {
+        Projection::Perspective {
+            reversed_z: true, ..
+        } => (1.0, 0.0),
+        _ => (0.0, 1.0),
+    };
+
+    let inverse = (projection.matrix(dims) * view)
+        .try_inverse()
+        .unwrap_or_else(Mat4::identity);
+    let unproject = |depth: f32| {
+        let p = inverse * vec4(ndc.x, ndc.y, depth, 1.0);
+        p.xyz() / p.w
+    };
+
+    let origin = unproject(near);
+    Ray::new(origin, unproject(far) - origin)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Triangle {
+    face: FaceId,
+    vertices: [VertexId; 3],
+    points: [Vec3; 3],
+    centroid: Vec3,
+}
+
+impl Triangle {
+    fn new(face: FaceId, vertices: [VertexId; 3], points: [Vec3; 3]) -> Self {
+        Self {
+            face,
+            vertices,
+            points,
+            centroid: (points[0] + points[1] + points[2]) / 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    // leaves hold `count` triangles starting at `start`; inner nodes
+    // have their first child right after them, and the second at
+    // `start`
+    start: usize,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh, for picking
+/// in large meshes. It holds a copy of the vertex positions, so it
+/// has to be rebuilt after the mesh changes.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    triangles: Vec<Triangle>,
+}
+
+impl Bvh {
+    /// Builds a BVH over the faces of a mesh, splitting polygons into
+    /// triangle fans
+    pub fn new(mesh: &HalfedgeMesh) -> Self {
+        let mut triangles = Vec::new();
+
+        for face in mesh.face_ids() {
+            let vertices = mesh.face_vertices(face).collect::<Vec<_>>();
+            for i in 1..vertices.len().saturating_sub(1) {
+                let vertices = [vertices[0], vertices[i], vertices[i + 1]];
+                let points = vertices.map(|v| mesh.pos(v));
+                triangles.push(Triangle::new(face, vertices, points));
+            }
+        }
+
+        Self::build(triangles)
+    }
+
+    /// Builds a BVH over a triangle list, e.g. an `io::TriangleMesh`;
+    /// face IDs are triangle indices
+    pub fn from_triangles(
+        positions: &[Vec3],
+        triangles: &[[usize; 3]],
+    ) -> Self {
+        let triangles = triangles
+            .iter()
+            .enumerate()
+            .map(|(i, tri)| {
+                let points = tri.map(|v| positions[v]);
+                Triangle::new(FaceId(i), tri.map(VertexId), points)
+            })
+            .collect();
+
+        Self::build(triangles)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// The closest hit along the ray
+    pub fn pick(&self, ray: &Ray) -> Option<MeshHit> {
+        let mut best: Option<(Hit, &Triangle)> = None;
+
+        let root = self.nodes.first()?;
+        let mut stack = Vec::new();
+        if ray.intersect_aabb(&root.bounds).is_some() {
+            stack.push(0);
+        }
+
+        while let Some(ix) = stack.pop() {
+            let node = &self.nodes[ix];
+
+            let best_t = best.map(|(hit, _)| hit.t).unwrap_or(f32::INFINITY);
+            match ray.intersect_aabb(&node.bounds) {
+                Some(t) if t <= best_t => (),
+                _ => continue,
+            }
+
+            if node.count > 0 {
+                let tris = &self.triangles[node.start..node.start + node.count];
+                for tri in tris {
+                    if let Some(hit) = ray.intersect_triangle(tri.points) {
+                        let closer = match best {
+                            Some((b, _)) => hit.t < b.t,
+                            None => true,
+                        };
+                        if closer {
+                            best = Some((hit, tri));
+                        }
+                    }
+                }
+            } else {
+                // visit the nearer child first, so that the other can
+                // often be skipped
+                let children = [ix + 1, node.start];
+                let [t0, t1] =
+                    children.map(|c| ray.intersect_aabb(&self.nodes[c].bounds));
+
+                let mut push = |c: usize, t: Option<f32>| {
+                    if t.is_some() {
+                        stack.push(c);
+                    }
+                };
+
+                if t0.unwrap_or(f32::INFINITY) <= t1.unwrap_or(f32::INFINITY) {
+                    push(children[1], t1);
+                    push(children[0], t0);
+                } else {
+                    push(children[0], t0);
+                    push(children[1], t1);
+                }
+            }
+        }
+
+        best.map(|(hit, tri)| MeshHit {
+            face: tri.face,
+            vertices: tri.vertices,
+            barycentric: hit.barycentric,
+            t: hit.t,
+            point: ray.at(hit.t),
+        })
+    }
+
+    fn build(mut triangles: Vec<Triangle>) -> Self {
+        let mut nodes = Vec::with_capacity(2 * triangles.len() / LEAF_SIZE + 1);
+
+        if !triangles.is_empty() {
+            let len = triangles.len();
+            Self::build_node(&mut nodes, &mut triangles, 0, len);
+        }
+
+        Self { nodes, triangles }
+    }
+
+    // builds the node for `triangles[start..end]`, splitting at the
+    // median centroid along the longest axis, and returns its index
+    fn build_node(
+        nodes: &mut Vec<Node>,
+        triangles: &mut [Triangle],
+        start: usize,
+        end: usize,
+    ) -> usize {
+        let tris = &mut triangles[start..end];
+
+        let mut bounds = Aabb::empty();
+        let mut centroids = Aabb::empty();
+
+        for tri in tris.iter() {
+            for p in tri.points {
+                bounds.extend(p);
+            }
+            centroids.extend(tri.centroid);
+        }
+
+        let ix = nodes.len();
+        nodes.push(Node {
+            bounds,
+            start,
+            count: tris.len(),
+        });
+
+        if tris.len() <= LEAF_SIZE {
+            return ix;
+        }
+
+        let axis = centroids.size().imax();
+        let mid = tris.len() / 2;
+        tris.select_nth_unstable_by(mid, |a, b| {
+            a.centroid[axis].total_cmp(&b.centroid[axis])
+        });
+
+        Self::build_node(nodes, triangles, start, start + mid);
+        let second = Self::build_node(nodes, triangles, start + mid, end);
+
+        nodes[ix].start = second;
+        nodes[ix].count = 0;
+
+        ix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::camera::view_matrix;
+    use crate::mesh::primitives;
+    use rand::prelude::*;
+
+    #[test]
+    fn test_ray_queries() {
+        let ray = Ray::new(vec3(0.0, 0.0, 5.0), vec3(0.0, 0.0, -2.0));
+        assert_eq!(ray.dir, vec3(0.0, 0.0, -1.0));
+
+        let tri = [
+            vec3(-1.0, -1.0, 0.0),
+            vec3(1.0, -1.0, 0.0),
+            vec3(-1.0, 1.0, 0.0),
+        ];
+        let hit = ray.intersect_triangle(tri).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-5);
+        assert!((hit.barycentric - vec3(0.0, 0.5, 0.5)).norm() < 1e-5);
+
+        // both sides are hit, but not behind the origin
+        let back = [tri[0], tri[2], tri[1]];
+        assert!(ray.intersect_triangle(back).is_some());
+        let behind = tri.map(|p| p + vec3(0.0, 0.0, 10.0));
+        assert!(ray.intersect_triangle(behind).is_none());
+
+        let points = [
+            vec3(0.5, 0.0, -1.0),
+            vec3(0.05, 0.0, 0.0),
+            vec3(0.0, 0.05, 1.0),
+            vec3(0.0, 0.0, 10.0),
+        ];
+        let hit = pick_points(&ray, points, 0.1).unwrap();
+        assert_eq!(hit.index, 2);
+        assert!((hit.t - 4.0).abs() < 1e-5);
+        assert!((hit.distance - 0.05).abs() < 1e-5);
+
+        let segments = [
+            [vec3(-1.0, 0.2, 0.0), vec3(1.0, 0.2, 0.0)],
+            [vec3(-1.0, 0.0, 1.0), vec3(1.0, 0.0, 3.0)],
+        ];
+        let hit = pick_segments(&ray, segments, 0.1).unwrap();
+        assert_eq!(hit.index, 1);
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!((hit.s - 0.5).abs() < 1e-5);
+        assert!(pick_segments(&ray, [segments[0]], 0.1).is_none());
+
+        // 2D points in window pixels
+        let dots = [vec2(100.0, 100.0), vec2(203.0, 48.0)];
+        let ray = Ray::screen(vec2(200.0, 50.0));
+        let hit = pick_points(&ray, dots.map(|p| vec3(p.x, p.y, 0.0)), 8.0);
+        assert_eq!(hit.map(|h| h.index), Some(1));
+    }
+
+    #[test]
+    fn test_bvh() {
+        let mesh = primitives::icosphere(1.0, 3).to_halfedge().unwrap();
+        let bvh = Bvh::new(&mesh);
+        assert_eq!(bvh.triangle_count(), mesh.face_count());
+
+        let brute_force = |ray: &Ray| {
+            mesh.face_ids()
+                .filter_map(|f| {
+                    let vs = mesh.face_vertices(f).collect::<Vec<_>>();
+                    let points = [0, 1, 2].map(|i| mesh.pos(vs[i]));
+                    ray.intersect_triangle(points).map(|hit| (f, hit.t))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+        };
+
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..200 {
+            let origin = vec3(
+                rng.gen_range(-3.0..3.0),
+                rng.gen_range(-3.0..3.0),
+                rng.gen_range(-3.0..3.0),
+            );
+            let target = vec3(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let ray = Ray::new(origin, target - origin);
+
+            let hit = bvh.pick(&ray);
+            let expected = brute_force(&ray);
+            assert_eq!(hit.is_some(), expected.is_some());
+
+            if let (Some(hit), Some((_, t))) = (hit, expected) {
+                assert!((hit.t - t).abs() < 1e-4);
+
+                let [a, b, c] = hit.vertices.map(|v| mesh.pos(v));
+                let bary = hit.barycentric;
+                let p = a * bary.x + b * bary.y + c * bary.z;
+                assert!((p - hit.point).norm() < 1e-4);
+                assert!(hit.vertices.contains(&hit.nearest_vertex()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cursor_ray() {
+        let dims = [800.0, 600.0];
+        let eye = vec3(0.0, 0.0, 5.0);
+        let view = view_matrix(eye, [Vec3::x(), Vec3::y(), Vec3::z()]);
+
+        let reversed = Projection::Perspective {
+            fov_y: 1.0,
+            near: 0.5,
+            far: 100.0,
+            reversed_z: true,
+        };
+        let orthographic = Projection::orthographic(4.0, 0.5, 100.0);
+
+        for proj in [Projection::default(), reversed, orthographic] {
+            // the window center looks straight ahead from the near
+            // plane
+            let ray = cursor_ray(&proj, &view, vec2(400.0, 300.0), dims);
+            assert!((ray.dir - vec3(0.0, 0.0, -1.0)).norm() < 1e-4);
+            assert!(ray.origin.xy().norm() < 1e-4);
+            assert!(ray.origin.z < eye.z && ray.origin.z > 0.0);
+
+            // rays through a cursor pass through the points that
+            // project to it
+            let view_proj = proj.matrix(dims) * view;
+            let p = vec3(0.7, -0.4, -1.5);
+            let clip = view_proj * vec4(p.x, p.y, p.z, 1.0);
+            let ndc = clip.xy() / clip.w;
+            let cursor = vec2(
+                (ndc.x + 1.0) * 0.5 * dims[0],
+                (ndc.y + 1.0) * 0.5 * dims[1],
+            );
+
+            let ray = cursor_ray(&proj, &view, cursor, dims);
+            let (_, dist) = ray.closest_to_point(p);
+            assert!(dist < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_bvh_large() {
+        // 100k triangles
+        let grid = primitives::plane_grid(10.0, 10.0, 250, 200);
+        let bvh = Bvh::from_triangles(&grid.positions, &grid.triangles);
+        assert_eq!(bvh.triangle_count(), 100_000);
+
+        let ray = Ray::new(vec3(1.234, 5.0, -2.345), vec3(0.0, -1.0, 0.0));
+        let hit = bvh.pick(&ray).unwrap();
+        assert!((hit.point - vec3(1.234, 0.0, -2.345)).norm() < 1e-4);
+
+        let [a, b, c] = hit.vertices.map(|v| grid.positions[v.0]);
+        let tri = grid.triangles[hit.face.0];
+        assert_eq!(hit.vertices, tri.map(VertexId));
+        assert!(ray.intersect_triangle([a, b, c]).is_some());
+
+        let miss = Ray::new(vec3(20.0, 5.0, 0.0), vec3(0.0, -1.0, 0.0));
+        assert!(bvh.pick(&miss).is_none());
+    }
+}
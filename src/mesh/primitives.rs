@@ -0,0 +1,549 @@
+use nalgebra_glm::{vec2, vec3, Vec2, Vec3};
+use rustc_hash::FxHashMap;
+
+use super::{io::TriangleMesh, normalize_or_zero};
+
+// Every primitive has per-vertex normals and counter-clockwise
+// triangles when seen from the side the normals point to. Solids of
+// revolution have Y as their axis; hard edges, such as the rim of a
+// cylinder, use separate vertices on either side.
+
+/// A sphere centered on the origin, with `segments` vertices around
+/// each of the `rings - 1` latitude rings, and a vertex at each pole
+pub fn uv_sphere(radius: f32, segments: usize, rings: usize) -> TriangleMesh {
+    let rings = rings.max(2);
+
+    let profile = (0..=rings)
+        .map(|i| {
+            let theta = std::f32::consts::PI * i as f32 / rings as f32;
+            // the poles have to be exactly on the axis
+            let sin = if i == 0 || i == rings {
+                0.0
+            } else {
+                theta.sin()
+            };
+            let cos = theta.cos();
+            point(radius * sin, radius * cos, [sin, cos])
+        })
+        .collect::<Vec<_>>();
+
+    let mut mesh = TriangleMesh::default();
+    revolve(&mut mesh, &profile, segments, false);
+    mesh
+}
+
+/// A sphere centered on the origin, made by subdividing an
+/// icosahedron `subdivisions` times, for evenly sized triangles
+pub fn icosphere(radius: f32, subdivisions: usize) -> TriangleMesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+
+    let mut points = vec![
+        vec3(-1.0, t, 0.0),
+        vec3(1.0, t, 0.0),
+        vec3(-1.0, -t, 0.0),
+        vec3(1.0, -t, 0.0),
+        vec3(0.0, -1.0, t),
+        vec3(0.0, 1.0, t),
+        vec3(0.0, -1.0, -t),
+        vec3(0.0, 1.0, -t),
+        vec3(t, 0.0, -1.0),
+        vec3(t, 0.0, 1.0),
+        vec3(-t, 0.0, -1.0),
+        vec3(-t, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|p| p.normalize())
+    .collect::<Vec<_>>();
+
+    let mut triangles = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: FxHashMap<(usize, usize), usize> =
+            FxHashMap::default();
+
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push((points[a] + points[b]).normalize());
+                points.len() - 1
+            })
+        };
+
+        triangles = triangles
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let ab = midpoint(a, b);
+                let bc = midpoint(b, c);
+                let ca = midpoint(c, a);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    TriangleMesh {
+        positions: points.iter().map(|p| p * radius).collect(),
+        normals: points,
+        colors: Vec::new(),
+        triangles,
+    }
+}
+
+/// A closed cylinder from the origin to `height` along Y
+pub fn cylinder(radius: f32, height: f32, segments: usize) -> TriangleMesh {
+    let mut mesh = TriangleMesh::default();
+
+    let top = [
+        point(0.0, height, [0.0, 1.0]),
+        point(radius, height, [0.0, 1.0]),
+    ];
+    let side = [
+        point(radius, height, [1.0, 0.0]),
+        point(radius, 0.0, [1.0, 0.0]),
+    ];
+
+    revolve(&mut mesh, &top, segments, false);
+    revolve(&mut mesh, &side, segments, false);
+    revolve(&mut mesh, &base(radius, 0.0), segments, false);
+
+    mesh
+}
+
+/// A closed cone with its base at the origin and its apex at
+/// `height` along Y
+pub fn cone(radius: f32, height: f32, segments: usize) -> TriangleMesh {
+    let mut mesh = TriangleMesh::default();
+
+    revolve(&mut mesh, &cone_side(radius, 0.0, height), segments, false);
+    revolve(&mut mesh, &base(radius, 0.0), segments, false);
+
+    mesh
+}
+
+/// A torus around the Y axis. `major_radius` is the distance from
+/// the origin to the center of the tube, and `minor_radius` is the
+/// radius of the tube.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: usize,
+    minor_segments: usize,
+) -> TriangleMesh {
+    let minor_segments = minor_segments.max(3);
+
+    // clockwise around the tube, so that the outside faces left
+    let profile = (0..minor_segments)
+        .map(|i| {
+            let psi = -std::f32::consts::TAU * i as f32 / minor_segments as f32;
+            let (sin, cos) = psi.sin_cos();
+            point(
+                major_radius + minor_radius * cos,
+                minor_radius * sin,
+                [cos, sin],
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut mesh = TriangleMesh::default();
+    revolve(&mut mesh, &profile, major_segments, true);
+    mesh
+}
+
+/// A flat grid in the XZ plane, centered on the origin and facing up,
+/// with `x_res` by `z_res` quads
+pub fn plane_grid(
+    width: f32,
+    depth: f32,
+    x_res: usize,
+    z_res: usize,
+) -> TriangleMesh {
+    parametric_surface(
+        |u, v| vec3((u - 0.5) * width, 0.0, (0.5 - v) * depth),
+        x_res,
+        z_res,
+    )
+}
+
+/// An arrow from the origin to `length` along Y, made of a cylindrical
+/// shaft and a conical head
+pub fn arrow(
+    length: f32,
+    shaft_radius: f32,
+    head_radius: f32,
+    head_length: f32,
+    segments: usize,
+) -> TriangleMesh {
+    let head_length = head_length.min(length);
+    let shaft_length = length - head_length;
+
+    let mut mesh = TriangleMesh::default();
+
+    let head = cone_side(head_radius, shaft_length, length);
+    let underside = [
+        point(head_radius, shaft_length, [0.0, -1.0]),
+        point(shaft_radius, shaft_length, [0.0, -1.0]),
+    ];
+    let shaft = [
+        point(shaft_radius, shaft_length, [1.0, 0.0]),
+        point(shaft_radius, 0.0, [1.0, 0.0]),
+    ];
+
+    revolve(&mut mesh, &head, segments, false);
+    revolve(&mut mesh, &underside, segments, false);
+    revolve(&mut mesh, &shaft, segments, false);
+    revolve(&mut mesh, &base(shaft_radius, 0.0), segments, false);
+
+    mesh
+}
+
+/// A cylinder of `height` with hemispherical caps, centered on the
+/// origin along Y. Each cap has `rings` latitude rings.
+pub fn capsule(
+    radius: f32,
+    height: f32,
+    segments: usize,
+    rings: usize,
+) -> TriangleMesh {
+    let rings = rings.max(1);
+    let half = height * 0.5;
+
+    let cap_point = |i: usize, y_offset: f32| {
+        let theta = std::f32::consts::FRAC_PI_2 * i as f32 / rings as f32;
+        let sin = if i == 0 || i == 2 * rings {
+            0.0
+        } else {
+            theta.sin()
+        };
+        let cos = theta.cos();
+        point(radius * sin, y_offset + radius * cos, [sin, cos])
+    };
+
+    // the two equator rings are joined by the sides of the cylinder
+    let profile = (0..=rings)
+        .map(|i| cap_point(i, half))
+        .chain((rings..=2 * rings).map(|i| cap_point(i, -half)))
+        .collect::<Vec<_>>();
+
+    let mut mesh = TriangleMesh::default();
+    revolve(&mut mesh, &profile, segments, false);
+    mesh
+}
+
+/// Samples `f` on a `u_res` by `v_res` grid over `[0, 1]^2`. Normals
+/// are `df/du x df/dv`, estimated with finite differences.
+///
+/// The grid isn't joined where `f` wraps around, so closed surfaces
+/// have duplicate vertices along their seams.
+pub fn parametric_surface<F>(f: F, u_res: usize, v_res: usize) -> TriangleMesh
+where
+    F: Fn(f32, f32) -> Vec3,
+{
+    let u_res = u_res.max(1);
+    let v_res = v_res.max(1);
+
+    let mut mesh = TriangleMesh::default();
+
+    for j in 0..=v_res {
+        for i in 0..=u_res {
+            let u = i as f32 / u_res as f32;
+            let v = j as f32 / v_res as f32;
+
+            mesh.positions.push(f(u, v));
+            mesh.normals.push(parametric_normal(&f, u, v));
+        }
+    }
+
+    let row = u_res + 1;
+
+    for j in 0..v_res {
+        for i in 0..u_res {
+            let a = j * row + i;
+            let b = a + 1;
+            let c = b + row;
+            let d = a + row;
+            mesh.triangles.push([a, b, c]);
+            mesh.triangles.push([a, c, d]);
+        }
+    }
+
+    mesh
+}
+
+// the step used for finite differences in parameter space
+const PARAM_STEP: f32 = 1e-3;
+
+fn parametric_normal<F>(f: &F, u: f32, v: f32) -> Vec3
+where
+    F: Fn(f32, f32) -> Vec3,
+{
+    let normal_at = |u: f32, v: f32| {
+        let u0 = (u - PARAM_STEP).max(0.0);
+        let u1 = (u + PARAM_STEP).min(1.0);
+        let v0 = (v - PARAM_STEP).max(0.0);
+        let v1 = (v + PARAM_STEP).min(1.0);
+
+        let du = f(u1, v) - f(u0, v);
+        let dv = f(u, v1) - f(u, v0);
+        normalize_or_zero(du.cross(&dv))
+    };
+
+    let normal = normal_at(u, v);
+    if normal != Vec3::zeros() {
+        return normal;
+    }
+
+    // degenerate points, such as poles, use a nearby normal
+    let towards_center = |x: f32| x + (0.5 - x).signum() * PARAM_STEP;
+    normal_at(towards_center(u), towards_center(v))
+}
+
+// a point on a profile curve, in the plane spanned by the radial
+// direction and Y
+#[derive(Debug, Clone, Copy)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: Vec2,
+}
+
+fn point(radius: f32, y: f32, [nr, ny]: [f32; 2]) -> ProfilePoint {
+    ProfilePoint {
+        radius,
+        y,
+        normal: vec2(nr, ny).normalize(),
+    }
+}
+
+// a disc facing down at height `y`
+fn base(radius: f32, y: f32) -> [ProfilePoint; 2] {
+    [point(radius, y, [0.0, -1.0]), point(0.0, y, [0.0, -1.0])]
+}
+
+fn cone_side(radius: f32, y0: f32, y1: f32) -> [ProfilePoint; 2] {
+    let normal = [y1 - y0, radius];
+    [point(0.0, y1, normal), point(radius, y0, normal)]
+}
+
+// Revolves `profile` around the Y axis and appends the surface to
+// `mesh`. The surface faces the left side of the profile as it's
+// traversed, e.g. a profile going down along +X faces outwards.
+//
+// Points with zero radius become a single vertex, unless their
+// normal has a radial component, as at the tip of a cone; those get
+// one vertex per triangle, with the normal between its neighbors.
+fn revolve(
+    mesh: &mut TriangleMesh,
+    profile: &[ProfilePoint],
+    segments: usize,
+    closed: bool,
+) {
+    let segments = segments.max(3);
+
+    let angle = |x: f32| std::f32::consts::TAU * x / segments as f32;
+
+    let mut push = |p: &ProfilePoint, phi: f32| {
+        let (sin, cos) = phi.sin_cos();
+        let n = p.normal;
+        mesh.positions
+            .push(vec3(p.radius * cos, p.y, p.radius * sin));
+        mesh.normals.push(vec3(n.x * cos, n.y, n.x * sin));
+        mesh.positions.len() - 1
+    };
+
+    // the vertex indices around each profile point, and whether the
+    // point is on the axis
+    let rings = profile
+        .iter()
+        .map(|p| {
+            let on_axis = p.radius.abs() <= f32::EPSILON;
+
+            let ring = if on_axis && p.normal.x.abs() <= f32::EPSILON {
+                vec![push(p, 0.0); segments]
+            } else if on_axis {
+                (0..segments)
+                    .map(|j| push(p, angle(j as f32 + 0.5)))
+                    .collect()
+            } else {
+                (0..segments).map(|j| push(p, angle(j as f32))).collect()
+            };
+
+            (ring, on_axis)
+        })
+        .collect::<Vec<_>>();
+
+    let pairs = if closed { rings.len() } else { rings.len() - 1 };
+
+    for k in 0..pairs {
+        let (top, top_axis) = &rings[k];
+        let (bottom, bottom_axis) = &rings[(k + 1) % rings.len()];
+
+        for j in 0..segments {
+            let next = (j + 1) % segments;
+
+            let a = top[j];
+            let b = top[next];
+            let c = bottom[next];
+            let d = bottom[j];
+
+            match (top_axis, bottom_axis) {
+                (true, true) => (),
+                (true, false) => mesh.triangles.push([a, c, d]),
+                (false, true) => mesh.triangles.push([a, b, d]),
+                (false, false) => {
+                    mesh.triangles.push([a, b, c]);
+                    mesh.triangles.push([a, c, d]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // checks that all indices are valid, normals are unit length, and
+    // that every triangle is wound consistently with its normals
+    fn assert_consistent(mesh: &TriangleMesh) {
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+
+        for n in mesh.normals.iter() {
+            assert!((n.norm() - 1.0).abs() < 1e-4, "normal {:?}", n);
+        }
+
+        for &[a, b, c] in mesh.triangles.iter() {
+            assert!(a < mesh.positions.len());
+            assert!(b < mesh.positions.len());
+            assert!(c < mesh.positions.len());
+
+            let [pa, pb, pc] = [a, b, c].map(|i| mesh.positions[i]);
+            let face = (pb - pa).cross(&(pc - pa));
+            assert!(face.norm() > 0.0, "degenerate triangle {:?}", [a, b, c]);
+
+            for i in [a, b, c] {
+                assert!(face.dot(&mesh.normals[i]) > 0.0);
+            }
+        }
+    }
+
+    // checks that the normals of a star-shaped mesh point away from
+    // `center`
+    fn assert_outwards(mesh: &TriangleMesh, center: Vec3) {
+        for (p, n) in mesh.positions.iter().zip(mesh.normals.iter()) {
+            assert!((p - center).dot(n) > -1e-5);
+        }
+    }
+
+    fn euler_characteristic(mesh: &TriangleMesh) -> isize {
+        let mesh = mesh.to_halfedge().unwrap();
+        assert_eq!(mesh.boundary_count(), 0);
+        mesh.vertex_count() as isize - mesh.edge_count() as isize
+            + mesh.face_count() as isize
+    }
+
+    #[test]
+    fn test_spheres() {
+        let sphere = uv_sphere(2.0, 16, 8);
+        assert_consistent(&sphere);
+        assert_outwards(&sphere, Vec3::zeros());
+        assert_eq!(sphere.positions.len(), 16 * 7 + 2);
+        assert_eq!(sphere.triangles.len(), 2 * 16 * 7);
+        assert!(sphere
+            .positions
+            .iter()
+            .all(|p| (p.norm() - 2.0).abs() < 1e-5));
+        assert_eq!(euler_characteristic(&sphere), 2);
+
+        let ico = icosphere(1.0, 2);
+        assert_consistent(&ico);
+        assert_outwards(&ico, Vec3::zeros());
+        assert_eq!(ico.triangles.len(), 20 * 16);
+        assert_eq!(ico.positions.len(), 10 * 16 + 2);
+        assert_eq!(euler_characteristic(&ico), 2);
+    }
+
+    #[test]
+    fn test_solids_of_revolution() {
+        let cyl = cylinder(0.5, 2.0, 12);
+        assert_consistent(&cyl);
+        assert_outwards(&cyl, vec3(0.0, 1.0, 0.0));
+        // caps and side don't share vertices
+        assert_eq!(cyl.positions.len(), 2 * (12 + 1) + 2 * 12);
+        assert_eq!(cyl.triangles.len(), 4 * 12);
+
+        let cone = cone(1.0, 2.0, 12);
+        assert_consistent(&cone);
+        assert_outwards(&cone, vec3(0.0, 0.5, 0.0));
+        let apex = cone.positions.iter().filter(|p| p.y == 2.0).count();
+        assert_eq!(apex, 12);
+
+        let torus = torus(2.0, 0.5, 24, 12);
+        assert_consistent(&torus);
+        assert_eq!(torus.positions.len(), 24 * 12);
+        assert_eq!(euler_characteristic(&torus), 0);
+        for (p, n) in torus.positions.iter().zip(torus.normals.iter()) {
+            let tube_center = vec3(p.x, 0.0, p.z).normalize() * 2.0;
+            assert!(((p - tube_center) * 2.0 - n).norm() < 1e-4);
+        }
+
+        let capsule = capsule(0.5, 1.0, 12, 4);
+        assert_consistent(&capsule);
+        assert_outwards(&capsule, Vec3::zeros());
+        assert_eq!(euler_characteristic(&capsule), 2);
+        let max_y = capsule.positions.iter().map(|p| p.y).fold(0.0, f32::max);
+        assert!((max_y - 1.0).abs() < 1e-6);
+
+        let arrow = arrow(1.0, 0.05, 0.1, 0.25, 8);
+        assert_consistent(&arrow);
+        assert!(arrow.positions.iter().all(|p| p.y >= 0.0 && p.y <= 1.0));
+    }
+
+    #[test]
+    fn test_parametric_surface() {
+        let grid = plane_grid(2.0, 4.0, 4, 8);
+        assert_consistent(&grid);
+        assert_eq!(grid.positions.len(), 5 * 9);
+        assert_eq!(grid.triangles.len(), 2 * 4 * 8);
+        assert!(grid.normals.iter().all(|n| *n == vec3(0.0, 1.0, 0.0)));
+        assert_eq!(grid.positions[0], vec3(-1.0, 0.0, 2.0));
+
+        // a sphere, with degenerate normals at the poles
+        let sphere = parametric_surface(
+            |u, v| {
+                let phi = u * std::f32::consts::TAU;
+                let theta = v * std::f32::consts::PI;
+                vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                )
+            },
+            16,
+            8,
+        );
+        assert_eq!(sphere.positions.len(), 17 * 9);
+        for (p, n) in sphere.positions.iter().zip(sphere.normals.iter()) {
+            assert!((p - n).norm() < 0.01, "{:?} {:?}", p, n);
+        }
+    }
+}
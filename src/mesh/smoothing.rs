@@ -0,0 +1,351 @@
+use nalgebra_glm::Vec3;
+use ndarray::Array1;
+use rayon::prelude::*;
+use sprs::{CsMat, TriMat};
+
+use super::{HalfedgeMesh, MeshError, VertexId};
+
+// relative residual at which the conjugate gradient solver stops
+const CG_TOLERANCE: f32 = 1e-5;
+
+/// One of the smoothing operators, with its parameters, for applying
+/// with `HalfedgeMesh::smooth`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// See `HalfedgeMesh::laplacian_smooth`
+    Laplacian {
+        iterations: usize,
+        lambda: f32,
+        fix_boundary: bool,
+    },
+    /// See `HalfedgeMesh::taubin_smooth`
+    Taubin {
+        iterations: usize,
+        lambda: f32,
+        mu: f32,
+        fix_boundary: bool,
+    },
+    /// See `HalfedgeMesh::implicit_mcf`
+    ImplicitMcf {
+        iterations: usize,
+        dt: f32,
+        fix_boundary: bool,
+    },
+}
+
+impl HalfedgeMesh {
+    /// Applies the operator and parameters in `smoothing`
+    pub fn smooth(&mut self, smoothing: Smoothing) -> Result<(), MeshError> {
+        match smoothing {
+            Smoothing::Laplacian {
+                iterations,
+                lambda,
+                fix_boundary,
+            } => self.laplacian_smooth(iterations, lambda, fix_boundary),
+            Smoothing::Taubin {
+                iterations,
+                lambda,
+                mu,
+                fix_boundary,
+            } => self.taubin_smooth(iterations, lambda, mu, fix_boundary),
+            Smoothing::ImplicitMcf {
+                iterations,
+                dt,
+                fix_boundary,
+            } => self.implicit_mcf(iterations, dt, fix_boundary)?,
+        }
+
+        Ok(())
+    }
+
+    /// Applies `iterations` steps of uniform Laplacian smoothing,
+    /// moving each vertex `lambda` of the way towards the average of
+    /// its neighbors. Works on any polygon mesh, but shrinks the
+    /// surface with each step.
+    pub fn laplacian_smooth(
+        &mut self,
+        iterations: usize,
+        lambda: f32,
+        fix_boundary: bool,
+    ) {
+        for _ in 0..iterations {
+            self.umbrella_step(lambda, fix_boundary);
+        }
+    }
+
+    /// Applies `iterations` steps of Taubin's lambda/mu smoothing,
+    /// where each step is a shrinking Laplacian step with `lambda > 0`
+    /// followed by an inflating one with `mu < -lambda`. `0.5` and
+    /// `-0.53` are reasonable defaults.
+    pub fn taubin_smooth(
+        &mut self,
+        iterations: usize,
+        lambda: f32,
+        mu: f32,
+        fix_boundary: bool,
+    ) {
+        for _ in 0..iterations {
+            self.umbrella_step(lambda, fix_boundary);
+            self.umbrella_step(mu, fix_boundary);
+        }
+    }
+
+    /// Applies `iterations` steps of implicit mean curvature flow,
+    /// solving `(M + dt L) x' = M x` with the cotangent Laplacian `L`
+    /// and the mass matrix `M`. Unlike the explicit methods, this is
+    /// stable for any time step. Every face must be a triangle.
+    pub fn implicit_mcf(
+        &mut self,
+        iterations: usize,
+        dt: f32,
+        fix_boundary: bool,
+    ) -> Result<(), MeshError> {
+        self.check_triangles()?;
+
+        for _ in 0..iterations {
+            self.implicit_mcf_step(dt, fix_boundary)?;
+        }
+
+        Ok(())
+    }
+
+    fn umbrella_step(&mut self, lambda: f32, fix_boundary: bool) {
+        let positions = (0..self.vertices.len())
+            .into_par_iter()
+            .map(|i| {
+                let vertex = self.vertex(VertexId(i));
+                let pos = vertex.center();
+
+                if fix_boundary && vertex.on_boundary() {
+                    pos
+                } else {
+                    pos + (vertex.neighborhood_center() - pos) * lambda
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.vertices
+            .par_iter_mut()
+            .zip(positions)
+            .for_each(|(vertex, pos)| vertex.pos = pos);
+    }
+
+    fn implicit_mcf_step(
+        &mut self,
+        dt: f32,
+        fix_boundary: bool,
+    ) -> Result<(), MeshError> {
+        let n = self.vertices.len();
+
+        let laplacian = self.cotan_laplacian()?;
+        let areas = self.vertex_areas()?;
+
+        // isolated vertices have no area, and are kept in place along
+        // with the boundary
+        let fixed = self
+            .vertices()
+            .zip(areas.iter())
+            .map(|(v, &a)| a <= 0.0 || (fix_boundary && v.on_boundary()))
+            .collect::<Vec<_>>();
+
+        // fixed vertices get identity rows, and their columns are
+        // moved to the right hand side, keeping the system symmetric
+        let mut system = TriMat::new((n, n));
+        let mut rhs = vec![Array1::zeros(n); 3];
+
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let weight = if fixed[i] { 1.0 } else { areas[i] };
+            system.add_triplet(i, i, weight);
+            for (c, b) in rhs.iter_mut().enumerate() {
+                b[i] = weight * vertex.pos[c];
+            }
+        }
+
+        for (i, row) in laplacian.outer_iterator().enumerate() {
+            if fixed[i] {
+                continue;
+            }
+
+            for (j, &l) in row.iter() {
+                if fixed[j] {
+                    let pos = self.vertices[j].pos;
+                    for (c, b) in rhs.iter_mut().enumerate() {
+                        b[i] -= dt * l * pos[c];
+                    }
+                } else {
+                    system.add_triplet(i, j, dt * l);
+                }
+            }
+        }
+
+        let system: CsMat<f32> = system.to_csr();
+
+        let solved = rhs
+            .into_par_iter()
+            .enumerate()
+            .map(|(c, b)| {
+                let x0 = self.vertices.iter().map(|v| v.pos[c]).collect();
+                conjugate_gradient(&system, &b, x0)
+            })
+            .collect::<Vec<_>>();
+
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            vertex.pos = Vec3::new(solved[0][i], solved[1][i], solved[2][i]);
+        }
+
+        Ok(())
+    }
+}
+
+// Jacobi-preconditioned conjugate gradient; `a` must be symmetric
+// positive definite
+fn conjugate_gradient(
+    a: &CsMat<f32>,
+    b: &Array1<f32>,
+    mut x: Array1<f32>,
+) -> Array1<f32> {
+    let inv_diag = a.diag().to_dense().mapv(|d| {
+        if d.abs() > f32::EPSILON {
+            1.0 / d
+        } else {
+            1.0
+        }
+    });
+
+    let tolerance = CG_TOLERANCE * b.dot(b).sqrt().max(f32::EPSILON);
+
+    let mut r = b - &(a * &x);
+    let mut z = &r * &inv_diag;
+    let mut p = z.clone();
+    let mut rz = r.dot(&z);
+
+    for _ in 0..2 * b.len() {
+        if r.dot(&r).sqrt() <= tolerance {
+            break;
+        }
+
+        let ap = a * &p;
+        let pap = p.dot(&ap);
+        if pap <= 0.0 {
+            break;
+        }
+
+        let alpha = rz / pap;
+        x.scaled_add(alpha, &p);
+        r.scaled_add(-alpha, &ap);
+
+        z = &r * &inv_diag;
+        let rz_next = r.dot(&z);
+        p = &z + &(p * (rz_next / rz));
+        rz = rz_next;
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::test_meshes::{noisy_grid, noisy_sphere};
+    use nalgebra_glm::vec3;
+
+    fn roughness(mesh: &HalfedgeMesh) -> f32 {
+        mesh.vertices().map(|v| v.center().y.abs()).sum()
+    }
+
+    fn mean_radius(mesh: &HalfedgeMesh) -> f32 {
+        let sum: f32 = mesh.vertices().map(|v| v.center().norm()).sum();
+        sum / mesh.vertex_count() as f32
+    }
+
+    // the largest deviation from the mean radius
+    fn spread(mesh: &HalfedgeMesh) -> f32 {
+        let r = mean_radius(mesh);
+        mesh.vertices()
+            .map(|v| (v.center().norm() - r).abs())
+            .fold(0.0f32, f32::max)
+    }
+
+    #[test]
+    fn test_laplacian_smooth() {
+        let grid = noisy_grid(8, 1);
+
+        let mut smooth = grid.clone();
+        smooth.laplacian_smooth(10, 0.5, true);
+        assert!(roughness(&smooth) < 0.2 * roughness(&grid));
+
+        // the boundary stays in place
+        for (a, b) in grid.vertices().zip(smooth.vertices()) {
+            if a.on_boundary() {
+                assert_eq!(a.center(), b.center());
+            }
+        }
+
+        // without fixing the boundary, the grid shrinks
+        let mut free = grid.clone();
+        free.laplacian_smooth(10, 0.5, false);
+        assert!(free.vertex(VertexId(0)).center().x > 0.5);
+    }
+
+    #[test]
+    fn test_taubin_smooth() {
+        let sphere = noisy_sphere(2);
+
+        let mut laplacian = sphere.clone();
+        laplacian.laplacian_smooth(10, 0.5, false);
+
+        let mut taubin = sphere.clone();
+        taubin.taubin_smooth(10, 0.5, -0.53, false);
+
+        // both remove the noise, but Taubin smoothing barely shrinks
+        assert!(spread(&taubin) < 0.5 * spread(&sphere));
+        assert!((mean_radius(&taubin) - 1.0).abs() < 0.05);
+        assert!(mean_radius(&laplacian) < mean_radius(&taubin) - 0.1);
+
+        let mut smoothed = sphere.clone();
+        smoothed
+            .smooth(Smoothing::Taubin {
+                iterations: 10,
+                lambda: 0.5,
+                mu: -0.53,
+                fix_boundary: false,
+            })
+            .unwrap();
+        assert!(smoothed
+            .vertices()
+            .zip(taubin.vertices())
+            .all(|(a, b)| a.center() == b.center()));
+    }
+
+    #[test]
+    fn test_implicit_mcf() {
+        let grid = noisy_grid(8, 3);
+
+        let mut smooth = grid.clone();
+        smooth.implicit_mcf(1, 10.0, true).unwrap();
+
+        // a large time step flattens the interior in a single step
+        assert!(roughness(&smooth) < 0.1 * roughness(&grid));
+        for (a, b) in grid.vertices().zip(smooth.vertices()) {
+            if a.on_boundary() {
+                assert_eq!(a.center(), b.center());
+            }
+            // the flow moves vertices along the normal
+            assert!((a.center().xz() - b.center().xz()).norm() < 0.1);
+        }
+
+        // a closed surface gets rounder as it shrinks
+        let noisy = noisy_sphere(4);
+        let mut sphere = noisy.clone();
+        sphere.implicit_mcf(3, 0.02, false).unwrap();
+        assert!(mean_radius(&sphere) < mean_radius(&noisy));
+        assert!(spread(&sphere) < 0.5 * spread(&noisy));
+
+        // only triangle meshes
+        let points = (0..4).map(|i| vec3((i % 2) as f32, 0.0, (i / 2) as f32));
+        let mut quad =
+            HalfedgeMesh::from_polygons(points, [[0, 2, 3, 1]]).unwrap();
+        assert!(quad.implicit_mcf(1, 0.1, false).is_err());
+    }
+}
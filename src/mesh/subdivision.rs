@@ -0,0 +1,355 @@
+use nalgebra_glm::Vec3;
+
+use super::{FaceId, HalfedgeMesh, VertexId};
+
+// Both schemes treat boundary edges as creases: boundary vertices
+// and edge points only depend on their neighbors along the boundary,
+// and boundary vertices with a single adjacent face are kept as
+// corners.
+
+impl HalfedgeMesh {
+    /// Applies `levels` iterations of Loop subdivision. Every
+    /// (non-boundary) face must be a triangle.
+    pub fn loop_subdivide(&self, levels: usize) -> anyhow::Result<Self> {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = mesh.loop_subdivide_once()?;
+        }
+        Ok(mesh)
+    }
+
+    /// Applies `levels` iterations of Catmull-Clark subdivision.
+    /// Faces can have any degree; after the first level, every face
+    /// is a quad.
+    pub fn catmull_clark(&self, levels: usize) -> anyhow::Result<Self> {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = mesh.catmull_clark_once()?;
+        }
+        Ok(mesh)
+    }
+
+    fn loop_subdivide_once(&self) -> anyhow::Result<Self> {
+        self.check_triangles()?;
+
+        let vertex_count = self.vertices.len();
+
+        // old vertices keep their indices, edge points come after
+        let mut points = Vec::with_capacity(vertex_count + self.edges.len());
+
+        for vertex in self.vertices() {
+            let pos = vertex.center();
+
+            if let Some([b0, b1]) = self.boundary_neighbors(vertex.id()) {
+                let pos = if self.is_corner(vertex.id()) {
+                    pos
+                } else {
+                    pos * 0.75 + (self.pos(b0) + self.pos(b1)) * 0.125
+                };
+                points.push(pos);
+                continue;
+            }
+
+            let n = vertex.degree();
+            if n == 0 {
+                points.push(pos);
+                continue;
+            }
+
+            let beta = loop_beta(n);
+            let sum = vertex
+                .neighbors()
+                .fold(Vec3::zeros(), |acc, v| acc + self.pos(v));
+
+            points.push(pos * (1.0 - n as f32 * beta) + sum * beta);
+        }
+
+        for edge in self.edge_ids() {
+            let [a, b] = self.edge_vertices(edge);
+            let mid = self.pos(a) + self.pos(b);
+
+            if self.edge_on_boundary(edge) {
+                points.push(mid * 0.5);
+            } else {
+                let he = self.edges[edge.0].halfedge;
+                let twin = self.halfedges[he.0].twin;
+                let c = self.opposite_vertex(he);
+                let d = self.opposite_vertex(twin);
+                let far = self.pos(c) + self.pos(d);
+                points.push(mid * 0.375 + far * 0.125);
+            }
+        }
+
+        let mut triangles = Vec::with_capacity(self.faces.len() * 4);
+
+        for face in self.face_ids() {
+            let mut corners = [0; 3];
+            let mut edge_points = [0; 3];
+
+            for (i, he) in self.face_halfedges(face).enumerate() {
+                corners[i] = self.halfedges[he.0].vertex.0;
+                edge_points[i] = vertex_count + self.halfedges[he.0].edge.0;
+            }
+
+            let [v0, v1, v2] = corners;
+            let [e01, e12, e20] = edge_points;
+
+            triangles.push([v0, e01, e20]);
+            triangles.push([v1, e12, e01]);
+            triangles.push([v2, e20, e12]);
+            triangles.push([e01, e12, e20]);
+        }
+
+        Self::from_triangles(points, triangles)
+    }
+
+    fn catmull_clark_once(&self) -> anyhow::Result<Self> {
+        let vertex_count = self.vertices.len();
+        let edge_count = self.edges.len();
+
+        let face_ids = self.face_ids().collect::<Vec<_>>();
+
+        // maps face IDs to the index of their face point
+        let mut face_point_ix = vec![usize::MAX; self.faces.len()];
+        for (i, face) in face_ids.iter().enumerate() {
+            face_point_ix[face.0] = vertex_count + edge_count + i;
+        }
+
+        let face_points = face_ids
+            .iter()
+            .map(|&f| self.face_center(f))
+            .collect::<Vec<_>>();
+
+        let face_point = |face: FaceId| {
+            face_points[face_point_ix[face.0] - vertex_count - edge_count]
+        };
+
+        let mut points =
+            Vec::with_capacity(vertex_count + edge_count + face_ids.len());
+
+        for vertex in self.vertices() {
+            let pos = vertex.center();
+
+            if let Some([b0, b1]) = self.boundary_neighbors(vertex.id()) {
+                let pos = if self.is_corner(vertex.id()) {
+                    pos
+                } else {
+                    pos * 0.75 + (self.pos(b0) + self.pos(b1)) * 0.125
+                };
+                points.push(pos);
+                continue;
+            }
+
+            let n = vertex.degree();
+            if n == 0 {
+                points.push(pos);
+                continue;
+            }
+
+            let n_f = n as f32;
+
+            let q = vertex
+                .faces()
+                .fold(Vec3::zeros(), |acc, f| acc + face_point(f))
+                / n_f;
+
+            let r = vertex
+                .neighbors()
+                .fold(Vec3::zeros(), |acc, v| acc + (pos + self.pos(v)) * 0.5)
+                / n_f;
+
+            points.push((q + r * 2.0 + pos * (n_f - 3.0)) / n_f);
+        }
+
+        for edge in self.edge_ids() {
+            let [a, b] = self.edge_vertices(edge);
+            let mid = (self.pos(a) + self.pos(b)) * 0.5;
+
+            if self.edge_on_boundary(edge) {
+                points.push(mid);
+            } else {
+                let he = self.edges[edge.0].halfedge;
+                let twin = self.halfedges[he.0].twin;
+                let f0 = face_point(self.halfedges[he.0].face);
+                let f1 = face_point(self.halfedges[twin.0].face);
+                points.push((mid + (f0 + f1) * 0.5) * 0.5);
+            }
+        }
+
+        points.extend(face_points.iter().copied());
+
+        let mut quads = Vec::new();
+
+        for &face in face_ids.iter() {
+            let f = face_point_ix[face.0];
+
+            let hes = self.face_halfedges(face).collect::<Vec<_>>();
+            let degree = hes.len();
+
+            for (i, &he) in hes.iter().enumerate() {
+                let prev = hes[(i + degree - 1) % degree];
+
+                let v = self.halfedges[he.0].vertex.0;
+                let e_next = vertex_count + self.halfedges[he.0].edge.0;
+                let e_prev = vertex_count + self.halfedges[prev.0].edge.0;
+
+                quads.push([v, e_next, f, e_prev]);
+            }
+        }
+
+        Self::from_polygons(points, quads)
+    }
+
+    // a boundary vertex with a single adjacent face
+    fn is_corner(&self, vertex: VertexId) -> bool {
+        self.vertex(vertex).faces().count() == 1
+    }
+
+    /// Returns the vertices on either side of a boundary vertex,
+    /// along the boundary loop, or `None` if the vertex isn't on
+    /// the boundary
+    pub fn boundary_neighbors(
+        &self,
+        vertex: VertexId,
+    ) -> Option<[VertexId; 2]> {
+        let out = self.vertex(vertex).outgoing_halfedges().find(|he| {
+            let face = self.halfedges[he.0].face;
+            self.faces[face.0].is_boundary
+        })?;
+
+        let next = self.halfedge_target(out);
+        let prev = self.halfedges[self.halfedge_prev(out).0].vertex;
+
+        Some([prev, next])
+    }
+}
+
+// Loop's original vertex weight
+fn loop_beta(n: usize) -> f32 {
+    let n = n as f32;
+    let c = 0.375 + 0.25 * (2.0 * std::f32::consts::PI / n).cos();
+    (0.625 - c * c) / n
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::mesh::test_meshes::tetrahedron;
+    use nalgebra_glm::vec3;
+
+    fn cube() -> HalfedgeMesh {
+        let points = (0..8).map(|i| {
+            let bit = |b: usize| ((i >> b) & 1) as f32;
+            vec3(bit(0), bit(1), bit(2))
+        });
+
+        let quads = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+
+        HalfedgeMesh::from_polygons(points, quads).unwrap()
+    }
+
+    // a flat n x n quad grid in the XZ plane
+    fn quad_grid(n: usize) -> HalfedgeMesh {
+        let points =
+            (0..n * n).map(|i| vec3((i % n) as f32, 0.0, (i / n) as f32));
+
+        let mut quads = Vec::new();
+        for row in 0..n - 1 {
+            for col in 0..n - 1 {
+                let a = row * n + col;
+                quads.push([a, a + n, a + n + 1, a + 1]);
+            }
+        }
+
+        HalfedgeMesh::from_polygons(points, quads).unwrap()
+    }
+
+    #[test]
+    fn test_loop_subdivide() {
+        let tet = tetrahedron();
+
+        let sub = tet.loop_subdivide(1).unwrap();
+        assert_eq!(sub.vertex_count(), 10);
+        assert_eq!(sub.edge_count(), 24);
+        assert_eq!(sub.face_count(), 16);
+        assert_eq!(sub.boundary_count(), 0);
+
+        let sub = tet.loop_subdivide(3).unwrap();
+        assert_eq!(sub.face_count(), 4 * 64);
+
+        // the limit surface lies within the convex hull
+        for v in sub.vertices() {
+            let p = v.center();
+            assert!(p.min() >= 0.0 && p.sum() <= 1.0);
+        }
+
+        // quads have to be triangulated first
+        assert!(cube().loop_subdivide(1).is_err());
+    }
+
+    #[test]
+    fn test_loop_subdivide_boundary() {
+        let points = (0..9).map(|i| vec3((i % 3) as f32, 0.0, (i / 3) as f32));
+        let mut triangles = Vec::new();
+        for row in 0..2 {
+            for col in 0..2 {
+                let a = row * 3 + col;
+                triangles.push([a, a + 3, a + 1]);
+                triangles.push([a + 1, a + 3, a + 4]);
+            }
+        }
+        let grid = HalfedgeMesh::from_triangles(points, triangles).unwrap();
+
+        let sub = grid.loop_subdivide(2).unwrap();
+        assert_eq!(sub.face_count(), 8 * 16);
+        assert_eq!(sub.boundary_count(), 1);
+
+        // flat stays flat, and the corners and straight boundary
+        // edges are kept in place
+        for v in sub.vertices() {
+            let p = v.center();
+            assert!(p.y.abs() < 1e-6);
+            assert!(p.x >= -1e-6 && p.x <= 2.0 + 1e-6);
+            assert!(p.z >= -1e-6 && p.z <= 2.0 + 1e-6);
+        }
+        assert_eq!(sub.vertex(VertexId(0)).center(), vec3(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_catmull_clark() {
+        let sub = cube().catmull_clark(1).unwrap();
+        assert_eq!(sub.vertex_count(), 8 + 12 + 6);
+        assert_eq!(sub.face_count(), 24);
+        assert!(sub.face_ids().all(|f| sub.face_degree(f) == 4));
+
+        // cube corners move inwards along the diagonal
+        let corner = sub.vertex(VertexId(0)).center();
+        assert!((corner - vec3(2.0, 2.0, 2.0) / 9.0).norm() < 1e-6);
+
+        let sub = tetrahedron().catmull_clark(2).unwrap();
+        assert_eq!(sub.face_count(), 12 * 4);
+
+        let grid = quad_grid(3).catmull_clark(2).unwrap();
+        assert_eq!(grid.face_count(), 4 * 16);
+        assert_eq!(grid.boundary_count(), 1);
+        for v in grid.vertices() {
+            assert!(v.center().y.abs() < 1e-6);
+        }
+        assert_eq!(grid.vertex(VertexId(0)).center(), vec3(0.0, 0.0, 0.0));
+
+        // a regular interior vertex of a flat grid doesn't move
+        let grid = quad_grid(3).catmull_clark(1).unwrap();
+        assert!(
+            (grid.vertex(VertexId(4)).center() - vec3(1.0, 0.0, 1.0)).norm()
+                < 1e-6
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Meshes shared by the tests of the `HalfedgeMesh` operators
+
+use nalgebra_glm::vec3;
+use rand::prelude::*;
+
+use super::HalfedgeMesh;
+
+/// An n x n vertex grid in the XZ plane, split into triangles
+pub(crate) fn grid(n: usize) -> HalfedgeMesh {
+    let points = (0..n * n).map(|i| {
+        let x = (i % n) as f32;
+        let z = (i / n) as f32;
+        vec3(x, 0.0, z)
+    });
+
+    let mut triangles = Vec::new();
+    for row in 0..n - 1 {
+        for col in 0..n - 1 {
+            let a = row * n + col;
+            let b = a + 1;
+            let c = a + n;
+            let d = c + 1;
+            triangles.push([a, c, b]);
+            triangles.push([b, c, d]);
+        }
+    }
+
+    HalfedgeMesh::from_triangles(points, triangles).unwrap()
+}
+
+/// `grid(n)` with the interior vertices displaced randomly along Y
+pub(crate) fn noisy_grid(n: usize, seed: u64) -> HalfedgeMesh {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut mesh = grid(n);
+
+    for (i, vertex) in mesh.vertices.iter_mut().enumerate() {
+        let (x, z) = (i % n, i / n);
+        if x > 0 && z > 0 && x < n - 1 && z < n - 1 {
+            vertex.pos.y = rng.gen_range(-0.2..0.2);
+        }
+    }
+
+    mesh
+}
+
+pub(crate) fn tetrahedron() -> HalfedgeMesh {
+    let points = [
+        vec3(0.0, 0.0, 0.0),
+        vec3(1.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+    ];
+    let triangles = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+    HalfedgeMesh::from_triangles(points, triangles).unwrap()
+}
+
+fn octahedron() -> HalfedgeMesh {
+    let points = [
+        vec3(1.0, 0.0, 0.0),
+        vec3(-1.0, 0.0, 0.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(0.0, -1.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(0.0, 0.0, -1.0),
+    ];
+    let triangles = [
+        [0, 2, 4],
+        [2, 1, 4],
+        [1, 3, 4],
+        [3, 0, 4],
+        [2, 0, 5],
+        [1, 2, 5],
+        [3, 1, 5],
+        [0, 3, 5],
+    ];
+    HalfedgeMesh::from_triangles(points, triangles).unwrap()
+}
+
+/// A unit sphere approximation: an octahedron subdivided `levels`
+/// times, projected onto the sphere
+pub(crate) fn sphere(levels: usize) -> HalfedgeMesh {
+    let mut mesh = octahedron().loop_subdivide(levels).unwrap();
+
+    for vertex in mesh.vertices.iter_mut() {
+        vertex.pos = vertex.pos.normalize();
+    }
+
+    mesh
+}
+
+/// `sphere(3)` with each vertex at a random radius between 0.9 and
+/// 1.1
+pub(crate) fn noisy_sphere(seed: u64) -> HalfedgeMesh {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut mesh = sphere(3);
+
+    for vertex in mesh.vertices.iter_mut() {
+        vertex.pos *= rng.gen_range(0.9..1.1);
+    }
+
+    mesh
+}
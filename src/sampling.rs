@@ -0,0 +1,704 @@
+use nalgebra_glm::{vec2, vec3, Vec2, Vec3};
+use rand::prelude::*;
+use rand_distr::Normal;
+use rustc_hash::FxHashMap;
+
+use crate::mesh::{HalfedgeMesh, Smoothing};
+
+/// Rejection samplers give up after this many attempts per requested
+/// point, so that e.g. a Gaussian far outside its domain can't hang
+const MAX_ATTEMPTS_PER_POINT: usize = 1000;
+
+/// The most attempts a rejection sampler makes in total, however
+/// many points are requested
+const MAX_ATTEMPTS: usize = 1 << 24;
+
+/// The most points a sampler returns, so that a count of `usize::MAX`
+/// can be used to fill the domain
+pub const MAX_POINTS: usize = 1 << 20;
+
+/// Candidates tried around each active point in Bridson's algorithm
+const POISSON_CANDIDATES: usize = 30;
+
+/// A region of the plane to sample points in
+#[derive(Debug, Clone, PartialEq)]
+pub enum Domain {
+    Disc {
+        center: Vec2,
+        radius: f32,
+    },
+    Rect {
+        min: Vec2,
+        max: Vec2,
+    },
+    /// A simple polygon, in either winding order
+    Polygon(Vec<Vec2>),
+}
+
+impl Domain {
+    pub fn unit_disc() -> Self {
+        Self::Disc {
+            center: Vec2::zeros(),
+            radius: 1.0,
+        }
+    }
+
+    pub fn contains(&self, p: Vec2) -> bool {
+        match self {
+            Self::Disc { center, radius } => (p - center).norm() <= *radius,
+            Self::Rect { min, max } => {
+                p.x >= min.x && p.y >= min.y && p.x <= max.x && p.y <= max.y
+            }
+            Self::Polygon(points) => {
+                // even-odd rule
+                let mut inside = false;
+                let mut prev = match points.last() {
+                    Some(&p) => p,
+                    None => return false,
+                };
+
+                for &next in points.iter() {
+                    if (next.y > p.y) != (prev.y > p.y) {
+                        let t = (p.y - next.y) / (prev.y - next.y);
+                        if p.x < next.x + t * (prev.x - next.x) {
+                            inside = !inside;
+                        }
+                    }
+                    prev = next;
+                }
+
+                inside
+            }
+        }
+    }
+
+    /// The corners of the bounding box
+    pub fn bounds(&self) -> [Vec2; 2] {
+        match self {
+            Self::Disc { center, radius } => {
+                let r = vec2(*radius, *radius);
+                [center - r, center + r]
+            }
+            Self::Rect { min, max } => [*min, *max],
+            Self::Polygon(points) => {
+                let mut min = vec2(f32::MAX, f32::MAX);
+                let mut max = vec2(f32::MIN, f32::MIN);
+                for p in points {
+                    min = min.inf(p);
+                    max = max.sup(p);
+                }
+                [min, max]
+            }
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        match self {
+            Self::Disc { radius, .. } => std::f32::consts::PI * radius * radius,
+            Self::Rect { min, max } => (max.x - min.x) * (max.y - min.y),
+            Self::Polygon(_) => self.polygon_moments().0.abs(),
+        }
+    }
+
+    /// The disc center, rectangle center, or polygon centroid
+    pub fn center(&self) -> Vec2 {
+        match self {
+            Self::Disc { center, .. } => *center,
+            Self::Rect { min, max } => (min + max) * 0.5,
+            Self::Polygon(points) => {
+                let (area, centroid) = self.polygon_moments();
+                if area.abs() > f32::EPSILON {
+                    centroid
+                } else {
+                    let sum = points.iter().fold(Vec2::zeros(), |a, p| a + p);
+                    sum / points.len().max(1) as f32
+                }
+            }
+        }
+    }
+
+    // the signed area and centroid of a polygon
+    fn polygon_moments(&self) -> (f32, Vec2) {
+        let points = match self {
+            Self::Polygon(points) => points,
+            _ => return (0.0, Vec2::zeros()),
+        };
+
+        let mut area = 0.0;
+        let mut centroid = Vec2::zeros();
+
+        for (i, a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            let cross = a.x * b.y - b.x * a.y;
+            area += cross;
+            centroid += (a + b) * cross;
+        }
+
+        area *= 0.5;
+
+        if area.abs() > f32::EPSILON {
+            centroid /= 6.0 * area;
+        }
+
+        (area, centroid)
+    }
+
+    // domains that contain no points, or whose bounds can't be
+    // sampled: polygons with fewer than 3 points, inverted or
+    // non-finite bounds, e.g. a negative radius, and domains with
+    // next to no area compared to their bounds, e.g. a polygon with
+    // collinear points, where a grid of cells would be unbounded
+    fn is_degenerate(&self) -> bool {
+        if let Self::Polygon(points) = self {
+            if points.len() < 3 {
+                return true;
+            }
+        }
+
+        let [min, max] = self.bounds();
+        let finite = min.iter().chain(max.iter()).all(|v| v.is_finite());
+        if !finite || min.x > max.x || min.y > max.y {
+            return true;
+        }
+
+        let size = max - min;
+        let area = self.area();
+        area.is_nan() || area <= 1e-6 * size.x * size.y
+    }
+
+    fn uniform_point(&self, rng: &mut impl Rng) -> Option<Vec2> {
+        let [min, max] = self.bounds();
+
+        (0..MAX_ATTEMPTS_PER_POINT).find_map(|_| {
+            let p = vec2(
+                rng.gen_range(min.x..=max.x),
+                rng.gen_range(min.y..=max.y),
+            );
+            self.contains(p).then_some(p)
+        })
+    }
+}
+
+/// How points are distributed over a `Domain`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampler {
+    Uniform,
+    /// Normally distributed around the center of the domain, with
+    /// points outside the domain rejected
+    Gaussian {
+        std_dev: f32,
+    },
+    /// Bridson's Poisson-disc sampling, with no two points closer
+    /// than `min_distance`. Stops early once the domain is full.
+    PoissonDisc {
+        min_distance: f32,
+    },
+    /// One randomly placed point in each cell of a grid over the
+    /// domain, which gives approximately the requested count
+    Jittered,
+}
+
+impl Sampler {
+    /// Samples `count` points in `domain`, but no more than
+    /// `MAX_POINTS`, using an RNG seeded with `seed`; the same
+    /// arguments always give the same points
+    pub fn sample(
+        &self,
+        domain: &Domain,
+        count: usize,
+        seed: u64,
+    ) -> Vec<Vec2> {
+        if domain.is_degenerate() {
+            return Vec::new();
+        }
+
+        let count = count.min(MAX_POINTS);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        match *self {
+            Sampler::Uniform => uniform(domain, count, &mut rng),
+            Sampler::Gaussian { std_dev } => {
+                gaussian(domain, count, std_dev, &mut rng)
+            }
+            Sampler::PoissonDisc { min_distance } => {
+                poisson_disc(domain, count, min_distance, &mut rng)
+            }
+            Sampler::Jittered => jittered(domain, count, &mut rng),
+        }
+    }
+}
+
+// the attempts a rejection sampler gets for `count` points
+fn max_attempts(count: usize) -> usize {
+    count
+        .saturating_mul(MAX_ATTEMPTS_PER_POINT)
+        .min(MAX_ATTEMPTS)
+}
+
+fn uniform(domain: &Domain, count: usize, rng: &mut impl Rng) -> Vec<Vec2> {
+    let [min, max] = domain.bounds();
+    let mut points = Vec::with_capacity(count.min(1 << 16));
+
+    for _ in 0..max_attempts(count) {
+        if points.len() == count {
+            break;
+        }
+
+        let p =
+            vec2(rng.gen_range(min.x..=max.x), rng.gen_range(min.y..=max.y));
+        if domain.contains(p) {
+            points.push(p);
+        }
+    }
+
+    points
+}
+
+fn gaussian(
+    domain: &Domain,
+    count: usize,
+    std_dev: f32,
+    rng: &mut impl Rng,
+) -> Vec<Vec2> {
+    let center = domain.center();
+    let mut points = Vec::with_capacity(count.min(1 << 16));
+
+    let distr = match Normal::new(0.0, std_dev) {
+        Ok(distr) => distr,
+        Err(_) => return points,
+    };
+
+    for _ in 0..max_attempts(count) {
+        if points.len() == count {
+            break;
+        }
+
+        let p = center + vec2(distr.sample(rng), distr.sample(rng));
+        if domain.contains(p) {
+            points.push(p);
+        }
+    }
+
+    points
+}
+
+fn poisson_disc(
+    domain: &Domain,
+    count: usize,
+    min_distance: f32,
+    rng: &mut impl Rng,
+) -> Vec<Vec2> {
+    let mut points: Vec<Vec2> = Vec::new();
+
+    if count == 0 || min_distance <= 0.0 {
+        return points;
+    }
+
+    let [min, _] = domain.bounds();
+    let cell_size = min_distance / 2.0f32.sqrt();
+
+    // each cell holds at most one point; only the cells with points
+    // are stored, as a tiny `min_distance` gives more cells than fit
+    // in memory
+    let mut grid: FxHashMap<(u64, u64), usize> = FxHashMap::default();
+
+    // float to integer casts saturate, so far away cells can share a
+    // coordinate, but never a point closer than `min_distance`
+    let cell = |p: Vec2| {
+        let x = ((p.x - min.x) / cell_size) as u64;
+        let y = ((p.y - min.y) / cell_size) as u64;
+        (x, y)
+    };
+
+    let far_enough = |grid: &FxHashMap<_, usize>, points: &[Vec2], p: Vec2| {
+        let (cx, cy) = cell(p);
+        for y in cy.saturating_sub(2)..=cy.saturating_add(2) {
+            for x in cx.saturating_sub(2)..=cx.saturating_add(2) {
+                if let Some(&i) = grid.get(&(x, y)) {
+                    if (points[i] - p).norm() < min_distance {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    };
+
+    let first = match domain.uniform_point(rng) {
+        Some(p) => p,
+        None => return points,
+    };
+
+    grid.insert(cell(first), 0);
+    points.push(first);
+
+    let mut active = vec![0];
+
+    while !active.is_empty() && points.len() < count {
+        let active_ix = rng.gen_range(0..active.len());
+        let origin = points[active[active_ix]];
+
+        let candidate = (0..POISSON_CANDIDATES).find_map(|_| {
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let dist = rng.gen_range(min_distance..2.0 * min_distance);
+            let p = origin + vec2(angle.cos(), angle.sin()) * dist;
+
+            (domain.contains(p) && far_enough(&grid, &points, p)).then_some(p)
+        });
+
+        match candidate {
+            Some(p) => {
+                grid.insert(cell(p), points.len());
+                active.push(points.len());
+                points.push(p);
+            }
+            None => {
+                active.swap_remove(active_ix);
+            }
+        }
+    }
+
+    points
+}
+
+fn jittered(domain: &Domain, count: usize, rng: &mut impl Rng) -> Vec<Vec2> {
+    let [min, max] = domain.bounds();
+    let size = max - min;
+
+    let bounds_area = size.x * size.y;
+    if count == 0 || bounds_area <= 0.0 {
+        return Vec::new();
+    }
+
+    // enough cells over the bounding box that about `count` of them
+    // are inside the domain; each cell gives at most one point, so
+    // there are no more than `MAX_POINTS`
+    let cells = (count as f32 * bounds_area / domain.area())
+        .clamp(1.0, MAX_POINTS as f32);
+    let columns = (cells * size.x / size.y).sqrt().round().clamp(1.0, cells);
+    let columns = columns as usize;
+    let rows = (cells / columns as f32).floor().max(1.0) as usize;
+
+    let cell_size = vec2(size.x / columns as f32, size.y / rows as f32);
+
+    let mut points =
+        Vec::with_capacity(columns.saturating_mul(rows).min(1 << 16));
+
+    for y in 0..rows {
+        for x in 0..columns {
+            let offset = vec2(rng.gen::<f32>(), rng.gen::<f32>());
+            let p = min
+                + (vec2(x as f32, y as f32) + offset).component_mul(&cell_size);
+
+            if domain.contains(p) {
+                points.push(p);
+            }
+        }
+    }
+
+    points
+}
+
+/// A height field over sampled points, triangulated into a surface;
+/// used by `mesh::sampled_disc`
+pub struct SurfaceSampling {
+    pub domain: Domain,
+    pub sampler: Sampler,
+    pub count: usize,
+    pub seed: u64,
+    /// The height at each point of the domain
+    pub height: Box<dyn Fn(Vec2) -> f32>,
+    /// The color of each vertex, given its index and position
+    pub color: Box<dyn Fn(usize, Vec3) -> [f32; 4]>,
+    /// Applied to the triangulated surface, to even out the noise of
+    /// random samples
+    pub smoothing: Option<Smoothing>,
+}
+
+impl Default for SurfaceSampling {
+    /// 1000 points in the unit disc, normally distributed, on a
+    /// paraboloid, colored with a cycling categorical palette, and
+    /// not smoothed
+    fn default() -> Self {
+        Self {
+            domain: Domain::unit_disc(),
+            sampler: Sampler::Gaussian { std_dev: 0.5 },
+            count: 1000,
+            seed: 0,
+            height: Box::new(|p| p.norm_squared()),
+            color: Box::new(|i, _| category_color(i)),
+            smoothing: None,
+        }
+    }
+}
+
+impl SurfaceSampling {
+    /// The sampled points in the domain
+    pub fn points(&self) -> Vec<Vec2> {
+        self.sampler.sample(&self.domain, self.count, self.seed)
+    }
+
+    /// The 3D positions of the sampled points, with the domain in the
+    /// XZ plane and the height along Y
+    pub fn positions(&self) -> Vec<Vec3> {
+        self.points()
+            .into_iter()
+            .map(|p| vec3(p.x, (self.height)(p), p.y))
+            .collect()
+    }
+
+    /// Delaunay triangulates the sampled points into a surface, and
+    /// applies `smoothing`, if any
+    pub fn mesh(&self) -> anyhow::Result<HalfedgeMesh> {
+        let positions = self.positions();
+
+        let tri_points = positions
+            .iter()
+            .map(|p| delaunator::Point {
+                x: p.x as f64,
+                y: p.z as f64,
+            })
+            .collect::<Vec<_>>();
+
+        let result = delaunator::triangulate(&tri_points);
+
+        let mut mesh = HalfedgeMesh::from_triangles(
+            positions,
+            result.triangles.chunks_exact(3).map(|t| [t[0], t[1], t[2]]),
+        )?;
+
+        if let Some(smoothing) = self.smoothing {
+            mesh.smooth(smoothing)?;
+        }
+
+        Ok(mesh)
+    }
+}
+
+/// The `i`th color of the Tableau 10 palette, after white
+pub fn category_color(i: usize) -> [f32; 4] {
+    const COLORS: [[u8; 3]; 11] = [
+        [0xff, 0xff, 0xff],
+        [0x1f, 0x77, 0xb4],
+        [0xff, 0x7f, 0x0e],
+        [0x2c, 0xa0, 0x2c],
+        [0xd6, 0x27, 0x28],
+        [0x94, 0x67, 0xbd],
+        [0x8c, 0x56, 0x4b],
+        [0xe3, 0x77, 0xc2],
+        [0x7f, 0x7f, 0x7f],
+        [0xbc, 0xbd, 0x22],
+        [0x17, 0xbe, 0xcf],
+    ];
+
+    let [r, g, b] = COLORS[i % COLORS.len()];
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn domains() -> Vec<Domain> {
+        vec![
+            Domain::unit_disc(),
+            Domain::Rect {
+                min: vec2(-1.0, 0.0),
+                max: vec2(3.0, 1.0),
+            },
+            // an L shape
+            Domain::Polygon(vec![
+                vec2(0.0, 0.0),
+                vec2(2.0, 0.0),
+                vec2(2.0, 1.0),
+                vec2(1.0, 1.0),
+                vec2(1.0, 2.0),
+                vec2(0.0, 2.0),
+            ]),
+        ]
+    }
+
+    fn samplers() -> Vec<Sampler> {
+        vec![
+            Sampler::Uniform,
+            Sampler::Gaussian { std_dev: 0.5 },
+            Sampler::PoissonDisc { min_distance: 0.05 },
+            Sampler::Jittered,
+        ]
+    }
+
+    #[test]
+    fn test_domain() {
+        let l_shape = &domains()[2];
+        assert_eq!(l_shape.area(), 3.0);
+        assert!(l_shape.contains(vec2(0.5, 1.5)));
+        assert!(!l_shape.contains(vec2(1.5, 1.5)));
+        assert!((l_shape.center() - vec2(5.0 / 6.0, 5.0 / 6.0)).norm() < 1e-6);
+
+        // the winding order doesn't matter
+        if let Domain::Polygon(points) = l_shape {
+            let reversed =
+                Domain::Polygon(points.iter().rev().copied().collect());
+            assert_eq!(reversed.area(), 3.0);
+            assert_eq!(reversed.center(), l_shape.center());
+        }
+
+        let disc = &domains()[0];
+        assert!(disc.contains(vec2(0.6, 0.6)));
+        assert!(!disc.contains(vec2(0.8, 0.8)));
+        assert_eq!(disc.bounds(), [vec2(-1.0, -1.0), vec2(1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_samplers() {
+        for domain in domains() {
+            for sampler in samplers() {
+                let points = sampler.sample(&domain, 500, 7);
+
+                assert!(points.iter().all(|&p| domain.contains(p)));
+                assert_eq!(points, sampler.sample(&domain, 500, 7));
+                assert_ne!(points, sampler.sample(&domain, 500, 8));
+
+                match sampler {
+                    Sampler::Jittered => {
+                        let n = points.len() as f32;
+                        assert!(n > 400.0 && n < 600.0, "{} points", n);
+                    }
+                    _ => assert_eq!(points.len(), 500),
+                }
+            }
+        }
+
+        let degenerate = [
+            Domain::Polygon(vec![]),
+            Domain::Polygon(vec![vec2(0.0, 0.0), vec2(1.0, 1.0)]),
+            Domain::Rect {
+                min: vec2(1.0, 0.0),
+                max: vec2(0.0, 1.0),
+            },
+            Domain::Rect {
+                min: vec2(0.0, 0.0),
+                max: vec2(f32::INFINITY, 1.0),
+            },
+            Domain::Disc {
+                center: Vec2::zeros(),
+                radius: -1.0,
+            },
+            Domain::Disc {
+                center: Vec2::zeros(),
+                radius: f32::NAN,
+            },
+            // no area, but bounds that do have one
+            Domain::Polygon(vec![
+                vec2(0.0, 0.0),
+                vec2(1.0, 1.0),
+                vec2(2.0, 2.0),
+            ]),
+            Domain::Rect {
+                min: vec2(0.0, 0.0),
+                max: vec2(1.0, 0.0),
+            },
+            Domain::Disc {
+                center: Vec2::zeros(),
+                radius: 0.0,
+            },
+        ];
+
+        for domain in degenerate {
+            for sampler in samplers() {
+                assert!(sampler.sample(&domain, 500, 7).is_empty());
+                assert!(sampler.sample(&domain, usize::MAX, 7).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sample_limits() {
+        let disc = Domain::unit_disc();
+
+        // filling the domain stops at `MAX_POINTS`, rather than after
+        // `usize::MAX` attempts, or cells
+        for sampler in [Sampler::Uniform, Sampler::Jittered] {
+            let points = sampler.sample(&disc, usize::MAX, 1);
+            assert!(points.len() > MAX_POINTS / 2);
+            assert!(points.len() <= MAX_POINTS);
+        }
+        let gaussian = Sampler::Gaussian { std_dev: 0.5 };
+        assert_eq!(gaussian.sample(&disc, usize::MAX, 1).len(), MAX_POINTS);
+
+        // a sliver of a triangle would need a grid of about 10^8 cells
+        // to get 500 points in it
+        let sliver = Domain::Polygon(vec![
+            vec2(0.0, 0.0),
+            vec2(1.0, 1.0),
+            vec2(1.0, 1.00001),
+        ]);
+        assert!(sliver.area() > 1e-6);
+        let points = Sampler::Jittered.sample(&sliver, 500, 1);
+        assert!(points.len() < 500);
+        assert!(points.iter().all(|&p| sliver.contains(p)));
+
+        // a tiny distance doesn't need a grid over the whole domain,
+        // here about 10^13 cells
+        let min_distance = 1e-6;
+        let sampler = Sampler::PoissonDisc { min_distance };
+        let points = sampler.sample(&disc, 100, 1);
+        assert_eq!(points.len(), 100);
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                assert!((a - b).norm() >= min_distance);
+            }
+        }
+
+        // nor does one below the float precision, which used to
+        // overflow the cell count
+        let sampler = Sampler::PoissonDisc {
+            min_distance: 1e-20,
+        };
+        assert!(!sampler.sample(&disc, 100, 1).is_empty());
+    }
+
+    #[test]
+    fn test_poisson_disc() {
+        let domain = Domain::unit_disc();
+        let min_distance = 0.1;
+
+        let sampler = Sampler::PoissonDisc { min_distance };
+        let points = sampler.sample(&domain, usize::MAX, 1);
+
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                assert!((a - b).norm() >= min_distance);
+            }
+        }
+
+        // the disc is filled up; each point covers at most a disc of
+        // radius `min_distance`, and at least half of one
+        let per_point = std::f32::consts::PI * min_distance * min_distance;
+        let n = points.len() as f32;
+        assert!(n > domain.area() / per_point);
+        assert!(n < 4.0 * 1.44 * domain.area() / per_point);
+    }
+
+    #[test]
+    fn test_surface_sampling() {
+        let sampling = SurfaceSampling {
+            count: 100,
+            seed: 3,
+            height: Box::new(|p| p.x),
+            ..SurfaceSampling::default()
+        };
+
+        let positions = sampling.positions();
+        assert_eq!(positions.len(), 100);
+        assert!(positions.iter().all(|p| p.y == p.x));
+        assert_eq!(positions, sampling.positions());
+
+        // the sampled surface is left as is unless asked otherwise
+        assert_eq!(sampling.smoothing, None);
+
+        assert_eq!(category_color(0), [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(category_color(11), category_color(0));
+    }
+}
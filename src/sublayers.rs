@@ -20,6 +20,8 @@ use std::collections::{BTreeMap, HashMap};
 
 use std::sync::Arc;
 
+use crate::vertex::{LineVertex, MeshVertex, RectInstance, VertexFormat};
+
 use anyhow::{anyhow, bail, Result};
 
 // use zerocopy::{AsBytes, FromBytes};
@@ -59,44 +61,15 @@ pub fn tri_3d_sublayer(
     let vert = res.insert_shader(vert);
     let frag = res.insert_shader(frag);
 
-    let vertex_stride = std::mem::size_of::<[f32; 10]>();
-
-    let vert_binding_desc = vk::VertexInputBindingDescription::builder()
-        .binding(0)
-        .stride(vertex_stride as u32)
-        .input_rate(vk::VertexInputRate::VERTEX)
-        .build();
-
-    let pos_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(0)
-        .format(vk::Format::R32G32B32_SFLOAT)
-        .offset(0)
-        .build();
-
-    let norm_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(1)
-        .format(vk::Format::R32G32B32_SFLOAT)
-        .offset(12)
-        .build();
-
-    let color_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(2)
-        .format(vk::Format::R32G32B32A32_SFLOAT)
-        .offset(24)
-        .build();
-
-    let vert_binding_descs = [vert_binding_desc];
-    let vert_attr_descs = [pos_desc, norm_desc, color_desc];
+    let vert_binding_descs = [MeshVertex::binding_description(0)];
+    let vert_attr_descs = MeshVertex::attribute_descriptions(0);
 
     let vert_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
         .vertex_binding_descriptions(&vert_binding_descs)
         .vertex_attribute_descriptions(&vert_attr_descs);
 
     let vertex_offset = 0;
-    let vertex_stride = vertex_stride as usize;
+    let vertex_stride = std::mem::size_of::<MeshVertex>();
 
     let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
         .depth_clamp_enable(false)
@@ -104,15 +77,14 @@ pub fn tri_3d_sublayer(
         .polygon_mode(vk::PolygonMode::FILL)
         .line_width(1.0)
         .cull_mode(vk::CullModeFlags::BACK)
-        .front_face(vk::FrontFace::CLOCKWISE)
-        // .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
         .depth_bias_enable(false)
         .depth_bias_constant_factor(0.0)
         .depth_bias_clamp(0.0)
         .depth_bias_slope_factor(0.0)
         .build();
 
-    SublayerDef::new::<([f32; 3], [f32; 3], [f32; 4]), _>(
+    SublayerDef::new::<MeshVertex, _>(
         ctx,
         res,
         "tri-3d",
@@ -149,44 +121,17 @@ pub fn rect_rgb_sublayer(
     let vert = res.insert_shader(vert);
     let frag = res.insert_shader(frag);
 
-    let vert_binding_desc = vk::VertexInputBindingDescription::builder()
-        .binding(0)
-        .stride(std::mem::size_of::<[f32; 8]>() as u32)
-        .input_rate(vk::VertexInputRate::INSTANCE)
-        .build();
-
-    let pos_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(0)
-        .format(vk::Format::R32G32_SFLOAT)
-        .offset(0)
-        .build();
-
-    let size_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(1)
-        .format(vk::Format::R32G32_SFLOAT)
-        .offset(8)
-        .build();
-
-    let color_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(2)
-        .format(vk::Format::R32G32B32A32_SFLOAT)
-        .offset(16)
-        .build();
-
-    let vert_binding_descs = [vert_binding_desc];
-    let vert_attr_descs = [pos_desc, size_desc, color_desc];
+    let vert_binding_descs = [RectInstance::binding_description(0)];
+    let vert_attr_descs = RectInstance::attribute_descriptions(0);
 
     let vert_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
         .vertex_binding_descriptions(&vert_binding_descs)
         .vertex_attribute_descriptions(&vert_attr_descs);
 
     let vertex_offset = 0;
-    let vertex_stride = 32;
+    let vertex_stride = std::mem::size_of::<RectInstance>();
 
-    SublayerDef::new::<([f32; 2], [f32; 2], [f32; 4]), _>(
+    SublayerDef::new::<RectInstance, _>(
         ctx,
         res,
         "rect-rgb",
@@ -221,46 +166,17 @@ pub fn line_rgb_sublayer(
     let vert = res.insert_shader(vert);
     let frag = res.insert_shader(frag);
 
-    let vertex_size = std::mem::size_of::<[f32; 10]>() as u32;
-
-    let vert_binding_desc = vk::VertexInputBindingDescription::builder()
-        .binding(0)
-        .stride(vertex_size)
-        .input_rate(vk::VertexInputRate::INSTANCE)
-        .build();
-
-    let p0_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(0)
-        .format(vk::Format::R32G32B32_SFLOAT)
-        .offset(0)
-        .build();
-
-    let p1_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(1)
-        .format(vk::Format::R32G32B32_SFLOAT)
-        .offset(12)
-        .build();
-
-    let color_desc = vk::VertexInputAttributeDescription::builder()
-        .binding(0)
-        .location(2)
-        .format(vk::Format::R32G32B32A32_SFLOAT)
-        .offset(24)
-        .build();
-
-    let vert_binding_descs = [vert_binding_desc];
-    let vert_attr_descs = [p0_desc, p1_desc, color_desc];
+    let vert_binding_descs = [LineVertex::binding_description(0)];
+    let vert_attr_descs = LineVertex::attribute_descriptions(0);
 
     let vert_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
         .vertex_binding_descriptions(&vert_binding_descs)
         .vertex_attribute_descriptions(&vert_attr_descs);
 
     let vertex_offset = 0;
-    let vertex_stride = vertex_size as usize;
+    let vertex_stride = std::mem::size_of::<LineVertex>();
 
-    SublayerDef::new::<([f32; 3], [f32; 3], [f32; 4]), _>(
+    SublayerDef::new::<LineVertex, _>(
         ctx,
         res,
         "line-rgb",
@@ -6,19 +6,19 @@ use nalgebra_glm::Vec2;
 
 use palette::{FromColor, Hsl, IntoColor, Srgb};
 
+use crate::vertex::LineVertex;
+
 // points should be in the unit square
 pub fn dot_plot(
     width: f32,
     height: f32,
     color: [f32; 4],
-    buf: &mut Vec<[u8; 40]>,
+    buf: &mut Vec<LineVertex>,
     points: impl IntoIterator<Item = Vec2>,
 ) {
     buf.clear();
 
     for p in points {
-        let mut vertex = [0u8; 40];
-
         // let s0 = p * Vec2::new(1.0 / width, 1.0 / height);
         // let dim = Vec2::new(width, height);
         // let s0 = p * dim;
@@ -28,12 +28,7 @@ pub fn dot_plot(
         let w0 = 7.0;
         let w1 = 0.5;
 
-        vertex[0..12].clone_from_slice(bytemuck::cast_slice(&[s0.x, s0.y, w0]));
-        vertex[12..24]
-            .clone_from_slice(bytemuck::cast_slice(&[s1.x, s1.y, w1]));
-        vertex[24..40].clone_from_slice(bytemuck::cast_slice(&color));
-
-        buf.push(vertex);
+        buf.push(LineVertex::new(s0, w0, s1, w1, color));
     }
 }
 
@@ -44,7 +39,7 @@ pub fn vector_field_vertices<F>(
     cols: usize,
     time: f32,
     color: [f32; 4],
-    buf: &mut Vec<[u8; 40]>,
+    buf: &mut Vec<LineVertex>,
     f: F,
 ) where
     F: Fn(Vec2) -> Vec2,
@@ -67,8 +62,6 @@ pub fn vector_field_vertices<F>(
 
             let s1 = s0 + out * 50.0;
 
-            let mut vertex = [0u8; 40];
-
             let w0 = 4.0;
             let w1 = 0.5;
 
@@ -77,13 +70,7 @@ pub fn vector_field_vertices<F>(
 
             let color = [rgb.red, rgb.green, rgb.blue, 1.0];
 
-            vertex[0..12]
-                .clone_from_slice(bytemuck::cast_slice(&[s0.x, s0.y, w0]));
-            vertex[12..24]
-                .clone_from_slice(bytemuck::cast_slice(&[s1.x, s1.y, w1]));
-            vertex[24..40].clone_from_slice(bytemuck::cast_slice(&color));
-
-            buf.push(vertex);
+            buf.push(LineVertex::new(s0, w0, s1, w1, color));
         }
     }
 }
@@ -0,0 +1,262 @@
+//! Vertex and instance layouts shared by the generators and the
+//! sublayer pipelines, so that the two can't drift apart.
+
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use nalgebra_glm::{Vec2, Vec3};
+
+/// A `#[repr(C)]` vertex type whose fields are the attributes of a
+/// vertex shader, in order of location.
+pub trait VertexFormat: Pod {
+    const INPUT_RATE: vk::VertexInputRate;
+
+    /// The format of each field; locations count up from zero, and
+    /// the offsets follow from the formats' sizes
+    const ATTRIBUTES: &'static [vk::Format];
+
+    /// Fails to compile, for each type whose descriptions are used,
+    /// if the formats don't add up to the size of the type, including
+    /// when a format isn't supported by `format_size`
+    const LAYOUT_CHECK: () = assert!(
+        attributes_size(Self::ATTRIBUTES) as usize
+            == std::mem::size_of::<Self>(),
+        "vertex attribute formats don't match the size of the vertex type"
+    );
+
+    fn binding_description(binding: u32) -> vk::VertexInputBindingDescription {
+        let () = Self::LAYOUT_CHECK;
+
+        vk::VertexInputBindingDescription::builder()
+            .binding(binding)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(Self::INPUT_RATE)
+            .build()
+    }
+
+    fn attribute_descriptions(
+        binding: u32,
+    ) -> Vec<vk::VertexInputAttributeDescription> {
+        let () = Self::LAYOUT_CHECK;
+
+        let mut offset = 0;
+
+        Self::ATTRIBUTES
+            .iter()
+            .enumerate()
+            .map(|(location, &format)| {
+                let desc = vk::VertexInputAttributeDescription::builder()
+                    .binding(binding)
+                    .location(location as u32)
+                    .format(format)
+                    .offset(offset)
+                    .build();
+                offset += format_size(format);
+                desc
+            })
+            .collect()
+    }
+}
+
+/// The size in bytes of an attribute of `format`, or 0 for formats
+/// that aren't used for vertex attributes here
+pub const fn format_size(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R32_SFLOAT => 4,
+        vk::Format::R32G32_SFLOAT => 8,
+        vk::Format::R32G32B32_SFLOAT => 12,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => 0,
+    }
+}
+
+const fn attributes_size(formats: &[vk::Format]) -> u32 {
+    let mut size = 0;
+    let mut i = 0;
+    while i < formats.len() {
+        let format_size = format_size(formats[i]);
+        if format_size == 0 {
+            return 0;
+        }
+        size += format_size;
+        i += 1;
+    }
+    size
+}
+
+// the sublayers take vertex data as byte arrays
+macro_rules! impl_to_bytes {
+    ($name:ident, $size:literal) => {
+        const _: () = assert!(std::mem::size_of::<$name>() == $size);
+        const _: () = <$name as VertexFormat>::LAYOUT_CHECK;
+
+        impl $name {
+            pub fn to_bytes(&self) -> [u8; $size] {
+                let mut bytes = [0u8; $size];
+                bytes.copy_from_slice(bytemuck::bytes_of(self));
+                bytes
+            }
+        }
+    };
+}
+
+/// Vertex of the `tri-3d` sublayer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct MeshVertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl MeshVertex {
+    pub fn new(pos: Vec3, normal: Vec3, color: [f32; 4]) -> Self {
+        Self {
+            pos: pos.into(),
+            normal: normal.into(),
+            color,
+        }
+    }
+}
+
+impl VertexFormat for MeshVertex {
+    const INPUT_RATE: vk::VertexInputRate = vk::VertexInputRate::VERTEX;
+    const ATTRIBUTES: &'static [vk::Format] = &[
+        vk::Format::R32G32B32_SFLOAT,
+        vk::Format::R32G32B32_SFLOAT,
+        vk::Format::R32G32B32A32_SFLOAT,
+    ];
+}
+
+impl_to_bytes!(MeshVertex, 40);
+
+/// Instance of the `line-rgb` sublayer: a line segment in window
+/// pixels, with the Z components holding the width at each end
+#[derive(Debug, Default, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct LineVertex {
+    pub p0: [f32; 3],
+    pub p1: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl LineVertex {
+    pub fn new(p0: Vec2, w0: f32, p1: Vec2, w1: f32, color: [f32; 4]) -> Self {
+        Self {
+            p0: [p0.x, p0.y, w0],
+            p1: [p1.x, p1.y, w1],
+            color,
+        }
+    }
+}
+
+impl VertexFormat for LineVertex {
+    const INPUT_RATE: vk::VertexInputRate = vk::VertexInputRate::INSTANCE;
+    const ATTRIBUTES: &'static [vk::Format] = &[
+        vk::Format::R32G32B32_SFLOAT,
+        vk::Format::R32G32B32_SFLOAT,
+        vk::Format::R32G32B32A32_SFLOAT,
+    ];
+}
+
+impl_to_bytes!(LineVertex, 40);
+
+/// Instance of the `rect-rgb` sublayer: a rectangle in window pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct RectInstance {
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl RectInstance {
+    pub fn new(pos: Vec2, size: Vec2, color: [f32; 4]) -> Self {
+        Self {
+            pos: pos.into(),
+            size: size.into(),
+            color,
+        }
+    }
+}
+
+impl VertexFormat for RectInstance {
+    const INPUT_RATE: vk::VertexInputRate = vk::VertexInputRate::INSTANCE;
+    const ATTRIBUTES: &'static [vk::Format] = &[
+        vk::Format::R32G32_SFLOAT,
+        vk::Format::R32G32_SFLOAT,
+        vk::Format::R32G32B32A32_SFLOAT,
+    ];
+}
+
+impl_to_bytes!(RectInstance, 32);
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use nalgebra_glm::{vec2, vec3};
+
+    // checks that each attribute description points at the field it
+    // describes, by reading back the bytes of a vertex whose fields
+    // are numbered
+    fn check_layout<V: VertexFormat>(vertex: V, fields: &[&[f32]]) {
+        let attrs = V::attribute_descriptions(1);
+        assert_eq!(attrs.len(), fields.len());
+
+        let binding = V::binding_description(1);
+        assert_eq!(binding.stride as usize, std::mem::size_of::<V>());
+
+        let last = attrs.last().unwrap();
+        let end = last.offset + format_size(last.format);
+        assert_eq!(end, binding.stride);
+
+        let bytes = bytemuck::bytes_of(&vertex);
+        for (attr, field) in attrs.iter().zip(fields) {
+            assert_eq!(attr.binding, 1);
+            assert_eq!(format_size(attr.format) as usize, field.len() * 4);
+
+            let start = attr.offset as usize;
+            let data = &bytes[start..start + field.len() * 4];
+            let floats = data
+                .chunks(4)
+                .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect::<Vec<_>>();
+            assert_eq!(&floats, field);
+        }
+    }
+
+    #[test]
+    fn test_layouts() {
+        let v = MeshVertex::new(
+            vec3(1.0, 2.0, 3.0),
+            vec3(4.0, 5.0, 6.0),
+            [7.0, 8.0, 9.0, 10.0],
+        );
+        check_layout(v, &[&v.pos, &v.normal, &v.color]);
+        assert_eq!(&v.to_bytes()[..], bytemuck::bytes_of(&v));
+
+        let l = LineVertex::new(
+            vec2(1.0, 2.0),
+            3.0,
+            vec2(4.0, 5.0),
+            6.0,
+            [7.0, 8.0, 9.0, 10.0],
+        );
+        assert_eq!(l.p1, [4.0, 5.0, 6.0]);
+        check_layout(l, &[&l.p0, &l.p1, &l.color]);
+
+        let r = RectInstance::new(
+            vec2(1.0, 2.0),
+            vec2(3.0, 4.0),
+            [5.0, 6.0, 7.0, 8.0],
+        );
+        check_layout(r, &[&r.pos, &r.size, &r.color]);
+        assert_eq!(r.to_bytes().len(), 32);
+
+        assert_eq!(format_size(vk::Format::R8G8B8A8_UNORM), 0);
+        assert_eq!(attributes_size(MeshVertex::ATTRIBUTES), 40);
+
+        assert_eq!(MeshVertex::INPUT_RATE, vk::VertexInputRate::VERTEX);
+        assert_eq!(RectInstance::INPUT_RATE, vk::VertexInputRate::INSTANCE);
+    }
+}